@@ -0,0 +1,213 @@
+use super::report::IterationReport;
+
+use log::{error, info, warn};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// how long a refresh request waits for the iteration to finish
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(600);
+
+// start the admin HTTP API on localhost:port in a background thread and return the bound port
+// (useful for port 0); POST /refresh with "Authorization: Bearer <token>" wakes up the main
+// loop via wakeup and answers with the JSON report of the iteration it triggered, POST /cleanup
+// sets cleanup so the main loop runs the midnight cleanup before its next iteration
+pub fn start_admin_server(
+    port: u16,
+    token: String,
+    wakeup: Sender<Sender<IterationReport>>,
+    cleanup: Arc<AtomicBool>,
+) -> Result<u16, String> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .map_err(|e| format!("Admin port {} cannot be opened: {}", port, e))?;
+    let port = listener
+        .local_addr()
+        .map_err(|e| format!("Admin port {} cannot be opened: {}", port, e))?
+        .port();
+    info!("Admin API listening on 127.0.0.1:{}", port);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = handle_request(stream, &token, &wakeup, &cleanup) {
+                        error!("Admin request failed: {}", e);
+                    }
+                }
+                Err(e) => error!("Admin connection failed: {}", e),
+            }
+        }
+    });
+    Ok(port)
+}
+
+fn handle_request(
+    stream: TcpStream,
+    token: &str,
+    wakeup: &Sender<Sender<IterationReport>>,
+    cleanup: &AtomicBool,
+) -> Result<(), String> {
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader
+        .read_line(&mut request_line)
+        .map_err(|e| e.to_string())?;
+    let mut authorized = false;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).map_err(|e| e.to_string())?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("authorization")
+                && value
+                    .trim()
+                    .strip_prefix("Bearer ")
+                    .is_some_and(|bearer| constant_time_eq(bearer, token))
+            {
+                authorized = true;
+            }
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("POST"), Some("/refresh")) if authorized => refresh(wakeup),
+        (Some("POST"), Some("/cleanup")) if authorized => {
+            info!("Cleanup requested via admin API");
+            cleanup.store(true, Ordering::SeqCst);
+            (
+                "202 Accepted",
+                "{\"status\":\"cleanup scheduled\"}".to_string(),
+            )
+        }
+        (Some("POST"), Some("/refresh" | "/cleanup")) => {
+            warn!("Unauthorized admin request");
+            (
+                "401 Unauthorized",
+                "{\"error\":\"unauthorized\"}".to_string(),
+            )
+        }
+        (_, Some("/refresh" | "/cleanup")) => (
+            "405 Method Not Allowed",
+            "{\"error\":\"method not allowed\"}".to_string(),
+        ),
+        _ => ("404 Not Found", "{\"error\":\"not found\"}".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    (&stream)
+        .write_all(response.as_bytes())
+        .map_err(|e| e.to_string())
+}
+
+// compare a and b in a time that depends only on their lengths, so the response time of a
+// request does not tell how much of the token it guessed
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+// trigger an iteration and wait for its report
+fn refresh(wakeup: &Sender<Sender<IterationReport>>) -> (&'static str, String) {
+    info!("Refresh requested via admin API");
+    let (reply, report) = mpsc::channel();
+    if wakeup.send(reply).is_err() {
+        return (
+            "503 Service Unavailable",
+            "{\"error\":\"not running\"}".to_string(),
+        );
+    }
+    match report.recv_timeout(REFRESH_TIMEOUT) {
+        Ok(report) => ("200 OK", serde_json::to_string(&report).unwrap()),
+        Err(_) => (
+            "504 Gateway Timeout",
+            "{\"error\":\"iteration did not finish\"}".to_string(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::report::{FeedReport, FeedStatus};
+    use super::super::timer::periodic_task;
+    use super::super::utilities::setup_test_logger;
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_refresh() {
+        setup_test_logger();
+        let (wakeup, wakeup_receiver) = mpsc::channel();
+        let cleanup = Arc::new(AtomicBool::new(false));
+        let port = start_admin_server(0, "secret".to_string(), wakeup, cleanup.clone()).unwrap();
+        thread::spawn(move || {
+            let mut iteration = 0;
+            periodic_task(
+                || {
+                    iteration += 1;
+                    IterationReport {
+                        started: "2023-11-15T07:11:10+00:00".to_string(),
+                        duration_ms: 5,
+                        feeds: vec![FeedReport {
+                            url: format!("https://www.faz.net/rss/{}", iteration),
+                            filename: "faz.rss".to_string(),
+                            status: FeedStatus::Updated,
+                            dedup: None,
+                            error: None,
+                        }],
+                        ..Default::default()
+                    }
+                },
+                || {},
+                3600,
+                0,
+                Some(&wakeup_receiver),
+                None,
+            );
+        });
+        let url = format!("http://127.0.0.1:{}/refresh", port);
+        let client = reqwest::blocking::Client::new();
+
+        let response = client.post(&url).bearer_auth("wrong").send().unwrap();
+        assert_eq!(response.status(), 401);
+        let response = client.get(&url).bearer_auth("secret").send().unwrap();
+        assert_eq!(response.status(), 405);
+
+        let start = Instant::now();
+        let response = client.post(&url).bearer_auth("secret").send().unwrap();
+        assert_eq!(response.status(), 200);
+        let report: IterationReport = serde_json::from_str(&response.text().unwrap()).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(60));
+        assert_eq!(report.feeds[0].url, "https://www.faz.net/rss/2");
+        assert_eq!(report.count(FeedStatus::Updated), 1);
+
+        let url = format!("http://127.0.0.1:{}/cleanup", port);
+        let response = client.post(&url).bearer_auth("wrong").send().unwrap();
+        assert_eq!(response.status(), 401);
+        assert!(!cleanup.load(Ordering::SeqCst));
+        let response = client.post(&url).bearer_auth("secret").send().unwrap();
+        assert_eq!(response.status(), 202);
+        assert!(cleanup.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq("secret", "secret"));
+        assert!(!constant_time_eq("secret", "secreT"));
+        assert!(!constant_time_eq("secret", "secret2"));
+        assert!(!constant_time_eq("", "secret"));
+    }
+}
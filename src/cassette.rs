@@ -0,0 +1,225 @@
+// record and replay of HTTP responses for tests: in record mode the responses of the fetcher are
+// saved to a cassette directory, in replay mode they are served from it without any network
+// access; responses are stored per feed url (before ${VAR} expansion, so secrets stay out of the
+// cassette) in the order they were received
+use reqwest::blocking::Response;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Clone, Debug)]
+pub enum CassetteMode {
+    /// fetch from the network and save the responses to the directory
+    Record(PathBuf),
+    /// serve the responses saved in the directory
+    Replay(PathBuf),
+}
+
+impl CassetteMode {
+    // parse record:DIR or replay:DIR
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.split_once(':') {
+            Some(("record", directory)) if !directory.is_empty() => {
+                Ok(Self::Record(PathBuf::from(directory)))
+            }
+            Some(("replay", directory)) if !directory.is_empty() => {
+                Ok(Self::Replay(PathBuf::from(directory)))
+            }
+            _ => Err(format!(
+                "expected record:DIR or replay:DIR but got {}",
+                value
+            )),
+        }
+    }
+
+    // the mode set with the RSSDEDUPER_CASSETTE environment variable, if any
+    pub fn from_env() -> Result<Option<Self>, String> {
+        std::env::var("RSSDEDUPER_CASSETTE")
+            .ok()
+            .map(|value| Self::parse(&value))
+            .transpose()
+    }
+}
+
+/// one recorded response
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Recording {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+pub struct Cassette {
+    mode: CassetteMode,
+    /// number of responses already recorded or replayed per url
+    positions: Mutex<HashMap<String, usize>>,
+}
+
+impl Cassette {
+    pub fn new(mode: CassetteMode) -> Self {
+        Self {
+            mode,
+            positions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // the next response for url, send performs the actual request in record mode
+    pub fn get<F>(&self, url: &str, send: F) -> Result<Response, String>
+    where
+        F: FnOnce() -> Result<Response, String>,
+    {
+        let position = {
+            let mut positions = self.positions.lock().unwrap();
+            let position = positions.entry(url.to_string()).or_default();
+            *position += 1;
+            *position - 1
+        };
+        match &self.mode {
+            CassetteMode::Replay(directory) => {
+                let filename = recording_filename(directory, url, position);
+                let content = fs::read_to_string(&filename)
+                    .map_err(|e| format!("No recorded response {}: {}", filename.display(), e))?;
+                let recording: Recording = serde_json::from_str(&content).map_err(|e| {
+                    format!(
+                        "Recorded response {} cannot be parsed: {}",
+                        filename.display(),
+                        e
+                    )
+                })?;
+                recording.to_response()
+            }
+            CassetteMode::Record(directory) => {
+                let response = send()?;
+                let status = response.status().as_u16();
+                let headers = response
+                    .headers()
+                    .iter()
+                    .filter_map(|(name, value)| {
+                        Some((name.to_string(), value.to_str().ok()?.to_string()))
+                    })
+                    .collect();
+                let body = response
+                    .text()
+                    .map_err(|e| format!("Feed {} cannot be read: {}", url, e))?;
+                let recording = Recording {
+                    status,
+                    headers,
+                    body,
+                };
+                let filename = recording_filename(directory, url, position);
+                fs::create_dir_all(directory)
+                    .and_then(|_| {
+                        fs::write(&filename, serde_json::to_string_pretty(&recording).unwrap())
+                    })
+                    .map_err(|e| {
+                        format!(
+                            "Response cannot be recorded to {}: {}",
+                            filename.display(),
+                            e
+                        )
+                    })?;
+                recording.to_response()
+            }
+        }
+    }
+}
+
+impl Recording {
+    fn to_response(&self) -> Result<Response, String> {
+        let mut builder = http::Response::builder().status(self.status);
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+        builder
+            .body(self.body.clone())
+            .map(Response::from)
+            .map_err(|e| format!("Recorded response cannot be replayed: {}", e))
+    }
+}
+
+// e.g. https_www_faz_net_rss_aktuell_.0.json for the first response of https://www.faz.net/rss/aktuell/
+fn recording_filename(directory: &Path, url: &str, position: usize) -> PathBuf {
+    let name: String = url
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    directory.join(format!("{}.{}.json", name, position))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fetch::{FetchOptions, Fetcher};
+    use super::super::rss::{ContentNormalization, Feed};
+    use super::super::testserver::{response, TestServer};
+    use super::super::utilities::setup_test_logger;
+    use super::*;
+
+    #[test]
+    fn test_parse_mode() {
+        assert!(matches!(
+            CassetteMode::parse("replay:testdata/cassettes"),
+            Ok(CassetteMode::Replay(directory)) if directory == Path::new("testdata/cassettes")
+        ));
+        assert!(matches!(
+            CassetteMode::parse("record:/tmp/cassettes"),
+            Ok(CassetteMode::Record(_))
+        ));
+        assert!(CassetteMode::parse("replay:").is_err());
+        assert!(CassetteMode::parse("testdata/cassettes").is_err());
+    }
+
+    #[test]
+    fn test_replay_not_modified() {
+        setup_test_logger();
+        let fetcher = Fetcher::new(&FetchOptions {
+            cassette: Some(CassetteMode::Replay(PathBuf::from(
+                "testdata/cassettes/not-modified",
+            ))),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut feed = Feed::new(
+            "https://www.stuttgarter-zeitung.de/news.rss",
+            "testdata/cassette_not_modified.rss",
+        );
+        let read = |feed: &mut Feed| feed.read(&fetcher, ContentNormalization::default());
+        assert_eq!(read(&mut feed), Ok(true));
+        assert_eq!(read(&mut feed), Ok(false));
+        assert_eq!(read(&mut feed), Ok(false));
+        assert!(read(&mut feed)
+            .unwrap_err()
+            .contains("No recorded response"));
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        const FEED1: &str = include_str!("../testdata/channel1.rss");
+        setup_test_logger();
+        let directory = std::env::temp_dir().join("rssdeduper-cassette-record");
+        let _ = fs::remove_dir_all(&directory);
+        let server = TestServer::start(vec![
+            response("200 OK", &[("ETag", "\"5f3c\"")], FEED1),
+            response("304 Not Modified", &[("ETag", "\"5f3c\"")], ""),
+        ]);
+        for mode in [
+            CassetteMode::Record(directory.clone()),
+            CassetteMode::Replay(directory.clone()),
+        ] {
+            let fetcher = Fetcher::new(&FetchOptions {
+                cassette: Some(mode),
+                ..Default::default()
+            })
+            .unwrap();
+            let mut feed = Feed::new(&server.url, "testdata/cassette_record.rss");
+            let read = |feed: &mut Feed| feed.read(&fetcher, ContentNormalization::default());
+            assert_eq!(read(&mut feed), Ok(true));
+            assert_eq!(feed.content(), FEED1);
+            assert_eq!(read(&mut feed), Ok(false));
+        }
+        // the replay did not reach the server
+        assert_eq!(server.requests().len(), 2);
+        let _ = fs::remove_dir_all(&directory);
+    }
+}
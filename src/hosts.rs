@@ -0,0 +1,87 @@
+use super::ids::normalize_host;
+
+use regex::Regex;
+use serde::{Serialize, Serializer};
+
+/// a host pattern of a host-scoped option: an exact host, a glob like `*.elpais.com` where `*`
+/// matches any characters, or a regular expression between slashes like `/^news[0-9]+\.faz\.net$/`;
+/// patterns are matched against the normalized host (lowercase, punycode)
+#[derive(Clone, Debug)]
+pub struct HostMatcher {
+    pattern: String,
+    regex: Regex,
+}
+
+impl HostMatcher {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let regex = match pattern
+            .strip_prefix('/')
+            .and_then(|pattern| pattern.strip_suffix('/'))
+        {
+            Some(regex) => regex.to_string(),
+            None => {
+                let parts: Vec<String> = pattern
+                    .split('*')
+                    .map(|part| {
+                        if part.is_empty() {
+                            String::new()
+                        } else {
+                            regex::escape(&normalize_host(part))
+                        }
+                    })
+                    .collect();
+                format!("^{}$", parts.join(".*"))
+            }
+        };
+        let regex =
+            Regex::new(&regex).map_err(|e| format!("invalid host pattern {}: {}", pattern, e))?;
+        Ok(Self {
+            pattern: pattern.to_string(),
+            regex,
+        })
+    }
+
+    pub fn matches(&self, host: &str) -> bool {
+        self.regex.is_match(&normalize_host(host))
+    }
+}
+
+impl std::fmt::Display for HostMatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+// serialized as the pattern given on the command line
+impl Serialize for HostMatcher {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob() {
+        let matcher = HostMatcher::new("*.elpais.com").unwrap();
+        assert!(matcher.matches("elviajero.elpais.com"));
+        assert!(matcher.matches("ELVIAJERO.elpais.com"));
+        assert!(!matcher.matches("elpais.com.evil.com"));
+        assert!(!matcher.matches("elpais.com"));
+        assert!(!matcher.matches("evilelpais.com"));
+    }
+
+    #[test]
+    fn test_exact_and_regex() {
+        let matcher = HostMatcher::new("www.Bücher.de").unwrap();
+        assert!(matcher.matches("www.xn--bcher-kva.de"));
+        assert!(!matcher.matches("www.xn--bcher-kva.de.evil.com"));
+        let matcher = HostMatcher::new(r"/^news[0-9]+\.faz\.net$/").unwrap();
+        assert!(matcher.matches("news12.faz.net"));
+        assert!(!matcher.matches("news.faz.net"));
+        assert!(HostMatcher::new("/news(/").is_err());
+        assert_eq!(matcher.to_string(), r"/^news[0-9]+\.faz\.net$/");
+    }
+}
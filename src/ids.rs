@@ -1,8 +1,10 @@
 extern crate test;
 
+use super::hosts::HostMatcher;
+
 use lazy_static::lazy_static;
 use regex::Regex;
-use url::Url;
+use url::{Host, Url};
 use uuid::Uuid;
 
 lazy_static! {
@@ -12,30 +14,235 @@ lazy_static! {
     ).unwrap();
     // number with at least 6 digits
     static ref NUMBER_REGEX: Regex = Regex::new(r"[0-9_]{6}[0-9_]*").unwrap();
+}
+
+/// host patterns with the index of the path segment that holds the item id, negative indices
+/// count from the last segment; the first matching pattern is used
+pub type IdPathSegments = Vec<(HostMatcher, i32)>;
+
+/// host patterns with the regular expression that finds the item id in the item title; the
+/// first matching pattern is used
+pub type IdTitlePatterns = Vec<(HostMatcher, Regex)>;
+
+/// how the id of an item is extracted from its url
+#[derive(Clone, Debug, Default)]
+pub struct IdOptions {
+    /// hosts whose item ids are taken from a path segment
+    pub path_segments: IdPathSegments,
+    /// hosts whose item ids are taken from the item title, for links without a stable id
+    pub title_patterns: IdTitlePatterns,
+    /// url components forming the id of urls without an id
+    pub fallback_key: FallbackKey,
+    /// path segments matching one of these are removed before the id is extracted, for session
+    /// or tracking tokens in the path
+    pub ignored_segments: Vec<Regex>,
+}
+
+/// which part of an item url is used as its id
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdRule {
+    /// a UUID in the path
+    Uuid,
+    /// a number with at least 6 digits in the path
+    Number,
+    /// the path segment at the configured index
+    PathSegment(i32),
+    /// the match of the title pattern configured for the host in the item title
+    Title,
+    /// the whole url
+    Url,
+}
+
+/// which components of an item url form its id if no id is found in the url
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FallbackKey {
+    /// the path, so urls differing only in query or fragment are duplicates
+    Path,
+    /// the path and the query, dropping the fragment
+    PathQuery,
+    /// the whole url
+    #[default]
+    Full,
+}
+
+impl std::fmt::Display for IdRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdRule::Uuid => write!(f, "uuid"),
+            IdRule::Number => write!(f, "number"),
+            IdRule::PathSegment(index) => write!(f, "path segment {}", index),
+            IdRule::Title => write!(f, "title"),
+            IdRule::Url => write!(f, "url"),
+        }
+    }
+}
+
+// canonical form of a host: lowercase and punycode for internationalized domains, so that hosts
+// match whether they are written in Unicode or punycode
+pub fn normalize_host(host: &str) -> String {
+    Host::parse(host)
+        .map(|host| host.to_string())
+        .unwrap_or_else(|_| host.to_lowercase())
+}
+
+// url with normalized host, as the url crate serializes it; urls that cannot be parsed are
+// returned unchanged
+pub fn normalize_url(url: &str) -> String {
+    Url::parse(url)
+        .map(|url| url.to_string())
+        .unwrap_or_else(|_| url.to_string())
+}
+
+// the non-empty path segment at index, negative indices count from the last segment
+fn path_segment(url: &Url, index: i32) -> Option<&str> {
+    let segments: Vec<&str> = url
+        .path_segments()?
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    let index = if index < 0 {
+        segments.len().checked_sub(index.unsigned_abs() as usize)?
+    } else {
+        index as usize
+    };
+    segments.get(index).copied()
+}
 
-    static ref SANITIZE_REGEX: Regex = Regex::new(r"[^a-zA-Z0-9]+").unwrap();
+// the fallback id of url made of the components selected by fallback, the host is part of the
+// key anyway; the full url is taken as written with only its host in lowercase like the host of
+// the key, so the keys of lowercase hosts stay the same, unless segments were removed from the
+// parsed url
+fn fallback_id(
+    url: &str,
+    parsed_url: &Url,
+    fallback: FallbackKey,
+    segments_removed: bool,
+) -> String {
+    match fallback {
+        FallbackKey::Full if !segments_removed => lowercase_host(url),
+        FallbackKey::Path => parsed_url.path().to_string(),
+        FallbackKey::PathQuery => match parsed_url.query() {
+            Some(query) => format!("{}?{}", parsed_url.path(), query),
+            None => parsed_url.path().to_string(),
+        },
+        FallbackKey::Full => parsed_url.to_string(),
+    }
+}
+
+// url with its scheme and authority in lowercase and the rest as it is
+fn lowercase_host(url: &str) -> String {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return url.to_string();
+    };
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    format!(
+        "{}://{}{}",
+        scheme.to_lowercase(),
+        rest[..end].to_lowercase(),
+        &rest[end..]
+    )
+}
+
+// remove the path segments of url that match one of patterns
+fn remove_ignored_segments(url: &mut Url, patterns: &[Regex]) {
+    if patterns.is_empty() {
+        return;
+    }
+    let Some(segments) = url.path_segments() else {
+        return;
+    };
+    let kept: Vec<String> = segments
+        .filter(|segment| !patterns.iter().any(|pattern| pattern.is_match(segment)))
+        .map(str::to_string)
+        .collect();
+    url.set_path(&kept.join("/"));
+}
+
+pub fn extract_unique_id_and_host_from_url_string(
+    url: &str,
+    options: &IdOptions,
+) -> Option<(String, String)> {
+    extract_unique_id_host_and_rule_from_url_string(url, options).map(|(id, host, _)| (id, host))
 }
 
-pub fn extract_unique_id_and_host_from_url_string(url: &str) -> Option<(String, String)> {
-    let parsed_url = Url::parse(url).ok()?;
-    let host = parsed_url.host().unwrap().to_string();
-    let mut id = url;
-    if let Some(cap) = UUID_REGEX.captures(parsed_url.path()) {
+// like extract_unique_id_and_host_from_url_string, but also returns the rule that produced the id;
+// a path segment configured for the host takes precedence over the uuid and number rules, urls
+// without that segment fall back to them; None for urls without a host like tag: or mailto:
+pub fn extract_unique_id_host_and_rule_from_url_string(
+    url: &str,
+    options: &IdOptions,
+) -> Option<(String, String, IdRule)> {
+    let mut parsed_url = Url::parse(url).ok()?;
+    let host = normalize_host(&parsed_url.host()?.to_string());
+    remove_ignored_segments(&mut parsed_url, &options.ignored_segments);
+    let fallback_id = fallback_id(
+        url,
+        &parsed_url,
+        options.fallback_key,
+        !options.ignored_segments.is_empty(),
+    );
+    let mut id = fallback_id.as_str();
+    let mut rule = IdRule::Url;
+    let configured_segment = options
+        .path_segments
+        .iter()
+        .find(|(matcher, _)| matcher.matches(&host))
+        .and_then(|(_, index)| path_segment(&parsed_url, *index).map(|segment| (segment, *index)));
+    if let Some((segment, index)) = configured_segment {
+        id = segment;
+        rule = IdRule::PathSegment(index);
+    } else if let Some(cap) = UUID_REGEX.captures(parsed_url.path()) {
         if let Some(uuid_str) = cap.get(0) {
             if Uuid::parse_str(uuid_str.as_str()).is_ok() {
                 id = uuid_str.as_str();
+                rule = IdRule::Uuid;
             }
         }
     } else if let Some(cap) = NUMBER_REGEX.captures(parsed_url.path()) {
         if let Some(id_str) = cap.get(0) {
             id = id_str.as_str();
+            rule = IdRule::Number;
         }
     }
-    Some((id.to_string(), host))
+    Some((id.to_string(), host, rule))
+}
+
+// the (id, host) key of an item with link url from its title if a title pattern is configured
+// for the host of url and matches the title: the first capture group of the pattern, or the
+// whole match if it has no group; None lets the id be extracted from url as usual
+pub fn extract_unique_id_and_host_from_title(
+    url: &str,
+    title: &str,
+    options: &IdOptions,
+) -> Option<(String, String)> {
+    if options.title_patterns.is_empty() {
+        return None;
+    }
+    let host = normalize_host(Url::parse(url).ok()?.host_str()?);
+    let (_, pattern) = options
+        .title_patterns
+        .iter()
+        .find(|(matcher, _)| matcher.matches(&host))?;
+    let captures = pattern.captures(title)?;
+    let id = captures.get(1).or_else(|| captures.get(0))?;
+    Some((id.as_str().to_string(), host))
 }
 
+// replace each run of characters other than ASCII letters and digits with _, like the regex
+// [^a-zA-Z0-9]+ but without its allocations, which matters for OPML files with many feeds
 fn convert_url_to_filename(url: &str) -> String {
-    SANITIZE_REGEX.replace_all(url, "_").to_string() + ".rss"
+    let mut filename = String::with_capacity(url.len() + 4);
+    let mut in_run = false;
+    for c in url.chars() {
+        if c.is_ascii_alphanumeric() {
+            filename.push(c);
+            in_run = false;
+        } else if !in_run {
+            filename.push('_');
+            in_run = true;
+        }
+    }
+    filename + ".rss"
 }
 
 pub fn generate_uuid() -> String {
@@ -47,6 +254,77 @@ pub fn convert_url_to_unique_filename(url: &str, uuid: &str) -> String {
     uuid.to_owned() + convert_url_to_filename(url).as_str()
 }
 
+// the placeholders of a feed name template
+const FEED_NAME_PLACEHOLDERS: [&str; 4] = ["{host}", "{title-slug}", "{url}", "{id}"];
+
+/// template of the filenames of new feeds instead of a uuid followed by the url, with the
+/// placeholders {host}, {title-slug} (the OPML title, or the host without one), {url} and {id}
+/// (8 random hex digits), e.g. {host}-{title-slug}.rss
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(transparent)]
+pub struct FeedNameTemplate(String);
+
+impl FeedNameTemplate {
+    // the template is rejected if it has unknown placeholders or no placeholder at all, or if it
+    // could name a file outside the target directories or a hidden one
+    pub fn new(template: &str) -> Result<Self, String> {
+        let mut rest = template.to_string();
+        for placeholder in FEED_NAME_PLACEHOLDERS {
+            rest = rest.replace(placeholder, "");
+        }
+        if rest.contains(['{', '}']) {
+            return Err(format!("unknown placeholder in {}", template));
+        }
+        if rest.len() == template.len() {
+            return Err(format!("no placeholder in {}", template));
+        }
+        if template.starts_with('.')
+            || rest.contains(['/', '\\'])
+            || rest.chars().any(char::is_control)
+        {
+            return Err(format!("{} is no safe filename", template));
+        }
+        Ok(Self(template.to_string()))
+    }
+
+    // the filename of the feed with url and OPML title; the placeholders are replaced by
+    // lowercase ASCII letters, digits and -, and a name for which taken is true gets a uuid
+    // before its extension, so it does not replace the file of another feed
+    pub fn filename(&self, url: &str, title: &str, taken: impl Fn(&str) -> bool) -> String {
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(normalize_host))
+            .unwrap_or_default();
+        let title = if slug(title).is_empty() { &host } else { title };
+        let filename = self
+            .0
+            .replace("{host}", &slug(&host))
+            .replace("{title-slug}", &slug(title))
+            .replace("{url}", &slug(url))
+            .replace("{id}", &generate_uuid()[..8]);
+        if !taken(&filename) {
+            return filename;
+        }
+        let uuid = generate_uuid();
+        match filename.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => {
+                format!("{}-{}.{}", stem, uuid, extension)
+            }
+            _ => format!("{}-{}", filename, uuid),
+        }
+    }
+}
+
+// text in lowercase with each run of characters other than ASCII letters and digits replaced
+// by a single -, without leading or trailing -
+fn slug(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -56,7 +334,7 @@ mod tests {
     fn extract_faz() {
         let url = "https://www.faz.net/aktuell/finanzen/zinssaetze-fuer-festgeld-warum-erste-banken-die-sparzinsen-wieder-senken-19313464.html";
         assert_eq!(
-            extract_unique_id_and_host_from_url_string(url).unwrap(),
+            extract_unique_id_and_host_from_url_string(url, &IdOptions::default()).unwrap(),
             (String::from("19313464"), String::from("www.faz.net"))
         );
     }
@@ -65,7 +343,7 @@ mod tests {
     fn extract_stz() {
         let url = "https://www.stuttgarter-zeitung.de/inhalt.gluehwein-djs-und-handgemachte-geschenke-kleine-und-alternative-weihnachtsmaerkte-in-stuttgart.f3d6053d-c298-4b83-8e70-d5d6e7e8ed78.html";
         assert_eq!(
-            extract_unique_id_and_host_from_url_string(url).unwrap(),
+            extract_unique_id_and_host_from_url_string(url, &IdOptions::default()).unwrap(),
             (
                 String::from("f3d6053d-c298-4b83-8e70-d5d6e7e8ed78"),
                 String::from("www.stuttgarter-zeitung.de")
@@ -78,7 +356,7 @@ mod tests {
         let url = "https://elviajero.elpais.com/elviajero/2022/07/26/actualidad/1658829008_842300.html#?ref=rss&format=simple&link=link
         ";
         assert_eq!(
-            extract_unique_id_and_host_from_url_string(url).unwrap(),
+            extract_unique_id_and_host_from_url_string(url, &IdOptions::default()).unwrap(),
             (
                 String::from("1658829008_842300"),
                 String::from("elviajero.elpais.com")
@@ -86,6 +364,270 @@ mod tests {
         );
     }
 
+    #[test]
+    fn extract_without_host() {
+        for url in [
+            "tag:blogger.com,1999:blog-6021296093126498395.post-4531235463154337925",
+            "mailto:redaktion@faz.net",
+            "//www.faz.net/aktuell/finanzen/festgeld-19313464.html",
+        ] {
+            assert_eq!(
+                extract_unique_id_and_host_from_url_string(url, &IdOptions::default()),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn extract_idn_host() {
+        let unicode = "https://www.bücher.de/artikel/neuerscheinungen-19313464.html";
+        let punycode = "https://www.xn--bcher-kva.de/artikel/neuerscheinungen-19313464.html";
+        let expected = (
+            String::from("19313464"),
+            String::from("www.xn--bcher-kva.de"),
+        );
+        assert_eq!(
+            extract_unique_id_and_host_from_url_string(unicode, &IdOptions::default()).unwrap(),
+            expected
+        );
+        assert_eq!(
+            extract_unique_id_and_host_from_url_string(punycode, &IdOptions::default()).unwrap(),
+            expected
+        );
+        assert_eq!(
+            extract_unique_id_and_host_from_url_string(
+                &punycode.to_uppercase(),
+                &IdOptions::default()
+            )
+            .unwrap(),
+            (
+                String::from("19313464"),
+                String::from("www.xn--bcher-kva.de")
+            )
+        );
+    }
+
+    #[test]
+    fn extract_path_segment() {
+        let options = IdOptions {
+            path_segments: vec![(HostMatcher::new("*.example.com").unwrap(), -2)],
+            ..Default::default()
+        };
+        let url = "https://www.example.com/2024/story/a81f3c/eine-hiobsbotschaft-19313464/";
+        assert_eq!(
+            extract_unique_id_host_and_rule_from_url_string(url, &options).unwrap(),
+            (
+                String::from("a81f3c"),
+                String::from("www.example.com"),
+                IdRule::PathSegment(-2)
+            )
+        );
+        // other hosts and urls without the segment use the default rules
+        let other = "https://www.faz.net/2024/story/a81f3c/eine-hiobsbotschaft-19313464/";
+        assert_eq!(
+            extract_unique_id_host_and_rule_from_url_string(other, &options)
+                .unwrap()
+                .2,
+            IdRule::Number
+        );
+        let short = "https://www.example.com/";
+        assert_eq!(
+            extract_unique_id_host_and_rule_from_url_string(short, &options)
+                .unwrap()
+                .2,
+            IdRule::Url
+        );
+        let options = IdOptions {
+            path_segments: vec![(HostMatcher::new("www.example.com").unwrap(), 2)],
+            ..Default::default()
+        };
+        assert_eq!(
+            extract_unique_id_and_host_from_url_string(url, &options)
+                .unwrap()
+                .0,
+            "a81f3c"
+        );
+    }
+
+    #[test]
+    fn extract_title() {
+        let options = IdOptions {
+            title_patterns: vec![(
+                HostMatcher::new("*.example.com").unwrap(),
+                Regex::new(r"\[#(\d+)\]").unwrap(),
+            )],
+            ..Default::default()
+        };
+        let url = "https://www.example.com/tickets/view?session=a81f3c";
+        assert_eq!(
+            extract_unique_id_and_host_from_title(url, "Login fails [#12345]", &options),
+            Some((String::from("12345"), String::from("www.example.com")))
+        );
+        // titles without an id and other hosts use the id of the url
+        assert_eq!(
+            extract_unique_id_and_host_from_title(url, "Login fails", &options),
+            None
+        );
+        let other = "https://www.faz.net/tickets/view?session=a81f3c";
+        assert_eq!(
+            extract_unique_id_and_host_from_title(other, "Login fails [#12345]", &options),
+            None
+        );
+        // without a capture group the whole match is the id
+        let options = IdOptions {
+            title_patterns: vec![(
+                HostMatcher::new("www.example.com").unwrap(),
+                Regex::new(r"S\d+E\d+").unwrap(),
+            )],
+            ..Default::default()
+        };
+        assert_eq!(
+            extract_unique_id_and_host_from_title(url, "Podcast S02E17: Rust", &options)
+                .unwrap()
+                .0,
+            "S02E17"
+        );
+    }
+
+    #[test]
+    fn extract_fallback_key() {
+        let urls = [
+            "https://www.example.com/news/story.html",
+            "https://www.example.com/news/story.html?page=2",
+            "https://www.example.com/news/story.html?page=2#comments",
+            "http://www.example.com/news/story.html#top",
+        ];
+        let keys = |fallback| -> Vec<String> {
+            urls.iter()
+                .map(|url| {
+                    let options = IdOptions {
+                        fallback_key: fallback,
+                        ..Default::default()
+                    };
+                    let (id, host, rule) =
+                        extract_unique_id_host_and_rule_from_url_string(url, &options).unwrap();
+                    assert_eq!(host, "www.example.com");
+                    assert_eq!(rule, IdRule::Url);
+                    id
+                })
+                .collect()
+        };
+        assert_eq!(keys(FallbackKey::Path), vec!["/news/story.html"; 4]);
+        assert_eq!(
+            keys(FallbackKey::PathQuery),
+            vec![
+                "/news/story.html",
+                "/news/story.html?page=2",
+                "/news/story.html?page=2",
+                "/news/story.html",
+            ]
+        );
+        assert_eq!(keys(FallbackKey::Full), urls);
+        // an id in the url is used whatever the fallback
+        let url = "https://www.faz.net/aktuell/finanzen/senken-19313464.html?ref=rss";
+        let path_options = IdOptions {
+            fallback_key: FallbackKey::Path,
+            ..Default::default()
+        };
+        assert_eq!(
+            extract_unique_id_and_host_from_url_string(url, &path_options)
+                .unwrap()
+                .0,
+            "19313464"
+        );
+    }
+
+    #[test]
+    fn extract_ignored_segments() {
+        let options = IdOptions {
+            ignored_segments: vec![
+                Regex::new("^s$").unwrap(),
+                Regex::new("^[0-9a-f]{12}$").unwrap(),
+            ],
+            ..Default::default()
+        };
+        let urls = [
+            "https://www.example.com/s/3f9a1c2b7d4e/article/4567",
+            "https://www.example.com/s/a81f3c200914/article/4567",
+            "https://www.example.com/article/4567",
+        ];
+        for fallback_key in [FallbackKey::Full, FallbackKey::Path] {
+            let options = IdOptions {
+                fallback_key,
+                ..options.clone()
+            };
+            let keys: Vec<_> = urls
+                .iter()
+                .map(|url| extract_unique_id_and_host_from_url_string(url, &options).unwrap())
+                .collect();
+            assert_eq!(keys[0], keys[2]);
+            assert_eq!(keys[1], keys[2]);
+        }
+        // the number in the session token no longer hides the id of the article
+        let url = "https://www.example.com/s/a81f3c200914/article/19313464.html";
+        assert_eq!(
+            extract_unique_id_host_and_rule_from_url_string(url, &options).unwrap(),
+            (
+                String::from("19313464"),
+                String::from("www.example.com"),
+                IdRule::Number
+            )
+        );
+        assert_eq!(
+            extract_unique_id_and_host_from_url_string(url, &IdOptions::default())
+                .unwrap()
+                .0,
+            "200914"
+        );
+    }
+
+    #[test]
+    fn extract_host_case() {
+        let key =
+            |url| extract_unique_id_and_host_from_url_string(url, &IdOptions::default()).unwrap();
+        let lower = key("https://www.example.com/News/Story.html");
+        assert_eq!(
+            lower,
+            (
+                String::from("https://www.example.com/News/Story.html"),
+                String::from("www.example.com")
+            )
+        );
+        assert_eq!(key("https://WWW.Example.COM/News/Story.html"), lower);
+        assert_ne!(key("https://www.example.com/news/story.html"), lower);
+        // the url is kept as written, so the stored keys of lowercase hosts do not change
+        assert_eq!(
+            key("https://www.example.com/News Story.html?Id=A").0,
+            "https://www.example.com/News Story.html?Id=A"
+        );
+        assert_eq!(
+            key("HTTPS://WWW.Example.COM/News Story.html?Id=A").0,
+            "https://www.example.com/News Story.html?Id=A"
+        );
+        // ids from the path keep their case
+        assert_eq!(
+            key("https://WWW.EXAMPLE.COM/a/F3D6053D-C298-4B83-8E70-D5D6E7E8ED78.html"),
+            (
+                String::from("F3D6053D-C298-4B83-8E70-D5D6E7E8ED78"),
+                String::from("www.example.com")
+            )
+        );
+    }
+
+    #[test]
+    fn test_normalize_host() {
+        assert_eq!(normalize_host("www.Bücher.de"), "www.xn--bcher-kva.de");
+        assert_eq!(
+            normalize_host("WWW.XN--BCHER-KVA.DE"),
+            "www.xn--bcher-kva.de"
+        );
+        assert_eq!(normalize_host("127.0.0.1"), "127.0.0.1");
+        assert_eq!(
+            normalize_url("https://www.bücher.de/rss"),
+            normalize_url("https://www.xn--bcher-kva.de/rss")
+        );
+    }
+
     #[test]
     fn test_convert_url_to_filename() {
         let url = "https://www.faz.net/aktuell/finanzen/";
@@ -93,6 +635,34 @@ mod tests {
             convert_url_to_filename(url),
             String::from("https_www_faz_net_aktuell_finanzen_.rss")
         );
+        for url in bench_urls() {
+            assert_eq!(
+                convert_url_to_filename(&url),
+                convert_url_to_filename_regex(&url)
+            );
+        }
+    }
+
+    // the previous regex based implementation of convert_url_to_filename
+    fn convert_url_to_filename_regex(url: &str) -> String {
+        lazy_static! {
+            static ref SANITIZE_REGEX: Regex = Regex::new(r"[^a-zA-Z0-9]+").unwrap();
+        }
+        SANITIZE_REGEX.replace_all(url, "_").to_string() + ".rss"
+    }
+
+    // feed urls like in a large OPML file, with runs of special and non-ASCII characters
+    fn bench_urls() -> Vec<String> {
+        (0..500)
+            .map(|index| {
+                format!(
+                    "https://www.example{}.com/rss/__ressort-{}/München%20Süd/?view=1&&page={}#top",
+                    index % 7,
+                    index,
+                    index * 31
+                )
+            })
+            .collect()
     }
 
     #[test]
@@ -112,12 +682,69 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_feed_name_template() {
+        let url = "https://WWW.Tagesschau.de/xml/rss2/";
+        let template = FeedNameTemplate::new("{host}-{title-slug}.rss").unwrap();
+        assert_eq!(
+            template.filename(url, "Tagesschau: Nachrichten", |_| false),
+            "www-tagesschau-de-tagesschau-nachrichten.rss"
+        );
+        // without a title the host is used
+        assert_eq!(
+            template.filename(url, "", |_| false),
+            "www-tagesschau-de-www-tagesschau-de.rss"
+        );
+        let id = FeedNameTemplate::new("feed-{id}.rss")
+            .unwrap()
+            .filename(url, "", |_| false);
+        assert!(Regex::new("^feed-[0-9a-f]{8}\\.rss$")
+            .unwrap()
+            .is_match(&id));
+        // a name that is taken gets a uuid before its extension
+        let taken = "www-tagesschau-de-tagesschau.rss";
+        let filename = template.filename(url, "tagesschau", |name| name == taken);
+        assert!(filename.starts_with("www-tagesschau-de-tagesschau-"));
+        assert!(filename.ends_with(".rss"));
+        assert!(UUID_REGEX.is_match(&filename));
+
+        for invalid in [
+            "feed.rss",
+            "{name}.rss",
+            "../{host}.rss",
+            "a/{host}.rss",
+            ".{host}",
+        ] {
+            assert!(FeedNameTemplate::new(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[bench]
+    fn bench_convert_url_to_filename(b: &mut Bencher) {
+        let urls = bench_urls();
+        b.iter(|| {
+            for url in &urls {
+                test::black_box(convert_url_to_filename(url));
+            }
+        })
+    }
+
+    #[bench]
+    fn bench_convert_url_to_filename_regex(b: &mut Bencher) {
+        let urls = bench_urls();
+        b.iter(|| {
+            for url in &urls {
+                test::black_box(convert_url_to_filename_regex(url));
+            }
+        })
+    }
+
     #[bench]
     fn bench_extract_unique_id_and_host_from_url_string(b: &mut Bencher) {
         let url = "https://elviajero.elpais.com/elviajero/2022/07/26/actualidad/1658829008_842300.html#?ref=rss&format=simple&link=link
         ";
         b.iter(|| {
-            extract_unique_id_and_host_from_url_string(url);
+            extract_unique_id_and_host_from_url_string(url, &IdOptions::default());
         })
     }
 }
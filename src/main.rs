@@ -1,28 +1,63 @@
 #![feature(test)]
 
+mod admin;
+#[cfg(any(test, feature = "cassette"))]
+mod cassette;
+mod fetch;
+mod hosts;
 mod ids;
 mod opml;
+mod probe;
+mod report;
 mod rss;
+#[cfg(test)]
+mod testserver;
 mod timer;
 mod utilities;
+mod writer;
 
-use clap::Parser;
-use log::{error, info};
+use chrono::Local;
+use clap::{Parser, Subcommand};
+use log::{error, info, warn, LevelFilter};
+use regex::Regex;
+use report::{FeedReport, FeedStatus, IterationReport};
+use serde::Serialize;
 use std::cell::RefCell;
+use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+use tracing::info_span;
+use tracing_log::LogTracer;
+use tracing_subscriber::EnvFilter;
+
+// temporary files in the target directories older than this were left behind by a crash
+const STALE_TEMPORARY_FILE_AGE: Duration = Duration::from_secs(3600);
+
+// exit codes of --once: all feeds succeeded, some feeds failed, or the configuration is invalid
+// or the feeds cannot be initialized; invalid urls found by --validate-urls also exit with
+// EXIT_STARTUP_ERROR
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_FEEDS_FAILED: i32 = 1;
+const EXIT_STARTUP_ERROR: i32 = 2;
 
 /// This struct defines the command line interface for the application
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Serialize)]
 #[clap(
     author = "github.com/bodobolero/rssdeduper/",
     version,
     about = "Deduplicate RSS feeds",
     long_about = r#"See https://github.com/Bodobolero/rssdeduper/README.md for more information.
 To see logging information invoke with
-RUST_LOG=info"#
+--log-level info or RUST_LOG=info"#
 )]
 struct Cli {
+    #[clap(subcommand)]
+    #[serde(skip)]
+    command: Option<Command>,
+
     /// Sets the source OPML filename
     #[clap(long, value_name = "FILE", default_value = "./feedly-source.opml")]
     so: String,
@@ -31,13 +66,46 @@ struct Cli {
     #[clap(long, value_name = "FILE", default_value = "./feedly-target.opml")]
     to: String,
 
+    /// Only deduplicates the feeds in the source OPML category (folder outline) NAME, can be
+    /// repeated; the other feeds keep their original url in the target OPML. Takes effect when
+    /// the feeds file is regenerated from a changed source OPML
+    #[clap(long, value_name = "NAME")]
+    category: Vec<String>,
+
+    /// Sets the order of the feeds within each folder of the target OPML: as in the source OPML,
+    /// by title or by feed url. Takes effect when the target OPML is regenerated
+    #[clap(long, value_enum, value_name = "ORDER", default_value = "none")]
+    sort_opml: opml::OpmlSort,
+
+    /// Only adds the new and removes the deleted feeds of a changed source OPML in the existing
+    /// target OPML instead of regenerating it, leaving all other outlines untouched
+    #[clap(long)]
+    incremental_opml: bool,
+
+    /// Writes the feeds and the target OPML file without indentation and line breaks, for the
+    /// smallest files when bandwidth matters
+    #[clap(long)]
+    compact: bool,
+
     /// Sets the target feed file
     #[clap(long, value_name = "FILE", default_value = "./feeds.json")]
     ff: String,
 
-    /// Sets the target directory for rss feeds
+    /// Sets the format of the feed file, by default taken from its extension (.toml, .ndjson or
+    /// .jsonl, otherwise json)
+    #[clap(long, value_enum, value_name = "FORMAT")]
+    feeds_file_format: Option<opml::FeedsFileFormat>,
+
+    /// Names the files of new feeds by TEMPLATE instead of a uuid followed by their url, with
+    /// the placeholders {host}, {title-slug} (the OPML title), {url} and {id} (8 random hex
+    /// digits), e.g. {host}-{title-slug}.rss; a name that is already taken gets a uuid appended
+    #[clap(long, value_name = "TEMPLATE", value_parser = ids::FeedNameTemplate::new)]
+    feed_name_template: Option<ids::FeedNameTemplate>,
+
+    /// Sets the target directory for rss feeds, can be repeated to write a copy of each feed to
+    /// further directories
     #[clap(long, value_name = "DIRECTORY", default_value = "/var/www/html/rss/")]
-    td: String,
+    td: Vec<String>,
 
     /// Sets the url prefix to be used in the target OPML file
     #[clap(
@@ -47,34 +115,604 @@ struct Cli {
     )]
     up: String,
 
+    /// Sets the number of feeds fetched at the same time, deduplication stays sequential so the
+    /// result is the same as with one
+    #[clap(long, value_name = "THREADS", default_value = "1")]
+    fetch_threads: usize,
+
+    /// Sets the number of threads that write the deduplicated feeds in the background while the
+    /// next feed is deduplicated, for slow storage like network filesystems; 0 writes each feed
+    /// before the next one is deduplicated
+    #[clap(long, value_name = "THREADS", default_value = "0")]
+    write_threads: usize,
+
+    /// Checks at startup that the url prefix is a valid url ending in / that matches the target
+    /// directory and that the served urls of the feeds are valid, and exits with 2 if not
+    #[clap(long)]
+    validate_urls: bool,
+
+    /// Prints the feeds a regenerated target OPML and feeds file would contain, new ones with
+    /// the filename they would get, and exits without writing any file; takes --urls,
+    /// --feed-name-template and --incremental-opml into account like a real run
+    #[clap(long)]
+    dry_run_opml: bool,
+
     /// Sets the wait time in seconds between iterations
     #[clap(long, value_name = "SECONDS", default_value = "60")]
     wt: u64,
 
+    /// Sets the minimum wait time in seconds between iterations, a smaller --wt is raised to it
+    /// so a typo does not hammer the origins of the feeds
+    #[clap(long, value_name = "SECONDS", default_value = "30")]
+    min_interval: u64,
+
     /// Sets the maximum number of iterations, default 0 means unlimited
     #[clap(long, value_name = "ITERATIONS", default_value = "0")]
     it: u64,
 
+    /// Runs a single iteration and exits with 0 if all feeds succeeded, 1 if some feeds failed
+    /// and 2 if the configuration is invalid or the feeds cannot be initialized, e.g. for cron
+    /// or CI
+    #[clap(long)]
+    once: bool,
+
     /// Sets the maximum age of feeds in hours, 0 means unlimited, default 24
     #[clap(long, value_name = "MAXAGE", default_value = "24")]
     ma: u64,
 
+    /// Ages items dated more than 15 minutes in the future, e.g. by feeds with a wrong timezone,
+    /// from when they were first seen instead of their date, which would keep them forever;
+    /// such items are logged with a warning either way
+    #[clap(long)]
+    clamp_future_dates: bool,
+
     /// Sets the cache history in hours used for checking duplicates, default 48, 0 means unlimited
     #[clap(long, value_name = "CACHE_HISTORY", default_value = "48")]
     ch: u64,
+
+    /// Attributes items to the feed given in their <source> element when checking duplicates
+    #[clap(long)]
+    use_source: bool,
+
+    /// Only logs errors, RUST_LOG takes precedence if set
+    #[clap(long, conflicts_with = "log_level")]
+    quiet: bool,
+
+    /// Sets the log level (off, error, warn, info, debug, trace), RUST_LOG takes precedence if set
+    #[clap(long, value_name = "LEVEL")]
+    log_level: Option<LevelFilter>,
+
+    /// Runs the shell command with the feed filename and url as arguments after a feed file changed
+    #[clap(long, value_name = "COMMAND")]
+    on_update: Option<String>,
+
+    /// Keeps DOCTYPE declarations in feeds, unsafe because it allows entity expansion attacks
+    #[clap(long)]
+    allow_dtd: bool,
+
+    /// Does not deduplicate feeds larger than BYTES and keeps their previous file, 0 means
+    /// unlimited
+    #[clap(long, value_name = "BYTES", default_value = "0")]
+    max_feed_bytes: usize,
+
+    /// Does not deduplicate feeds with more than ELEMENTS XML elements and keeps their previous
+    /// file, 0 means unlimited
+    #[clap(long, value_name = "ELEMENTS", default_value = "0")]
+    max_feed_elements: usize,
+
+    /// Sets the time in seconds after which a feed is abandoned in one stage: fetch (the HTTP
+    /// request), parse (before deduplication) or write (of its file); can be given once per
+    /// stage; an abandoned write finishes in the background and the feed is not written again
+    /// before it has
+    #[clap(long, value_name = "STAGE=SECONDS", value_parser = parse_stage_timeout_arg)]
+    feed_timeout_per_stage: Vec<String>,
+
+    /// Uses IDENTITY instead of the channel link of the feed with url FEED_URL when checking
+    /// duplicates, can be repeated
+    #[clap(long, value_name = "FEED_URL=IDENTITY", value_parser = parse_channel_identity)]
+    channel_identity: Vec<(String, String)>,
+
+    /// Sets what identifies the channel of feeds without --channel-identity when checking
+    /// duplicates: their channel link, only its host, or the feed url, so a feed whose channel
+    /// link changes is not deduplicated against its own items; items kept in --state stay
+    /// attributed to the channel they were stored with, so after switching the key a feed's own
+    /// items count as duplicates from another channel until the state is cleared with
+    /// --reset-state
+    #[clap(long, value_enum, value_name = "KEY", default_value = "link")]
+    channel_key: rss::ChannelKey,
+
+    /// Takes the id of items whose link has host HOST from the path segment at INDEX instead of
+    /// searching it, negative indices count from the end (-1 is the last segment), can be repeated;
+    /// HOST may be a glob like *.example.com or a regular expression between slashes
+    #[clap(long, value_name = "HOST=INDEX", value_parser = parse_id_path_segment)]
+    id_path_segment: Vec<(hosts::HostMatcher, i32)>,
+
+    /// Takes the id of items whose link has host HOST from their title, as the first capture
+    /// group of the regular expression PATTERN or its whole match, for links without a stable
+    /// id; titles that do not match use the id of the link, can be repeated
+    #[clap(long, value_name = "HOST=PATTERN", value_parser = parse_id_title_pattern)]
+    id_title_pattern: Vec<(hosts::HostMatcher, String)>,
+
+    /// Removes the path segments that match the regular expression PATTERN as a whole from item
+    /// links before their id is extracted, for session or tracking tokens in the path, can be
+    /// repeated
+    #[clap(long, value_name = "PATTERN", value_parser = parse_segment_pattern_arg)]
+    ignore_path_segment: Vec<String>,
+
+    /// Writes a merged feed of all deduplicated items, newest first, to FILENAME in the target directory
+    #[clap(long, value_name = "FILENAME")]
+    firehose: Option<String>,
+
+    /// Sets the maximum number of items in the merged feed, 0 means unlimited
+    #[clap(long, value_name = "ITEMS", default_value = "0")]
+    firehose_max_items: usize,
+
+    /// Writes a feed with an item for each feed that failed in the iteration to FILENAME in the
+    /// target directory, to see failures in the RSS reader; it has no items if all feeds succeed
+    #[clap(long, value_name = "FILENAME")]
+    error_feed: Option<String>,
+
+    /// Writes the served urls of all feeds to FILENAME in the target directory in each
+    /// iteration, as a sitemap if FILENAME ends in .xml and one url per line otherwise
+    #[clap(long, value_name = "FILENAME")]
+    feeds_list: Option<String>,
+
+    /// Writes the fetched body of each feed that cannot be deduplicated, e.g. because it does
+    /// not parse, to a new file in DIRECTORY named by the time and the feed url
+    #[clap(long, value_name = "DIRECTORY")]
+    dead_letter_dir: Option<String>,
+
+    /// Sets the number of files kept in the dead letter directory, older ones are removed, 0
+    /// means unlimited
+    #[clap(long, value_name = "FILES", default_value = "100")]
+    dead_letter_max_files: usize,
+
+    /// Writes a JSON manifest with the time of the last update and the number of items of each
+    /// served feed to FILENAME in the target directory in each iteration
+    #[clap(long, value_name = "FILENAME")]
+    manifest: Option<String>,
+
+    /// Reads the feeds from a text file with one feed url per line instead of the source OPML file
+    #[clap(long, value_name = "FILE")]
+    urls: Option<String>,
+
+    /// Writes a FEED.sha256 file with the SHA-256 checksum next to each feed file
+    #[clap(long)]
+    checksum: bool,
+
+    /// Sets whether items are only duplicates of items with the same id on the same host or on
+    /// any host, e.g. for articles of a wire service syndicated by several publishers; global
+    /// also treats unrelated items on different hosts with the same number in their url as
+    /// duplicates
+    #[clap(long, value_enum, value_name = "SCOPE", default_value = "host")]
+    item_dedup_scope: rss::DedupScope,
+
+    /// Sets which item is kept if several items of a feed have the same guid
+    #[clap(long, value_enum, value_name = "ITEM", default_value = "first")]
+    duplicate_guids: rss::KeepDuplicate,
+
+    /// Keeps the date of the first appearance of an item on the served item, also if the content
+    /// of a newer duplicate is kept, so feed readers do not notify about the item again
+    #[clap(long)]
+    freeze_pubdate: bool,
+
+    /// Sets how feeds with XML defects are parsed: strict rejects them, lenient escapes bare
+    /// ampersands and removes control characters that XML does not allow first
+    #[clap(long, value_enum, value_name = "STRICTNESS", default_value = "strict")]
+    parse_strictness: rss::ParseStrictness,
+
+    /// Sets the order of the items in the deduplicated feeds
+    #[clap(long, value_enum, value_name = "ORDER", default_value = "source")]
+    item_order: rss::ItemOrder,
+
+    /// Sets the channel lastBuildDate (and pubDate, if the channel has one) of the
+    /// deduplicated feeds, which may no longer match their items after deduplication
+    #[clap(long, value_enum, value_name = "MODE", default_value = "keep")]
+    last_build_date: rss::LastBuildDate,
+
+    /// Truncates item titles longer than CHARACTERS at a word boundary with an ellipsis in the
+    /// deduplicated feeds, 0 means unlimited
+    #[clap(long, value_name = "CHARACTERS", default_value = "0")]
+    max_title_length: usize,
+
+    /// Sets the components of the item url that form the dedup key of items without an id in
+    /// their url: the path only, the path with the query, or the full url
+    #[clap(long, value_enum, value_name = "COMPONENTS", default_value = "full")]
+    dedup_key_include_path_query: ids::FallbackKey,
+
+    /// Sets the item elements whose url the item id is extracted from, tried in order, e.g.
+    /// link,guid for feeds that only have the article url in the guid; a guid with
+    /// isPermaLink="false" is used as it is, as an id within the host of the channel
+    #[clap(
+        long,
+        value_name = "ELEMENT",
+        value_delimiter = ',',
+        default_value = "link"
+    )]
+    link_source: Vec<String>,
+
+    /// Sets the generator of the deduplicated feeds to rssdeduper/<version>
+    #[clap(long)]
+    generator: bool,
+
+    /// Adds an rssdeduper:dedup element with the time of the deduplication and the number of
+    /// removed items to the deduplicated feeds, which changes the feed files in every iteration
+    #[clap(long)]
+    dedup_metadata: bool,
+
+    /// Adds an rssdeduper:dedup element with the time an item was first seen and the channel it
+    /// is attributed to to each item of the deduplicated feeds, to trace where items come from
+    #[clap(long)]
+    dedup_provenance: bool,
+
+    /// Deduplicates video items by their yt:videoId or media:content url instead of their link
+    #[clap(long)]
+    dedup_media: bool,
+
+    /// Also removes items whose title, start of description and enclosure match an item seen
+    /// before, ignoring case and punctuation, for reposts with a new link and guid
+    #[clap(long)]
+    signature_dedup: bool,
+
+    /// Removes all namespaced elements (content:, media:, dc:, ...) and namespace declarations
+    /// from the deduplicated feeds, leaving plain RSS 2.0 for simple readers
+    #[clap(long)]
+    strip_namespaces: bool,
+
+    /// Serves the new version of a known item if its dc:modified or atom:updated date is newer
+    /// than the one of the version served so far, for live-updated articles
+    #[clap(long)]
+    update_modified_items: bool,
+
+    /// Deduplicates items only by their guid and never by the id in their url, items without a
+    /// guid are always kept
+    #[clap(long)]
+    replace_on_guid_only: bool,
+
+    /// Keeps items of the previously served feed file that the origin no longer lists, as long
+    /// as they are not older than the maximum age
+    #[clap(long)]
+    retain_dropped_items: bool,
+
+    /// Fills the cache at startup with the items of the served feed files, so duplicates are
+    /// removed from the first iteration on even without a state file
+    #[clap(long)]
+    warm_from_served: bool,
+
+    /// Rewrites the channel link of the feeds to their served url under URL, defaults to the url prefix
+    #[clap(long, value_name = "URL", num_args = 0..=1)]
+    base_url: Option<Option<String>>,
+
+    /// Does not verify the TLS certificate of feeds on HOST, unsafe because it allows
+    /// man-in-the-middle attacks, can be repeated; HOST may be a glob like *.example.com or a
+    /// regular expression between slashes
+    #[clap(long, value_name = "HOST", value_parser = hosts::HostMatcher::new)]
+    allow_insecure_tls: Vec<hosts::HostMatcher>,
+
+    /// Sends USER_AGENT as User-Agent when reading feeds, the userAgent attribute of an outline
+    /// in the source OPML file overrides it for that feed
+    #[clap(long, value_name = "USER_AGENT")]
+    user_agent: Option<String>,
+
+    /// Accepts responses with the 2xx status CODES besides 200 as the feed, e.g. 203,206; feeds
+    /// answering with any other status fail
+    #[clap(
+        long,
+        value_name = "CODES",
+        value_delimiter = ',',
+        value_parser = clap::value_parser!(u16).range(200..300)
+    )]
+    feed_allowlist_status_codes: Vec<u16>,
+
+    /// Serves an admin API on localhost:PORT, POST /refresh triggers an immediate iteration and
+    /// POST /cleanup runs the midnight cache cleanup before the next iteration
+    #[clap(long, value_name = "PORT", requires = "admin_token")]
+    admin_port: Option<u16>,
+
+    /// Sets the bearer token required by the admin API, ${VAR} is replaced by the environment
+    /// variable VAR
+    #[clap(long, value_name = "TOKEN")]
+    #[serde(skip)]
+    admin_token: Option<String>,
+
+    /// Sets what is done with feeds that have no items after deduplication
+    #[clap(long, value_enum, value_name = "ACTION", default_value = "write")]
+    on_empty: rss::OnEmpty,
+
+    /// Sets what is done when the source OPML or feed urls file cannot be read, e.g. while the
+    /// newsreader rewrites it
+    #[clap(long, value_enum, value_name = "ACTION", default_value = "keep")]
+    on_source_error: OnSourceError,
+
+    /// Sets how the content of feeds without lastBuildDate is normalized before comparing it to
+    /// the previous iteration, so cosmetic changes do not count as updates
+    #[clap(long, value_enum, value_name = "MODE", default_value = "whitespace")]
+    content_normalization: rss::ContentNormalization,
+
+    /// Deduplicates against the items of previous iterations, with off only the items of the
+    /// current iteration are compared
+    #[clap(long, value_enum, value_name = "SWITCH", default_value = "on")]
+    dedup_across_iterations: Switch,
+
+    /// Keeps the previous COUNT versions of each feed file as FILE.bak, FILE.bak.1, ...
+    #[clap(
+        long,
+        value_name = "COUNT",
+        num_args = 0..=1,
+        default_value = "0",
+        default_missing_value = "1"
+    )]
+    keep_backup: usize,
+
+    /// Fails to write a feed file that is a symlink instead of writing to the file it points to
+    #[clap(long)]
+    no_follow_symlinks: bool,
+
+    /// Stops reading a feed after it failed in FAILURES consecutive iterations, 0 means never
+    #[clap(long, value_name = "FAILURES", default_value = "0")]
+    max_feed_failures: u32,
+
+    /// Sets how long a feed that failed too often is not read, 0 means until restart
+    #[clap(long, value_name = "SECONDS", default_value = "86400")]
+    feed_failure_backoff: u64,
+
+    /// Does not read a feed again before the cache lifetime of its last response, given by its
+    /// Cache-Control max-age or Expires header, has passed, even if iterations are more frequent
+    #[clap(long)]
+    respect_cache_control: bool,
+
+    /// Probes each feed with a HEAD request and only downloads it with a GET if the ETag,
+    /// Last-Modified or Content-Length changed since the last download; responses without an
+    /// ETag or Last-Modified are always downloaded, servers that do not support HEAD are read
+    /// with a GET
+    #[clap(long)]
+    head_first: bool,
+
+    /// Saves the items known for deduplication to FILE after each iteration and restores them on
+    /// startup
+    #[clap(long, value_name = "FILE")]
+    state: Option<String>,
+
+    /// Deletes the state file on startup and starts without known items, e.g. after a change of
+    /// the options that key the items
+    #[clap(long, requires = "state")]
+    reset_state: bool,
+
+    /// Writes a JSON report with the per-feed results, timings, errors and configuration of
+    /// each iteration to FILE
+    #[clap(long, value_name = "FILE")]
+    dedup_report_json: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Switch {
+    On,
+    Off,
+}
+
+/// what the daemon does when the source OPML or feed urls file cannot be read
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum OnSourceError {
+    /// log the error and keep processing the feeds of the feeds file
+    Keep,
+    /// exit with code 2
+    Exit,
+}
+
+/// stage of processing a feed that --feed-timeout-per-stage bounds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum Stage {
+    Fetch,
+    Parse,
+    Write,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Fetches the feed at URL and prints the key each item is deduplicated by
+    Probe {
+        /// Sets the url of the feed
+        #[clap(value_name = "URL")]
+        url: String,
+    },
+    /// Prints the source url, filename and served url of each feed in the feed file
+    ListFeeds,
+}
+
+// parse FEED_URL=IDENTITY, splitting at the last '=' because feed urls may contain query parameters
+fn parse_channel_identity(value: &str) -> Result<(String, String), String> {
+    match value.rsplit_once('=') {
+        Some((url, identity)) if !url.is_empty() && !identity.is_empty() => {
+            Ok((url.to_string(), identity.to_string()))
+        }
+        _ => Err(format!("expected FEED_URL=IDENTITY but got {}", value)),
+    }
+}
+
+fn parse_id_path_segment(value: &str) -> Result<(hosts::HostMatcher, i32), String> {
+    match value.rsplit_once('=') {
+        Some((host, index)) if !host.is_empty() => Ok((
+            hosts::HostMatcher::new(host)?,
+            index
+                .parse()
+                .map_err(|e| format!("invalid path segment index {}: {}", index, e))?,
+        )),
+        _ => Err(format!("expected HOST=INDEX but got {}", value)),
+    }
+}
+
+// parse HOST=PATTERN, splitting at the first '=' because hosts do not contain one; the pattern
+// is checked here and kept to show it in the configuration
+fn parse_id_title_pattern(value: &str) -> Result<(hosts::HostMatcher, String), String> {
+    match value.split_once('=') {
+        Some((host, pattern)) if !host.is_empty() && !pattern.is_empty() => {
+            Regex::new(pattern).map_err(|e| format!("invalid title pattern {}: {}", pattern, e))?;
+            Ok((hosts::HostMatcher::new(host)?, pattern.to_string()))
+        }
+        _ => Err(format!("expected HOST=PATTERN but got {}", value)),
+    }
+}
+
+// parse STAGE=SECONDS with a positive number of seconds, which may have a fraction
+fn parse_stage_timeout(value: &str) -> Result<(Stage, f64), String> {
+    let Some((stage, seconds)) = value.split_once('=') else {
+        return Err(format!("expected STAGE=SECONDS but got {}", value));
+    };
+    let stage = <Stage as clap::ValueEnum>::from_str(stage, true)
+        .map_err(|_| format!("unknown stage {}, expected fetch, parse or write", stage))?;
+    match seconds.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds > 0.0 => Ok((stage, seconds)),
+        _ => Err(format!(
+            "invalid timeout {}, expected positive seconds",
+            seconds
+        )),
+    }
+}
+
+// check --feed-timeout-per-stage on parsing, the value is kept to show it in the configuration
+fn parse_stage_timeout_arg(value: &str) -> Result<String, String> {
+    parse_stage_timeout(value).map(|_| value.to_string())
+}
+
+// the timeout of stage, the last one given wins
+fn stage_timeout(cli: &Cli, stage: Stage) -> Option<Duration> {
+    cli.feed_timeout_per_stage
+        .iter()
+        .filter_map(|value| parse_stage_timeout(value).ok())
+        .rev()
+        .find(|(timeout_stage, _)| *timeout_stage == stage)
+        .map(|(_, seconds)| Duration::from_secs_f64(seconds))
+}
+
+// a regular expression that matches whole path segments
+fn parse_segment_pattern(pattern: &str) -> Result<Regex, String> {
+    Regex::new(&format!("^(?:{})$", pattern))
+        .map_err(|e| format!("invalid path segment pattern {}: {}", pattern, e))
+}
+
+// check --ignore-path-segment on parsing, the pattern is kept to show it in the configuration
+fn parse_segment_pattern_arg(pattern: &str) -> Result<String, String> {
+    parse_segment_pattern(pattern).map(|_| pattern.to_string())
+}
+
+// the format of the feeds file, given by its extension unless set explicitly
+fn feeds_file_format(cli: &Cli) -> opml::FeedsFileFormat {
+    cli.feeds_file_format
+        .unwrap_or_else(|| opml::FeedsFileFormat::from_filename(&cli.ff))
+}
+
+// the wait time between iterations, --wt clamped to --min-interval
+fn wait_time(cli: &Cli) -> u64 {
+    if cli.wt < cli.min_interval {
+        warn!(
+            "Wait time of {} seconds is below the minimum interval, waiting {} seconds instead",
+            cli.wt, cli.min_interval
+        );
+    }
+    cli.wt.max(cli.min_interval)
+}
+
+// the log level requested on the command line, defaults to errors only like env_logger
+fn log_filter(cli: &Cli) -> LevelFilter {
+    if cli.quiet {
+        LevelFilter::Error
+    } else {
+        cli.log_level.unwrap_or(LevelFilter::Error)
+    }
+}
+
+// print the log records on stderr with the iteration and feed spans they were logged in; the
+// directives of RUST_LOG come last so they override the level from the command line
+fn init_logging(cli: &Cli) {
+    let mut directives = log_filter(cli).to_string().to_lowercase();
+    if let Ok(rust_log) = std::env::var("RUST_LOG") {
+        directives = format!("{},{}", directives, rust_log);
+    }
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new(directives))
+        .with_writer(std::io::stderr)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+    LogTracer::init().unwrap();
 }
 
 fn main() {
-    env_logger::init();
     let cli = Cli::parse();
+    init_logging(&cli);
 
-    info!("Source OPML filename: {}", cli.so);
+    if let Some(command) = &cli.command {
+        let output = match command {
+            Command::Probe { url } => Settings::new(&cli).and_then(|settings| {
+                probe::probe(url, &settings.fetcher, &settings.dedup_options.id_options)
+            }),
+            Command::ListFeeds => utilities::list_feeds(&cli.ff, feeds_file_format(&cli), &cli.up),
+        };
+        match output {
+            Ok(output) => print!("{}", output),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if cli.dry_run_opml {
+        let preview = Settings::new(&cli).and_then(|settings| {
+            utilities::preview_opml_changes(
+                &cli.so,
+                cli.urls.as_deref(),
+                &cli.ff,
+                &cli.up,
+                &cli.to,
+                feeds_file_format(&cli),
+                &settings.target_options,
+            )
+        });
+        match preview {
+            Ok(preview) => print!("{}", preview),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if cli.validate_urls {
+        let feeds = opml::read_feeds(&cli.ff, feeds_file_format(&cli)).unwrap_or_default();
+        let filenames: Vec<&str> = feeds
+            .iter()
+            .map(|(_, filename)| filename.as_str())
+            .chain(cli.firehose.as_deref())
+            .chain(cli.error_feed.as_deref())
+            .collect();
+        match utilities::validate_urls(&cli.up, &cli.td[0], &filenames) {
+            Ok(warnings) => warnings.iter().for_each(|warning| warn!("{}", warning)),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(EXIT_STARTUP_ERROR);
+            }
+        }
+    }
+
+    if let Some(urls) = &cli.urls {
+        info!("Feed urls filename: {}", urls);
+    } else {
+        info!("Source OPML filename: {}", cli.so);
+    }
     info!("Target OPML filename: {}", cli.to);
     info!("URL prefix: {}", cli.up);
     info!("Target feed file: {}", cli.ff);
-    info!("Target directory for rss feeds: {}", cli.td);
-    info!("Iteration wait time: {} seconds", cli.wt);
+    info!("Target directories for rss feeds: {}", cli.td.join(", "));
+    let wait_time = wait_time(&cli);
+    info!("Iteration wait time: {} seconds", wait_time);
     info!("Maximum number of iterations: {}", cli.it);
+    if cli.once {
+        std::process::exit(run_once(&cli));
+    }
     // I have a mutable reference known_feeds. I have function periodic_tasks that receives two closures that it never calls at the same time.
     // However each of the closures needs the mutable reference known_feeds.
     // Refcell allows to check ownership at runtime instead of compile time.
@@ -82,66 +720,2151 @@ fn main() {
     // passes it to each closure, but this would create a dependency on the feeds
     // datatypes in generic function periodic_task
     //let mut known_feeds: rss::ExistingItemsMap = HashMap::new();
-    let known_feeds: RefCell<rss::ExistingItemsMap> = RefCell::new(HashMap::new());
+    let (known_feeds, signatures) = load_state(&cli);
+    let known_feeds: RefCell<rss::ExistingItemsMap> = RefCell::new(known_feeds);
+    let signatures: RefCell<rss::SignatureMap> = RefCell::new(signatures);
     let mut feed_map: HashMap<String, rss::Feed> = HashMap::new();
+    let settings = match Settings::new(&cli) {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(EXIT_STARTUP_ERROR);
+        }
+    };
+    if cli.warm_from_served {
+        warm_from_served(&cli, &settings, &mut known_feeds.borrow_mut());
+    }
+    remove_stale_temporary_files(&cli);
+    let (wakeup, wakeup_receiver) = mpsc::channel();
+    let cleanup = Arc::new(AtomicBool::new(false));
+    if let (Some(port), Some(token)) = (cli.admin_port, &cli.admin_token) {
+        let token = match utilities::expand_env_vars(token) {
+            Ok(token) => token,
+            Err(e) => {
+                error!("Cannot read the admin token: {}", e);
+                std::process::exit(EXIT_STARTUP_ERROR);
+            }
+        };
+        if let Err(e) = admin::start_admin_server(port, token, wakeup, cleanup.clone()) {
+            error!("{}", e);
+            std::process::exit(EXIT_STARTUP_ERROR);
+        }
+    }
 
     timer::periodic_task(
         || {
-            let feeds =
-                utilities::check_and_init_feeds(&cli.so, &cli.ff, &cli.up, &cli.to).unwrap();
-            for (url, filename) in &feeds {
-                let current_feed = feed_map.entry(url.clone()).or_insert_with(|| {
-                    // create a valid filename in target directory cli.td
-                    let fully_qualified_filename = Path::new(&cli.td).join(filename);
-                    // if the target directory or filename is not valid we want to panic!
-                    rss::Feed::new(url, fully_qualified_filename.to_str().unwrap())
-                });
-                let read_result = current_feed.read();
-                if let Ok(updated) = read_result {
-                    if updated {
-                        let mut known_feeds = known_feeds.borrow_mut();
-                        let dedup_result = current_feed.remove_duplicates(&mut known_feeds, cli.ma);
-                        if dedup_result.is_ok() {
-                            let write_result = current_feed.write();
-                            if write_result.is_ok() {
-                                info!("Updated RSS feed {} in file {}", url, filename);
-                            } else {
-                                error!("Could not write updated feed {} to file {}", url, filename);
-                            }
-                        } else {
-                            error!(
-                                "Error de-duplicating feed {}: {}",
-                                url,
-                                dedup_result.unwrap_err()
-                            );
-                        }
-                    } else {
-                        info!("RSS feed not updated since last iteration: {}", url);
-                    }
-                } else {
-                    error!("Error reading feed {}: {}", url, read_result.unwrap_err());
-                }
+            let report = process_feeds(
+                &cli,
+                &settings,
+                &mut feed_map,
+                &mut known_feeds.borrow_mut(),
+                &mut signatures.borrow_mut(),
+            );
+            if report.source_error.is_some() {
+                std::process::exit(exit_code(&report));
             }
+            report
         },
         || {
+            let (entries, bytes) = rss::existing_items_usage(&known_feeds.borrow());
             info!(
-                "Midnight function invoked, cache before pruning contains {} feeds",
-                known_feeds.borrow().len()
+                "Midnight function invoked, cache before pruning contains {} feeds retaining about {} bytes",
+                entries, bytes
             );
             let now = std::time::SystemTime::now();
             // at midnight we want to clear the known feeds older than cache_history hours , to reduce memory usage
-            known_feeds.borrow_mut().retain(|_, &mut (_, _, time)| {
-                match now.duration_since(time) {
-                    Ok(elapsed) => elapsed < std::time::Duration::from_secs(cli.ch * 60 * 60),
-                    Err(_) => false,
-                }
-            });
+            let is_recent = |time: std::time::SystemTime| match now.duration_since(time) {
+                Ok(elapsed) => elapsed < std::time::Duration::from_secs(cli.ch * 60 * 60),
+                Err(_) => false,
+            };
+            known_feeds
+                .borrow_mut()
+                .retain(|_, &mut (_, _, time)| is_recent(time));
+            signatures
+                .borrow_mut()
+                .retain(|_, &mut (_, _, time)| is_recent(time));
+            let (entries, bytes) = rss::existing_items_usage(&known_feeds.borrow());
             info!(
-                "Midnight function invoked, cache after pruning contains {} feeds",
-                known_feeds.borrow().len()
+                "Midnight function invoked, cache after pruning contains {} feeds retaining about {} bytes",
+                entries, bytes
             );
+            remove_stale_temporary_files(&cli);
         },
-        cli.wt,
+        wait_time,
         cli.it,
+        Some(&wakeup_receiver),
+        Some(&cleanup),
+    );
+}
+
+// one iteration for --once, its exit code tells if all feeds succeeded
+fn run_once(cli: &Cli) -> i32 {
+    let settings = match Settings::new(cli) {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("{}", e);
+            return EXIT_STARTUP_ERROR;
+        }
+    };
+    // a single iteration has no feeds of a previous one to keep
+    let feeds = match init_feeds(cli, &settings) {
+        Ok(feeds) => feeds,
+        Err(e) => {
+            error!("{}", e);
+            return EXIT_STARTUP_ERROR;
+        }
+    };
+    remove_stale_temporary_files(cli);
+    let (mut known_feeds, mut signatures) = load_state(cli);
+    if cli.warm_from_served {
+        warm_from_served(cli, &settings, &mut known_feeds);
+    }
+    let report = process_iteration(
+        cli,
+        &settings,
+        &feeds,
+        &mut HashMap::new(),
+        &mut known_feeds,
+        &mut signatures,
     );
+    exit_code(&report)
+}
+
+// fill known_feeds with the items of the served feed files for --warm-from-served
+fn warm_from_served(cli: &Cli, settings: &Settings, known_feeds: &mut rss::ExistingItemsMap) {
+    match opml::read_feeds(&cli.ff, feeds_file_format(cli)) {
+        Ok(feeds) => {
+            let added =
+                rss::warm_existing_items(known_feeds, &feeds, &cli.td[0], &settings.dedup_options);
+            info!("Warmed the cache with {} items of the served feeds", added);
+        }
+        Err(e) => warn!("Cannot warm the cache from the served feeds: {}", e),
+    }
+}
+
+fn exit_code(report: &IterationReport) -> i32 {
+    if report.source_error.is_some() {
+        return EXIT_STARTUP_ERROR;
+    }
+    let failed = report.count(FeedStatus::Failed);
+    if failed > 0 {
+        error!("{} of {} feeds failed", failed, report.feeds.len());
+        EXIT_FEEDS_FAILED
+    } else {
+        EXIT_SUCCESS
+    }
+}
+
+// the (url, filename) pairs of the feeds file, created or updated from the source OPML or
+// feed urls file first if that changed
+fn init_feeds(cli: &Cli, settings: &Settings) -> Result<Vec<(String, String)>, String> {
+    let source = cli.urls.as_deref().unwrap_or(&cli.so);
+    if !Path::new(source).exists() {
+        return Err(format!("Source file {} not found", source));
+    }
+    let feeds_format = feeds_file_format(cli);
+    match &cli.urls {
+        Some(urls) => utilities::check_and_init_feeds_from_urls(
+            urls,
+            &cli.ff,
+            feeds_format,
+            cli.feed_name_template.as_ref(),
+        ),
+        None => utilities::check_and_init_feeds(
+            &cli.so,
+            &cli.ff,
+            &cli.up,
+            &cli.to,
+            feeds_format,
+            &settings.target_options,
+        ),
+    }
+}
+
+// the items and signatures saved to the state file, none without a state file or with
+// --reset-state, which deletes it
+fn load_state(cli: &Cli) -> (rss::ExistingItemsMap, rss::SignatureMap) {
+    match &cli.state {
+        Some(state) if cli.reset_state => {
+            info!("Resetting state {}", state);
+            if let Err(e) = std::fs::remove_file(state) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    error!("Could not delete state {}: {}", state, e);
+                }
+            }
+            Default::default()
+        }
+        Some(state) if Path::new(state).exists() => {
+            rss::load_existing_items(state).unwrap_or_else(|e| {
+                error!("Starting with empty state: {}", e);
+                Default::default()
+            })
+        }
+        _ => Default::default(),
+    }
+}
+
+// remove the temporary files left behind by a crash from the target directories
+fn remove_stale_temporary_files(cli: &Cli) {
+    for directory in &cli.td {
+        if let Err(e) = utilities::remove_stale_temporary_files(directory, STALE_TEMPORARY_FILE_AGE)
+        {
+            error!("{}", e);
+        }
+    }
+}
+
+/// options derived from the command line that are used in each iteration
+struct Settings {
+    fetcher: fetch::Fetcher,
+    dedup_options: rss::DedupOptions,
+    write_options: rss::WriteOptions,
+    target_options: opml::TargetOptions,
+}
+
+impl Settings {
+    fn new(cli: &Cli) -> Result<Self, String> {
+        Ok(Self {
+            fetcher: fetch::Fetcher::new(&fetch::FetchOptions {
+                insecure_tls_hosts: cli.allow_insecure_tls.clone(),
+                accepted_status_codes: cli.feed_allowlist_status_codes.clone(),
+                user_agent: cli.user_agent.clone(),
+                head_first: cli.head_first,
+                timeout: stage_timeout(cli, Stage::Fetch),
+                #[cfg(any(test, feature = "cassette"))]
+                cassette: cassette::CassetteMode::from_env()?,
+            })?,
+            dedup_options: rss::DedupOptions {
+                max_age: cli.ma,
+                parse_strictness: cli.parse_strictness,
+                clamp_future_dates: cli.clamp_future_dates,
+                use_source: cli.use_source,
+                allow_dtd: cli.allow_dtd,
+                max_document_bytes: cli.max_feed_bytes,
+                max_document_elements: cli.max_feed_elements,
+                parse_timeout: stage_timeout(cli, Stage::Parse),
+                channel_identities: cli.channel_identity.iter().cloned().collect(),
+                channel_key: cli.channel_key,
+                id_options: ids::IdOptions {
+                    path_segments: cli.id_path_segment.clone(),
+                    title_patterns: cli
+                        .id_title_pattern
+                        .iter()
+                        .map(|(host, pattern)| {
+                            Regex::new(pattern)
+                                .map(|pattern| (host.clone(), pattern))
+                                .map_err(|e| format!("invalid title pattern {}: {}", pattern, e))
+                        })
+                        .collect::<Result<_, _>>()?,
+                    fallback_key: cli.dedup_key_include_path_query,
+                    ignored_segments: cli
+                        .ignore_path_segment
+                        .iter()
+                        .map(|pattern| parse_segment_pattern(pattern))
+                        .collect::<Result<_, _>>()?,
+                },
+                item_dedup_scope: cli.item_dedup_scope,
+                duplicate_guids: cli.duplicate_guids,
+                freeze_pubdate: cli.freeze_pubdate,
+                item_order: cli.item_order,
+                last_build_date: cli.last_build_date,
+                max_title_length: cli.max_title_length,
+                link_sources: cli.link_source.clone(),
+                dedup_media: cli.dedup_media,
+                strip_namespaces: cli.strip_namespaces,
+                update_modified_items: cli.update_modified_items,
+                replace_on_guid_only: cli.replace_on_guid_only,
+                generator: cli.generator,
+                compact: cli.compact,
+                dedup_metadata: cli.dedup_metadata,
+                signature_dedup: cli.signature_dedup,
+                dedup_provenance: cli.dedup_provenance,
+                retain_dropped_items: cli.retain_dropped_items,
+                base_url: cli
+                    .base_url
+                    .as_ref()
+                    .map(|base_url| base_url.clone().unwrap_or_else(|| cli.up.clone())),
+            },
+            write_options: rss::WriteOptions {
+                checksum: cli.checksum,
+                on_empty: cli.on_empty,
+                mirror_directories: cli.td[1..].to_vec(),
+                backups: cli.keep_backup,
+                no_follow_symlinks: cli.no_follow_symlinks,
+                timeout: stage_timeout(cli, Stage::Write),
+            },
+            target_options: opml::TargetOptions {
+                categories: cli.category.clone(),
+                sort: cli.sort_opml,
+                incremental: cli.incremental_opml,
+                compact: cli.compact,
+                name_template: cli.feed_name_template.clone(),
+            },
+        })
+    }
+}
+
+// one iteration: update the feeds from the source file, then read, deduplicate and write
+// them; a source error that stops the run is returned in the report
+fn process_feeds(
+    cli: &Cli,
+    settings: &Settings,
+    feed_map: &mut HashMap<String, rss::Feed>,
+    known_feeds: &mut rss::ExistingItemsMap,
+    signatures: &mut rss::SignatureMap,
+) -> IterationReport {
+    let started = Local::now().to_rfc3339();
+    match init_feeds(cli, settings) {
+        Ok(feeds) => process_iteration(cli, settings, &feeds, feed_map, known_feeds, signatures),
+        Err(e) if cli.on_source_error == OnSourceError::Keep => {
+            error!("Cannot update the feeds, keeping the previous ones: {}", e);
+            match opml::read_feeds(&cli.ff, feeds_file_format(cli)) {
+                Ok(feeds) => {
+                    process_iteration(cli, settings, &feeds, feed_map, known_feeds, signatures)
+                }
+                Err(e) => {
+                    error!("Skipping the iteration: {}", e);
+                    IterationReport {
+                        started,
+                        ..Default::default()
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            error!("{}", e);
+            IterationReport {
+                started,
+                source_error: Some(e),
+                ..Default::default()
+            }
+        }
+    }
+}
+
+// read, deduplicate and write the feeds, (url, filename) pairs of the feeds file
+fn process_iteration(
+    cli: &Cli,
+    settings: &Settings,
+    feeds: &[(String, String)],
+    feed_map: &mut HashMap<String, rss::Feed>,
+    known_feeds: &mut rss::ExistingItemsMap,
+    signatures: &mut rss::SignatureMap,
+) -> IterationReport {
+    let started = Local::now();
+    let _span = info_span!("iteration", started = %started.to_rfc3339()).entered();
+    rss::update_feed_map(feed_map, feeds, &cli.td[0]);
+    if let Some(feeds_list) = &cli.feeds_list {
+        let filenames: Vec<&str> = feeds
+            .iter()
+            .map(|(_, filename)| filename.as_str())
+            .chain(cli.firehose.as_deref())
+            .chain(cli.error_feed.as_deref())
+            .collect();
+        let filename = Path::new(&cli.td[0]).join(feeds_list);
+        if let Err(e) = utilities::write_feeds_list(filename.to_str().unwrap(), &cli.up, &filenames)
+        {
+            error!("Could not write feeds list {}: {}", feeds_list, e);
+        }
+    }
+    // settings removed from the OPML or feeds file since the last iteration do not stay
+    for feed in feed_map.values_mut() {
+        feed.set_max_age(None);
+        feed.set_priority(0);
+    }
+    if cli.urls.is_none() {
+        let outline_settings = opml::read_outline_settings(&cli.so).unwrap_or_else(|e| {
+            error!("Cannot read the feed attributes of the source OPML: {}", e);
+            HashMap::new()
+        });
+        for (url, filename) in feeds {
+            // outlines of a re-imported target OPML point to the deduplicated feed
+            let served_url = format!("{}{}", cli.up, filename);
+            let settings = outline_settings
+                .get(url)
+                .or_else(|| outline_settings.get(&served_url))
+                .cloned()
+                .unwrap_or_default();
+            let feed = feed_map.get_mut(url).unwrap();
+            feed.set_change_detection(settings.change_detection);
+            feed.set_request_headers(settings.request_headers);
+            feed.set_priority(settings.priority);
+        }
+    }
+    // settings in the feeds file replace those of the OPML file
+    let feed_settings =
+        opml::read_feed_settings(&cli.ff, feeds_file_format(cli)).unwrap_or_else(|e| {
+            error!("Cannot read feed settings: {}", e);
+            HashMap::new()
+        });
+    for (url, settings) in &feed_settings {
+        if let Some(feed) = feed_map.get_mut(url) {
+            feed.set_max_age(settings.max_age);
+            if let Some(priority) = settings.priority {
+                feed.set_priority(priority);
+            }
+        }
+    }
+    let gone_feeds_file = utilities::gone_feeds_filename(&cli.ff);
+    let gone_feeds = utilities::read_gone_feeds(&gone_feeds_file).unwrap_or_else(|e| {
+        error!("{}", e);
+        Default::default()
+    });
+    for url in &gone_feeds {
+        if let Some(feed) = feed_map.get_mut(url) {
+            feed.mark_gone();
+        }
+    }
+    if cli.dedup_across_iterations == Switch::Off {
+        known_feeds.clear();
+        signatures.clear();
+    }
+    rss::evict_outdated_items(known_feeds, cli.ma, cli.clamp_future_dates);
+    rss::evict_outdated_items(signatures, cli.ma, cli.clamp_future_dates);
+    let mut report = IterationReport {
+        started: started.to_rfc3339(),
+        ..Default::default()
+    };
+    let now = Instant::now();
+    // fetch and parse the feeds in parallel, their deduplication below is sequential
+    let mut reading: Vec<&mut rss::Feed> = feed_map
+        .values_mut()
+        .filter(|feed| {
+            !feed.is_gone()
+                && feed.is_enabled(now)
+                && !(cli.respect_cache_control && feed.is_fresh(now))
+        })
+        .collect();
+    // by priority, highest first, and else in the order of the feeds file, as they were read
+    // before, so the feeds that matter most are fresh even if a slow feed delays the others
+    let positions: HashMap<&str, usize> = feeds
+        .iter()
+        .enumerate()
+        .map(|(position, (url, _))| (url.as_str(), position))
+        .collect();
+    reading.sort_by_key(|feed| (Reverse(feed.priority()), positions[feed.url()]));
+    let results = rss::read_all(
+        &mut reading,
+        &settings.fetcher,
+        cli.content_normalization,
+        &settings.dedup_options,
+        cli.fetch_threads,
+    );
+    let mut read_results: HashMap<String, Result<bool, String>> = reading
+        .iter()
+        .map(|feed| feed.url().to_string())
+        .zip(results)
+        .collect();
+    // indices into report.feeds of the feeds that were updated upstream
+    let mut updated = Vec::new();
+    for (url, filename) in feeds {
+        let current_feed = feed_map.get_mut(url).unwrap();
+        let mut feed_report = FeedReport {
+            url: url.clone(),
+            filename: filename.clone(),
+            status: FeedStatus::NotUpdated,
+            dedup: None,
+            error: None,
+        };
+        if current_feed.is_gone() {
+            info!("Not reading gone feed {}", url);
+            feed_report.status = FeedStatus::Gone;
+            report.feeds.push(feed_report);
+            continue;
+        }
+        if !current_feed.is_enabled(now) {
+            info!("Not reading disabled feed {}", url);
+            feed_report.status = FeedStatus::Disabled;
+            report.feeds.push(feed_report);
+            continue;
+        }
+        // only feeds within their cache lifetime were not read
+        let Some(read_result) = read_results.remove(url) else {
+            info!("Not reading feed {} within its cache lifetime", url);
+            report.feeds.push(feed_report);
+            continue;
+        };
+        match read_result {
+            Ok(true) => updated.push(report.feeds.len()),
+            Ok(false) => info!("RSS feed not updated since last iteration: {}", url),
+            Err(e) => {
+                let e = format!("Error reading feed {}: {}", url, e);
+                error!("{}", e);
+                feed_report.status = if current_feed.is_gone() {
+                    FeedStatus::Gone
+                } else {
+                    FeedStatus::Failed
+                };
+                feed_report.error = Some(e);
+            }
+        }
+        report.feeds.push(feed_report);
+    }
+    // record feeds that went away so they are not read again after a restart either
+    let gone: Vec<&str> = report
+        .feeds
+        .iter()
+        .filter(|feed_report| feed_report.status == FeedStatus::Gone)
+        .map(|feed_report| feed_report.url.as_str())
+        .collect();
+    if gone.iter().any(|url| !gone_feeds.contains(*url)) {
+        if let Err(e) = utilities::write_gone_feeds(&gone_feeds_file, &gone) {
+            error!("{}", e);
+        }
+    }
+    // the first channel that publishes an item keeps it, so deduplicate by priority, highest
+    // first, and else in the order of the feed urls instead of the order the feeds are listed or
+    // fetched in; this makes the channel that keeps an item shared by several channels
+    // reproducible
+    updated.sort_by_cached_key(|index| {
+        let url = &report.feeds[*index].url;
+        (Reverse(feed_map[url].priority()), url.clone())
+    });
+    let mut write_queue = (cli.write_threads > 0).then(|| {
+        let options = settings.write_options.clone();
+        writer::WriteQueue::start(cli.write_threads, move |feed| feed.write(&options))
+    });
+    for index in updated {
+        let feed_report = &mut report.feeds[index];
+        let current_feed = feed_map.get_mut(&feed_report.url).unwrap();
+        if let Err(e) = process_feed(
+            cli,
+            settings,
+            current_feed,
+            known_feeds,
+            signatures,
+            feed_report,
+            write_queue.as_mut(),
+        ) {
+            error!("{}", e);
+            feed_report.status = FeedStatus::Failed;
+            feed_report.error = Some(e);
+        }
+    }
+    // the report, manifest and state below are only written once all feeds are
+    if let Some(write_queue) = write_queue {
+        for (url, filename, result) in write_queue.finish() {
+            let feed_report = report
+                .feeds
+                .iter_mut()
+                .find(|feed_report| feed_report.url == url)
+                .unwrap();
+            if let Err(e) = record_write(cli, &url, &filename, result, feed_report) {
+                error!("{}", e);
+                feed_report.status = FeedStatus::Failed;
+                feed_report.error = Some(e);
+            }
+        }
+    }
+    let backoff = match cli.feed_failure_backoff {
+        0 => None,
+        seconds => Some(Duration::from_secs(seconds)),
+    };
+    for feed_report in &report.feeds {
+        let current_feed = feed_map.get_mut(&feed_report.url).unwrap();
+        match feed_report.status {
+            FeedStatus::Disabled | FeedStatus::Gone => {}
+            FeedStatus::Failed => {
+                if current_feed.record_failure(cli.max_feed_failures, backoff, now) {
+                    error!(
+                        "Disabling feed {} after {} consecutive failures",
+                        feed_report.url, cli.max_feed_failures
+                    );
+                }
+            }
+            _ => current_feed.record_success(),
+        }
+    }
+    if let Some(manifest) = &cli.manifest {
+        let filename = Path::new(&cli.td[0]).join(manifest);
+        let filename = filename.to_str().unwrap();
+        let items: HashMap<String, usize> = report
+            .feeds
+            .iter()
+            .filter(|feed_report| feed_report.status == FeedStatus::Updated)
+            .map(|feed_report| {
+                let count = feed_map[&feed_report.url].item_count();
+                (feed_report.url.clone(), count)
+            })
+            .collect();
+        let mut feeds_manifest = report::Manifest::read(filename).unwrap_or_default();
+        feeds_manifest.update(&report, &items);
+        if let Err(e) = feeds_manifest.write(filename) {
+            error!("Could not write manifest {}: {}", manifest, e);
+        }
+    }
+    if let Some(firehose) = &cli.firehose {
+        let url = format!("{}{}", cli.up, firehose);
+        let filename = Path::new(&cli.td[0]).join(firehose);
+        let write_result = rss::Feed::firehose(
+            &url,
+            filename.to_str().unwrap(),
+            known_feeds,
+            cli.firehose_max_items,
+            &settings.dedup_options,
+        )
+        .and_then(|feed| feed.write(&settings.write_options));
+        if let Err(e) = write_result {
+            error!("Could not write merged feed {}: {}", url, e);
+        }
+    }
+    if let Some(error_feed) = &cli.error_feed {
+        let url = format!("{}{}", cli.up, error_feed);
+        let filename = Path::new(&cli.td[0]).join(error_feed);
+        let failures: Vec<(&str, &str)> = report
+            .feeds
+            .iter()
+            .filter(|feed_report| feed_report.status == FeedStatus::Failed)
+            .map(|feed_report| {
+                let error = feed_report.error.as_deref().unwrap_or_default();
+                (feed_report.url.as_str(), error)
+            })
+            .collect();
+        // the feed without items is the signal that all is well, so it is always written
+        let write_options = rss::WriteOptions {
+            on_empty: rss::OnEmpty::Write,
+            ..settings.write_options.clone()
+        };
+        let write_result = rss::Feed::error_feed(
+            &url,
+            filename.to_str().unwrap(),
+            &failures,
+            settings.dedup_options.compact,
+        )
+        .and_then(|feed| feed.write(&write_options));
+        if let Err(e) = write_result {
+            error!("Could not write error feed {}: {}", url, e);
+        }
+    }
+    report.duration_ms = (Local::now() - started).num_milliseconds().max(0) as u64;
+    info!(
+        "Processed {} feeds in {} ms: {} updated, {} unchanged, {} not updated, {} failed, {} disabled, {} gone",
+        report.feeds.len(),
+        report.duration_ms,
+        report.count(FeedStatus::Updated),
+        report.count(FeedStatus::Unchanged),
+        report.count(FeedStatus::NotUpdated),
+        report.count(FeedStatus::Failed),
+        report.count(FeedStatus::Disabled),
+        report.count(FeedStatus::Gone)
+    );
+    if let Some(state) = &cli.state {
+        if let Err(e) = rss::save_existing_items(state, known_feeds, signatures) {
+            error!("Could not save state: {}", e);
+        }
+    }
+    if let Some(filename) = &cli.dedup_report_json {
+        report.config = serde_json::to_value(cli).unwrap_or_default();
+        if let Err(e) = report.write(filename) {
+            error!("Could not write report: {}", e);
+        }
+    }
+    report
+}
+
+// deduplicate and write a feed that was updated upstream
+fn process_feed(
+    cli: &Cli,
+    settings: &Settings,
+    current_feed: &mut rss::Feed,
+    known_feeds: &mut rss::ExistingItemsMap,
+    signatures: &mut rss::SignatureMap,
+    feed_report: &mut FeedReport,
+    write_queue: Option<&mut writer::WriteQueue>,
+) -> Result<(), String> {
+    let url = current_feed.url().to_string();
+    let _span = info_span!("dedup_feed", url = %url).entered();
+    let stats = current_feed
+        .remove_duplicates(known_feeds, signatures, &settings.dedup_options)
+        .map_err(|e| {
+            if let Some(directory) = &cli.dead_letter_dir {
+                match utilities::write_dead_letter(
+                    directory,
+                    &url,
+                    current_feed.body(),
+                    cli.dead_letter_max_files,
+                ) {
+                    Ok(filename) => info!("Wrote body of feed {} to {}", url, filename),
+                    Err(e) => error!("{}", e),
+                }
+            }
+            format!("Error de-duplicating feed {}: {}", url, e)
+        })?;
+    feed_report.dedup = Some(stats);
+    // the status of a queued feed is recorded when it is written
+    if let Some(write_queue) = write_queue {
+        write_queue.push(current_feed.output());
+        return Ok(());
+    }
+    let result = current_feed.write(&settings.write_options);
+    record_write(cli, &url, current_feed.filename(), result, feed_report)
+}
+
+// record in feed_report whether writing the feed to filename changed it and run the update hook
+// if it did
+fn record_write(
+    cli: &Cli,
+    url: &str,
+    filename: &str,
+    result: Result<bool, String>,
+    feed_report: &mut FeedReport,
+) -> Result<(), String> {
+    let changed = result.map_err(|e| {
+        format!(
+            "Could not write updated feed {} to file {}: {}",
+            url, filename, e
+        )
+    })?;
+    if !changed {
+        info!("RSS feed {} in file {} is unchanged", url, filename);
+        feed_report.status = FeedStatus::Unchanged;
+        return Ok(());
+    }
+    info!("Updated RSS feed {} in file {}", url, filename);
+    if let Some(command) = &cli.on_update {
+        utilities::run_update_hook(command, filename, url);
+    }
+    feed_report.status = FeedStatus::Updated;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use super::testserver::{response, TestServer};
+    use super::utilities::setup_test_logger;
+    use super::*;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id};
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+    use tracing_subscriber::registry::LookupSpan;
+    use xmltree::{Element, XMLNode};
+
+    #[test]
+    fn test_log_filter() {
+        let cli = Cli::parse_from(["rssfeed"]);
+        assert_eq!(log_filter(&cli), LevelFilter::Error);
+        let cli = Cli::parse_from(["rssfeed", "--log-level", "debug"]);
+        assert_eq!(log_filter(&cli), LevelFilter::Debug);
+        let cli = Cli::parse_from(["rssfeed", "--quiet"]);
+        assert_eq!(log_filter(&cli), LevelFilter::Error);
+        assert!(Cli::try_parse_from(["rssfeed", "--quiet", "--log-level", "info"]).is_err());
+    }
+
+    #[test]
+    fn test_wait_time() {
+        assert_eq!(wait_time(&Cli::parse_from(["rssfeed"])), 60);
+        assert_eq!(wait_time(&Cli::parse_from(["rssfeed", "--wt", "1"])), 30);
+        assert_eq!(wait_time(&Cli::parse_from(["rssfeed", "--wt", "0"])), 30);
+        let cli = Cli::parse_from(["rssfeed", "--wt", "5", "--min-interval", "10"]);
+        assert_eq!(wait_time(&cli), 10);
+        let cli = Cli::parse_from(["rssfeed", "--wt", "1", "--min-interval", "0"]);
+        assert_eq!(wait_time(&cli), 1);
+    }
+
+    #[test]
+    fn test_keep_backup() {
+        assert_eq!(Cli::parse_from(["rssfeed"]).keep_backup, 0);
+        assert_eq!(Cli::parse_from(["rssfeed", "--keep-backup"]).keep_backup, 1);
+        assert_eq!(
+            Cli::parse_from(["rssfeed", "--keep-backup", "3"]).keep_backup,
+            3
+        );
+    }
+
+    #[test]
+    fn test_parse_channel_identity() {
+        assert_eq!(
+            parse_channel_identity("https://www.faz.net/rss?view=1=faz-politik"),
+            Ok((
+                "https://www.faz.net/rss?view=1".to_string(),
+                "faz-politik".to_string()
+            ))
+        );
+        assert!(parse_channel_identity("https://www.faz.net/rss").is_err());
+        assert!(parse_channel_identity("https://www.faz.net/rss=").is_err());
+    }
+
+    #[test]
+    fn test_parse_stage_timeout() {
+        let cli = Cli::parse_from([
+            "rssdeduper",
+            "--feed-timeout-per-stage",
+            "fetch=30",
+            "--feed-timeout-per-stage",
+            "Parse=0.5",
+        ]);
+        assert_eq!(
+            stage_timeout(&cli, Stage::Fetch),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            stage_timeout(&cli, Stage::Parse),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(stage_timeout(&cli, Stage::Write), None);
+        assert!(parse_stage_timeout("dedup=1").is_err());
+        assert!(parse_stage_timeout("write=0").is_err());
+        assert!(parse_stage_timeout("write").is_err());
+    }
+
+    #[test]
+    fn test_parse_id_path_segment() {
+        let cli = Cli::parse_from(["rssdeduper", "--id-path-segment", "WWW.Example.com=-2"]);
+        let (host, index) = &cli.id_path_segment[0];
+        assert!(host.matches("www.example.com"));
+        assert_eq!(*index, -2);
+        assert!(parse_id_path_segment("www.example.com").is_err());
+        assert!(parse_id_path_segment("www.example.com=last").is_err());
+    }
+
+    #[test]
+    fn test_parse_segment_pattern() {
+        let cli = Cli::parse_from(["rssdeduper", "--ignore-path-segment", "[0-9a-f]{12}"]);
+        let settings = Settings::new(&cli).unwrap();
+        let pattern = &settings.dedup_options.id_options.ignored_segments[0];
+        assert!(pattern.is_match("3f9a1c2b7d4e"));
+        assert!(!pattern.is_match("article-3f9a1c2b7d4e"));
+        assert!(Cli::try_parse_from(["rssdeduper", "--ignore-path-segment", "("]).is_err());
+    }
+
+    #[test]
+    fn test_dedup_report_json() {
+        setup_test_logger();
+        let server = TestServer::start(vec![response(
+            "200 OK",
+            &[],
+            include_str!("../testdata/channel2.rss"),
+        )]);
+        fs::write("testdata/report_urls.txt", format!("{}\n", server.url)).unwrap();
+        let cli = Cli::parse_from([
+            "rssfeed",
+            "--urls",
+            "testdata/report_urls.txt",
+            "--ff",
+            "testdata/report_feeds.json",
+            "--td",
+            "testdata/",
+            "--ma",
+            "0",
+            "--admin-token",
+            "secret",
+            "--dedup-report-json",
+            "testdata/report_iteration.json",
+        ]);
+        let settings = Settings::new(&cli).unwrap();
+        let mut feed_map = HashMap::new();
+        let mut known_feeds = HashMap::new();
+        process_feeds(
+            &cli,
+            &settings,
+            &mut feed_map,
+            &mut known_feeds,
+            &mut HashMap::new(),
+        );
+
+        let json = fs::read_to_string("testdata/report_iteration.json").unwrap();
+        let report: IterationReport = serde_json::from_str(&json).unwrap();
+        let feed_filename = format!("testdata/{}", report.feeds[0].filename);
+        for filename in [
+            "testdata/report_urls.txt",
+            "testdata/report_feeds.json",
+            "testdata/report_iteration.json",
+            &feed_filename,
+        ] {
+            let _ = fs::remove_file(filename);
+        }
+        assert_eq!(report.feeds.len(), 1);
+        assert_eq!(report.count(FeedStatus::Updated), 1);
+        assert_eq!(report.feeds[0].url, server.url);
+        assert_eq!(
+            report.feeds[0].dedup,
+            Some(report::DedupStats {
+                items: 2,
+                removed: 0
+            })
+        );
+        assert_eq!(report.config["ma"], 0);
+        assert!(!json.contains("secret"));
+    }
+
+    // name, name of its parent and url field of a span
+    type RecordedSpan = (String, Option<String>, Option<String>);
+
+    // records each new span
+    #[derive(Clone, Default)]
+    struct SpanRecorder(Arc<Mutex<Vec<RecordedSpan>>>);
+
+    struct UrlVisitor(Option<String>);
+
+    impl Visit for UrlVisitor {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            if field.name() == "url" {
+                self.0 = Some(value.to_string());
+            }
+        }
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "url" {
+                self.0 = Some(format!("{:?}", value));
+            }
+        }
+    }
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for SpanRecorder {
+        fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            let parent = ctx
+                .span(id)
+                .and_then(|span| span.parent())
+                .map(|parent| parent.name().to_string());
+            let mut url = UrlVisitor(None);
+            attrs.record(&mut url);
+            self.0
+                .lock()
+                .unwrap()
+                .push((attrs.metadata().name().to_string(), parent, url.0));
+        }
+    }
+
+    #[test]
+    fn test_tracing_spans() {
+        setup_test_logger();
+        let server1 = TestServer::start(vec![response(
+            "200 OK",
+            &[],
+            include_str!("../testdata/channel1.rss"),
+        )]);
+        let server2 = TestServer::start(vec![response(
+            "200 OK",
+            &[],
+            include_str!("../testdata/channel2.rss"),
+        )]);
+        fs::write(
+            "testdata/spans_urls.txt",
+            format!("{}\n{}\n", server1.url, server2.url),
+        )
+        .unwrap();
+        let _ = fs::remove_file("testdata/spans_feeds.json");
+        let cli = Cli::parse_from([
+            "rssfeed",
+            "--urls",
+            "testdata/spans_urls.txt",
+            "--ff",
+            "testdata/spans_feeds.json",
+            "--td",
+            "testdata/",
+            "--ma",
+            "0",
+        ]);
+        let settings = Settings::new(&cli).unwrap();
+        let recorder = SpanRecorder::default();
+        let subscriber = tracing_subscriber::registry().with(recorder.clone());
+        let report = tracing::subscriber::with_default(subscriber, || {
+            process_feeds(
+                &cli,
+                &settings,
+                &mut HashMap::new(),
+                &mut HashMap::new(),
+                &mut HashMap::new(),
+            )
+        });
+        for feed in &report.feeds {
+            let _ = fs::remove_file(format!("testdata/{}", feed.filename));
+        }
+        let _ = fs::remove_file("testdata/spans_urls.txt");
+        let _ = fs::remove_file("testdata/spans_feeds.json");
+
+        let spans: Vec<_> = recorder
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(name, _, _)| {
+                ["iteration", "read_feed", "dedup_feed"].contains(&name.as_str())
+            })
+            .cloned()
+            .collect();
+        let span = |name: &str, url: Option<&String>| {
+            (
+                name.to_string(),
+                Some("iteration".to_string()),
+                url.cloned(),
+            )
+        };
+        // the feeds are deduplicated in the order of their urls
+        let mut urls = [&server1.url, &server2.url];
+        urls.sort();
+        assert_eq!(
+            spans,
+            vec![
+                ("iteration".to_string(), None, None),
+                span("read_feed", Some(&server1.url)),
+                span("read_feed", Some(&server2.url)),
+                span("dedup_feed", Some(urls[0])),
+                span("dedup_feed", Some(urls[1])),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_manifest() {
+        setup_test_logger();
+        let feed1 = include_str!("../testdata/channel1.rss");
+        let feed2 = include_str!("../testdata/channel2.rss");
+        let server1 = TestServer::start(vec![
+            response("200 OK", &[], feed1),
+            response("304 Not Modified", &[], ""),
+        ]);
+        let server2 = TestServer::start(vec![
+            response("200 OK", &[], feed2),
+            response(
+                "200 OK",
+                &[],
+                &feed2.replace("Neueste Meldungen", "Meldungen"),
+            ),
+        ]);
+        let directory = std::env::temp_dir().join("rssdeduper-manifest");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(
+            "testdata/manifest_urls.txt",
+            format!("{}\n{}\n", server1.url, server2.url),
+        )
+        .unwrap();
+        let _ = fs::remove_file("testdata/manifest_feeds.json");
+        let cli = Cli::parse_from([
+            "rssfeed",
+            "--urls",
+            "testdata/manifest_urls.txt",
+            "--ff",
+            "testdata/manifest_feeds.json",
+            "--td",
+            directory.to_str().unwrap(),
+            "--ma",
+            "0",
+            "--manifest",
+            "manifest.json",
+        ]);
+        let settings = Settings::new(&cli).unwrap();
+        let mut feed_map = HashMap::new();
+        let mut known_feeds = HashMap::new();
+        let manifest_file = directory.join("manifest.json");
+        let read_manifest = || report::Manifest::read(manifest_file.to_str().unwrap()).unwrap();
+
+        let report1 = process_feeds(
+            &cli,
+            &settings,
+            &mut feed_map,
+            &mut known_feeds,
+            &mut HashMap::new(),
+        );
+        let manifest1 = read_manifest();
+        let report2 = process_feeds(
+            &cli,
+            &settings,
+            &mut feed_map,
+            &mut known_feeds,
+            &mut HashMap::new(),
+        );
+        let manifest2 = read_manifest();
+        let _ = fs::remove_dir_all(&directory);
+        let _ = fs::remove_file("testdata/manifest_urls.txt");
+        let _ = fs::remove_file("testdata/manifest_feeds.json");
+
+        let filename1 = &report1.feeds[0].filename;
+        let filename2 = &report1.feeds[1].filename;
+        assert_eq!(manifest1.0.len(), 2);
+        assert_eq!(manifest1.0[filename1].updated, report1.started);
+        assert_eq!(manifest1.0[filename2].updated, report1.started);
+        // the channels share one of their 4 and 2 items
+        assert_eq!(
+            manifest1.0[filename1].items + manifest1.0[filename2].items,
+            5
+        );
+
+        assert_eq!(report2.feeds[0].status, FeedStatus::NotUpdated);
+        assert_eq!(report2.feeds[1].status, FeedStatus::Updated);
+        assert_eq!(manifest2.0[filename1], manifest1.0[filename1]);
+        assert_eq!(manifest2.0[filename2].updated, report2.started);
+    }
+
+    #[test]
+    fn test_dedup_independent_of_feed_order() {
+        setup_test_logger();
+        let feed1 = include_str!("../testdata/channel1.rss");
+        let feed2 = include_str!("../testdata/channel2.rss");
+        let server1 = TestServer::start(vec![response("200 OK", &[], feed1); 2]);
+        let server2 = TestServer::start(vec![response("200 OK", &[], feed2); 2]);
+        let mut results = Vec::new();
+        for urls in [[&server1.url, &server2.url], [&server2.url, &server1.url]] {
+            fs::write(
+                "testdata/order_urls.txt",
+                format!("{}\n{}\n", urls[0], urls[1]),
+            )
+            .unwrap();
+            let _ = fs::remove_file("testdata/order_feeds.json");
+            let cli = Cli::parse_from([
+                "rssfeed",
+                "--urls",
+                "testdata/order_urls.txt",
+                "--ff",
+                "testdata/order_feeds.json",
+                "--td",
+                "testdata/",
+                "--ma",
+                "0",
+            ]);
+            let settings = Settings::new(&cli).unwrap();
+            let report = process_feeds(
+                &cli,
+                &settings,
+                &mut HashMap::new(),
+                &mut HashMap::new(),
+                &mut HashMap::new(),
+            );
+            let mut contents = HashMap::new();
+            for feed in &report.feeds {
+                assert_eq!(feed.status, FeedStatus::Updated);
+                let filename = format!("testdata/{}", feed.filename);
+                contents.insert(feed.url.clone(), fs::read_to_string(&filename).unwrap());
+                let _ = fs::remove_file(filename);
+            }
+            results.push(contents);
+        }
+        let _ = fs::remove_file("testdata/order_urls.txt");
+        let _ = fs::remove_file("testdata/order_feeds.json");
+        assert_eq!(results[0], results[1]);
+    }
+
+    #[test]
+    fn test_max_feed_failures() {
+        setup_test_logger();
+        // answers that are not HTTP make every read fail
+        let server = TestServer::start(vec!["garbage".to_string(); 4]);
+        fs::write("testdata/failures_urls.txt", format!("{}\n", server.url)).unwrap();
+        let cli = Cli::parse_from([
+            "rssfeed",
+            "--urls",
+            "testdata/failures_urls.txt",
+            "--ff",
+            "testdata/failures_feeds.json",
+            "--td",
+            "testdata/",
+            "--max-feed-failures",
+            "2",
+            "--feed-failure-backoff",
+            "1",
+        ]);
+        let settings = Settings::new(&cli).unwrap();
+        let mut feed_map = HashMap::new();
+        let mut known_feeds = HashMap::new();
+        let mut iterate = || {
+            process_feeds(
+                &cli,
+                &settings,
+                &mut feed_map,
+                &mut known_feeds,
+                &mut HashMap::new(),
+            )
+            .feeds[0]
+                .status
+        };
+        assert_eq!(iterate(), FeedStatus::Failed);
+        assert_eq!(iterate(), FeedStatus::Failed);
+        assert_eq!(iterate(), FeedStatus::Disabled);
+        assert_eq!(server.requests().len(), 2);
+        std::thread::sleep(Duration::from_millis(1100));
+        // after the backoff the feed is read again and a single failure disables it again
+        assert_eq!(iterate(), FeedStatus::Failed);
+        assert_eq!(iterate(), FeedStatus::Disabled);
+        assert_eq!(server.requests().len(), 3);
+        let _ = fs::remove_file("testdata/failures_urls.txt");
+        let _ = fs::remove_file("testdata/failures_feeds.json");
+    }
+
+    #[test]
+    fn test_error_feed() {
+        setup_test_logger();
+        // the first read fails because the answer is not HTTP, the second one succeeds
+        let server = TestServer::start(vec![
+            "garbage".to_string(),
+            response("200 OK", &[], include_str!("../testdata/channel1.rss")),
+        ]);
+        fs::write("testdata/error_feed_urls.txt", format!("{}\n", server.url)).unwrap();
+        let cli = Cli::parse_from([
+            "rssfeed",
+            "--urls",
+            "testdata/error_feed_urls.txt",
+            "--ff",
+            "testdata/error_feed_feeds.json",
+            "--td",
+            "testdata/",
+            "--error-feed",
+            "error_feed_errors.rss",
+        ]);
+        let settings = Settings::new(&cli).unwrap();
+        let mut feed_map = HashMap::new();
+        let mut known_feeds = HashMap::new();
+        let report = process_feeds(
+            &cli,
+            &settings,
+            &mut feed_map,
+            &mut known_feeds,
+            &mut HashMap::new(),
+        );
+        let error = report.feeds[0].error.clone().unwrap();
+        let errors = fs::read_to_string("testdata/error_feed_errors.rss").unwrap();
+        let rssroot = Element::parse(errors.as_bytes()).unwrap();
+        let channel = rssroot.get_child("channel").unwrap();
+        let items: Vec<&Element> = channel
+            .children
+            .iter()
+            .filter_map(XMLNode::as_element)
+            .filter(|child| child.name == "item")
+            .collect();
+        assert_eq!(items.len(), 1);
+        let text = |name| items[0].get_child(name).unwrap().get_text().unwrap();
+        assert_eq!(text("title"), server.url);
+        assert_eq!(text("description"), error);
+        assert!(chrono::DateTime::parse_from_rfc2822(&text("pubDate")).is_ok());
+
+        // all is well, the error feed is still written but without items
+        let report = process_feeds(
+            &cli,
+            &settings,
+            &mut feed_map,
+            &mut known_feeds,
+            &mut HashMap::new(),
+        );
+        assert_eq!(report.feeds[0].status, FeedStatus::Updated);
+        let _ = fs::remove_file(Path::new("testdata").join(&report.feeds[0].filename));
+        let errors = fs::read_to_string("testdata/error_feed_errors.rss").unwrap();
+        assert!(Element::parse(errors.as_bytes()).is_ok());
+        assert!(errors.contains("<channel>"));
+        assert!(!errors.contains("<item>"));
+        for filename in [
+            "testdata/error_feed_urls.txt",
+            "testdata/error_feed_feeds.json",
+            "testdata/error_feed_errors.rss",
+        ] {
+            let _ = fs::remove_file(filename);
+        }
+    }
+
+    #[test]
+    fn test_unreadable_source_keeps_previous_feeds() {
+        setup_test_logger();
+        let (server, paths) = feed_routes(Duration::ZERO);
+        let directory = std::env::temp_dir().join("rssdeduper-unreadable-source");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let path = |name: &str| directory.join(name).to_str().unwrap().to_string();
+        fs::write(
+            path("source.opml"),
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<opml version="1.0">
+    <head><title>Feeds</title></head>
+    <body>
+        <outline type="rss" text="One" title="One" xmlUrl="{0}{1}"/>
+        <outline type="rss" text="Two" title="Two" xmlUrl="{0}{2}"/>
+    </body>
+</opml>
+"#,
+                server.url, paths[0], paths[1]
+            ),
+        )
+        .unwrap();
+        let cli = Cli::parse_from([
+            "rssfeed",
+            "--so",
+            &path("source.opml"),
+            "--to",
+            &path("target.opml"),
+            "--ff",
+            &path("feeds.json"),
+            "--td",
+            directory.to_str().unwrap(),
+        ]);
+        let settings = Settings::new(&cli).unwrap();
+        let mut feed_map = HashMap::new();
+        let mut known_feeds = HashMap::new();
+        let report = process_feeds(
+            &cli,
+            &settings,
+            &mut feed_map,
+            &mut known_feeds,
+            &mut HashMap::new(),
+        );
+        assert_eq!(report.count(FeedStatus::Updated), 2);
+
+        // the newsreader is rewriting the OPML file, then it is gone for a moment
+        std::thread::sleep(Duration::from_millis(10));
+        fs::write(path("source.opml"), "<?xml version=\"1.0\"?><opml><bo").unwrap();
+        let report = process_feeds(
+            &cli,
+            &settings,
+            &mut feed_map,
+            &mut known_feeds,
+            &mut HashMap::new(),
+        );
+        assert_eq!(report.feeds.len(), 2);
+        assert_eq!(report.count(FeedStatus::Failed), 0);
+        fs::remove_file(path("source.opml")).unwrap();
+        let report = process_feeds(
+            &cli,
+            &settings,
+            &mut feed_map,
+            &mut known_feeds,
+            &mut HashMap::new(),
+        );
+        assert_eq!(report.feeds.len(), 2);
+        assert_eq!(server.requests().len(), 6);
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_run_once_exit_codes() {
+        setup_test_logger();
+        let (server, paths) = feed_routes(Duration::ZERO);
+        let directory = std::env::temp_dir().join("rssdeduper-once");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let path = |name: &str| directory.join(name).to_str().unwrap().to_string();
+        let run = |urls: &[&str]| {
+            let urls: Vec<String> = urls
+                .iter()
+                .map(|path| format!("{}{}", server.url, path))
+                .collect();
+            fs::write(path("urls.txt"), urls.join("\n")).unwrap();
+            let _ = fs::remove_file(path("feeds.json"));
+            let cli = Cli::parse_from([
+                "rssfeed",
+                "--once",
+                "--urls",
+                &path("urls.txt"),
+                "--ff",
+                &path("feeds.json"),
+                "--td",
+                directory.to_str().unwrap(),
+            ]);
+            run_once(&cli)
+        };
+        assert_eq!(run(&[&paths[0], &paths[1]]), EXIT_SUCCESS);
+        assert_eq!(run(&[&paths[0], "/missing.rss"]), EXIT_FEEDS_FAILED);
+        let cli = Cli::parse_from([
+            "rssfeed",
+            "--once",
+            "--urls",
+            &path("missing.txt"),
+            "--ff",
+            &path("feeds.json"),
+            "--td",
+            directory.to_str().unwrap(),
+        ]);
+        assert_eq!(run_once(&cli), EXIT_STARTUP_ERROR);
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_run_once_warm_from_served() {
+        setup_test_logger();
+        let (server, paths) = feed_routes(Duration::ZERO);
+        let directory = std::env::temp_dir().join("rssdeduper-once-warm");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let path = |name: &str| directory.join(name).to_str().unwrap().to_string();
+        let url = |path: &str| format!("{}{}", server.url, path);
+        let run = |warm: bool| {
+            let mut args = vec![
+                "rssfeed".to_string(),
+                "--once".to_string(),
+                "--urls".to_string(),
+                path("urls.txt"),
+                "--ff".to_string(),
+                path("feeds.json"),
+                "--td".to_string(),
+                directory.to_str().unwrap().to_string(),
+                "--ma".to_string(),
+                "0".to_string(),
+            ];
+            if warm {
+                args.push("--warm-from-served".to_string());
+            }
+            assert_eq!(run_once(&Cli::parse_from(args)), EXIT_SUCCESS);
+        };
+        let feeds = || -> HashMap<String, String> {
+            opml::read_feeds(&path("feeds.json"), opml::FeedsFileFormat::Json)
+                .unwrap()
+                .into_iter()
+                .collect()
+        };
+        let items = |filename: &str| {
+            fs::read_to_string(directory.join(filename))
+                .unwrap()
+                .matches("<item>")
+                .count()
+        };
+        // the first run serves the item about the churches in the second channel
+        fs::write(path("urls.txt"), url(&paths[1])).unwrap();
+        run(false);
+        let served = feeds()[&url(&paths[1])].clone();
+        assert_eq!(items(&served), 2);
+        // the second run deduplicates the first channel first and only knows about the
+        // churches from the served file of the second channel
+        let feeds_file =
+            serde_json::json!([[url(&paths[0]), "channel1.rss"], [url(&paths[1]), served],]);
+        fs::write(path("feeds.json"), feeds_file.to_string()).unwrap();
+        run(true);
+        assert_eq!(items("channel1.rss"), 3);
+        assert_eq!(items(&served), 2);
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_reset_state() {
+        setup_test_logger();
+        let state = "testdata/reset_state.json";
+        let mut existing_items = HashMap::new();
+        existing_items.insert(
+            ("19314690".to_string(), "www.faz.net".to_string()),
+            (
+                "https://www.faz.net".to_string(),
+                Element::new("item"),
+                std::time::SystemTime::now(),
+            ),
+        );
+        rss::save_existing_items(state, &existing_items, &HashMap::new()).unwrap();
+        let cli = |reset: bool| {
+            let mut args = vec!["rssfeed", "--state", state];
+            if reset {
+                args.push("--reset-state");
+            }
+            Cli::parse_from(args)
+        };
+        assert_eq!(load_state(&cli(false)).0.len(), 1);
+        assert!(load_state(&cli(true)).0.is_empty());
+        assert!(!Path::new(state).exists());
+        assert!(load_state(&cli(true)).0.is_empty());
+    }
+
+    #[test]
+    fn test_dead_letter() {
+        setup_test_logger();
+        const BROKEN1: &str = "<rss version=\"2.0\"><channel><title>Broken</title></rss>";
+        const BROKEN2: &str = "<rss version=\"2.0\"><channel><item></channel></rss>";
+        let server = TestServer::start(vec![
+            response("200 OK", &[], BROKEN1),
+            response("200 OK", &[], &format!("\u{feff}{}", BROKEN2)),
+        ]);
+        let directory = std::env::temp_dir().join("rssdeduper-dead-letters");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        // other files in the directory are not pruned
+        fs::write(directory.join("README.txt"), "dead letters of rssdeduper").unwrap();
+        fs::write("testdata/dead_letter_urls.txt", format!("{}\n", server.url)).unwrap();
+        let cli = Cli::parse_from([
+            "rssfeed",
+            "--urls",
+            "testdata/dead_letter_urls.txt",
+            "--ff",
+            "testdata/dead_letter_feeds.json",
+            "--td",
+            "testdata/",
+            "--dead-letter-dir",
+            directory.to_str().unwrap(),
+            "--dead-letter-max-files",
+            "1",
+        ]);
+        let settings = Settings::new(&cli).unwrap();
+        let mut feed_map = HashMap::new();
+        let mut known_feeds = HashMap::new();
+        let dead_letters = || -> Vec<(String, String)> {
+            fs::read_dir(&directory)
+                .unwrap()
+                .map(|entry| {
+                    let path = entry.unwrap().path();
+                    let name = path.file_name().unwrap().to_str().unwrap().to_string();
+                    (name, fs::read_to_string(&path).unwrap())
+                })
+                .filter(|(name, _)| name != "README.txt")
+                .collect()
+        };
+        // the byte order mark the feed is served with is kept in its dead letter
+        for body in [BROKEN1.to_string(), format!("\u{feff}{}", BROKEN2)] {
+            let report = process_feeds(
+                &cli,
+                &settings,
+                &mut feed_map,
+                &mut known_feeds,
+                &mut HashMap::new(),
+            );
+            assert_eq!(report.feeds[0].status, FeedStatus::Failed);
+            // the older dead letter is removed
+            let dead_letters = dead_letters();
+            assert_eq!(dead_letters.len(), 1);
+            assert!(dead_letters[0].0.contains("Z_http_127_0_0_1_"));
+            assert_eq!(dead_letters[0].1, body);
+        }
+        assert!(directory.join("README.txt").exists());
+        let _ = fs::remove_dir_all(&directory);
+        let _ = fs::remove_file("testdata/dead_letter_urls.txt");
+        let _ = fs::remove_file("testdata/dead_letter_feeds.json");
+    }
+
+    // serve the feeds of the deduplication fixtures, each under /<n>.rss
+    fn feed_routes(delay: Duration) -> (TestServer, Vec<String>) {
+        let feeds = [
+            include_str!("../testdata/channel1.rss"),
+            include_str!("../testdata/channel2.rss"),
+            include_str!("../testdata/sourcechannel1.rss"),
+            include_str!("../testdata/sourcechannel2.rss"),
+            include_str!("../testdata/schlagzeilen.rss"),
+            include_str!("../testdata/news.rss"),
+        ];
+        let routes: Vec<(String, String)> = feeds
+            .iter()
+            .enumerate()
+            .map(|(index, feed)| (format!("/{}.rss", index), response("200 OK", &[], feed)))
+            .collect();
+        let paths = routes.iter().map(|(path, _)| path.clone()).collect();
+        (TestServer::start_routes(routes, delay), paths)
+    }
+
+    #[test]
+    fn test_request_headers_from_opml() {
+        setup_test_logger();
+        let (server, paths) = feed_routes(Duration::ZERO);
+        let directory = std::env::temp_dir().join("rssdeduper-request-headers");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let source = directory.join("source.opml");
+        fs::write(
+            &source,
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<opml version="1.0">
+    <head><title>Feeds</title></head>
+    <body>
+        <outline text="News" title="News">
+            <outline type="rss" text="Browser" title="Browser" xmlUrl="{0}{1}" userAgent="Mozilla/5.0 (X11; Linux x86_64)" acceptHeader="application/rss+xml"/>
+            <outline type="rss" text="Default" title="Default" xmlUrl="{0}{2}"/>
+        </outline>
+    </body>
+</opml>
+"#,
+                server.url, paths[0], paths[1]
+            ),
+        )
+        .unwrap();
+        let path = |name: &str| directory.join(name).to_str().unwrap().to_string();
+        let cli = Cli::parse_from([
+            "rssfeed",
+            "--so",
+            &path("source.opml"),
+            "--to",
+            &path("target.opml"),
+            "--ff",
+            &path("feeds.json"),
+            "--td",
+            directory.to_str().unwrap(),
+            "--user-agent",
+            "rssdeduper-test",
+        ]);
+        let settings = Settings::new(&cli).unwrap();
+        process_feeds(
+            &cli,
+            &settings,
+            &mut HashMap::new(),
+            &mut HashMap::new(),
+            &mut HashMap::new(),
+        );
+        let request = |path: &str| {
+            server
+                .requests()
+                .into_iter()
+                .find(|request| request.starts_with(&format!("GET {} ", path)))
+                .unwrap()
+                .to_lowercase()
+        };
+        let browser = request(&paths[0]);
+        assert!(browser.contains("user-agent: mozilla/5.0 (x11; linux x86_64)\r\n"));
+        assert!(browser.contains("accept: application/rss+xml\r\n"));
+        assert!(!browser.contains("rssdeduper-test"));
+        let default = request(&paths[1]);
+        assert!(default.contains("user-agent: rssdeduper-test\r\n"));
+        assert!(!default.contains("application/rss+xml"));
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_feed_priority() {
+        setup_test_logger();
+        let (server, paths) = feed_routes(Duration::ZERO);
+        let directory = std::env::temp_dir().join("rssdeduper-priority");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(
+            directory.join("source.opml"),
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<opml version="1.0">
+    <head><title>Feeds</title></head>
+    <body>
+        <outline text="News" title="News">
+            <outline type="rss" text="Default" title="Default" xmlUrl="{0}{1}"/>
+            <outline type="rss" text="Low" title="Low" xmlUrl="{0}{2}" priority="-1"/>
+            <outline type="rss" text="Breaking" title="Breaking" xmlUrl="{0}{3}" priority="10"/>
+        </outline>
+    </body>
+</opml>
+"#,
+                server.url, paths[0], paths[1], paths[2]
+            ),
+        )
+        .unwrap();
+        let path = |name: &str| directory.join(name).to_str().unwrap().to_string();
+        let cli = Cli::parse_from([
+            "rssfeed",
+            "--so",
+            &path("source.opml"),
+            "--to",
+            &path("target.opml"),
+            "--ff",
+            &path("feeds.json"),
+            "--td",
+            directory.to_str().unwrap(),
+        ]);
+        let settings = Settings::new(&cli).unwrap();
+        let report = process_feeds(
+            &cli,
+            &settings,
+            &mut HashMap::new(),
+            &mut HashMap::new(),
+            &mut HashMap::new(),
+        );
+        assert_eq!(report.count(FeedStatus::Updated), 3);
+        let requested: Vec<String> = server
+            .requests()
+            .iter()
+            .map(|request| request.split_whitespace().nth(1).unwrap().to_string())
+            .collect();
+        assert_eq!(
+            requested,
+            vec![paths[2].clone(), paths[0].clone(), paths[1].clone()]
+        );
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_feed_priority_keeps_shared_items() {
+        setup_test_logger();
+        let (server, paths) = feed_routes(Duration::ZERO);
+        let directory = std::env::temp_dir().join("rssdeduper-priority-shared");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        // both feeds have the item about the churches, the second one has the higher priority
+        fs::write(
+            directory.join("source.opml"),
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<opml version="1.0">
+    <head><title>Feeds</title></head>
+    <body>
+        <outline text="News" title="News">
+            <outline type="rss" text="News" title="News" xmlUrl="{0}{1}"/>
+            <outline type="rss" text="Schlagzeilen" title="Schlagzeilen" xmlUrl="{0}{2}" priority="10"/>
+        </outline>
+    </body>
+</opml>
+"#,
+                server.url, paths[0], paths[1]
+            ),
+        )
+        .unwrap();
+        let path = |name: &str| directory.join(name).to_str().unwrap().to_string();
+        let cli = Cli::parse_from([
+            "rssfeed",
+            "--so",
+            &path("source.opml"),
+            "--to",
+            &path("target.opml"),
+            "--ff",
+            &path("feeds.json"),
+            "--td",
+            directory.to_str().unwrap(),
+            "--ma",
+            "0",
+        ]);
+        let settings = Settings::new(&cli).unwrap();
+        process_feeds(
+            &cli,
+            &settings,
+            &mut HashMap::new(),
+            &mut HashMap::new(),
+            &mut HashMap::new(),
+        );
+        let feeds: HashMap<String, String> = opml::read_feeds(&cli.ff, feeds_file_format(&cli))
+            .unwrap()
+            .into_iter()
+            .collect();
+        let items = |path: &str| {
+            let filename = &feeds[&format!("{}{}", server.url, path)];
+            fs::read_to_string(directory.join(filename))
+                .unwrap()
+                .matches("<item>")
+                .count()
+        };
+        assert_eq!(items(&paths[1]), 2);
+        assert_eq!(items(&paths[0]), 3);
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_missing_source_keeps_feeds() {
+        setup_test_logger();
+        let (server, paths) = feed_routes(Duration::ZERO);
+        let directory = std::env::temp_dir().join("rssdeduper-missing-source");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let path = |name: &str| directory.join(name).to_str().unwrap().to_string();
+        let feeds = serde_json::json!([[format!("{}{}", server.url, paths[0]), "channel1.rss"]]);
+        fs::write(path("feeds.json"), feeds.to_string()).unwrap();
+        // the source OPML was removed, e.g. while the newsreader rewrites it
+        let (source, target, feeds_file) =
+            (path("source.opml"), path("target.opml"), path("feeds.json"));
+        let cli_args = vec![
+            "rssfeed",
+            "--so",
+            &source,
+            "--to",
+            &target,
+            "--ff",
+            &feeds_file,
+            "--td",
+            directory.to_str().unwrap(),
+            "--ma",
+            "0",
+        ];
+        let cli = Cli::parse_from(&cli_args);
+        let settings = Settings::new(&cli).unwrap();
+        let report = process_feeds(
+            &cli,
+            &settings,
+            &mut HashMap::new(),
+            &mut HashMap::new(),
+            &mut HashMap::new(),
+        );
+        assert_eq!(report.count(FeedStatus::Updated), 1);
+        assert!(directory.join("channel1.rss").exists());
+
+        // with --on-source-error exit the iteration stops and the run exits
+        fs::remove_file(directory.join("channel1.rss")).unwrap();
+        let mut args = cli_args.clone();
+        args.extend(["--on-source-error", "exit"]);
+        let cli = Cli::parse_from(args);
+        let report = process_feeds(
+            &cli,
+            &settings,
+            &mut HashMap::new(),
+            &mut HashMap::new(),
+            &mut HashMap::new(),
+        );
+        assert_eq!(exit_code(&report), EXIT_STARTUP_ERROR);
+        assert!(report.source_error.unwrap().contains("source.opml"));
+        assert!(report.feeds.is_empty());
+        assert!(!directory.join("channel1.rss").exists());
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_feed_settings_from_feeds_file() {
+        setup_test_logger();
+        let (server, paths) = feed_routes(Duration::ZERO);
+        let directory = std::env::temp_dir().join("rssdeduper-feed-settings");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let path = |name: &str| directory.join(name).to_str().unwrap().to_string();
+        let urls: Vec<String> = paths[..2]
+            .iter()
+            .map(|path| format!("{}{}", server.url, path))
+            .collect();
+        fs::write(path("urls.txt"), urls.join("\n")).unwrap();
+        // written after the urls file, so it is not regenerated; the items of the test feeds
+        // are older than the default max age of 24 hours, the first feed keeps them
+        fs::write(
+            path("feeds.json"),
+            format!(
+                r#"[["{}", "old.rss", {{"max_age": 0, "priority": 5}}], ["{}", "default.rss"]]"#,
+                urls[0], urls[1]
+            ),
+        )
+        .unwrap();
+        let cli = Cli::parse_from([
+            "rssfeed",
+            "--urls",
+            &path("urls.txt"),
+            "--ff",
+            &path("feeds.json"),
+            "--td",
+            directory.to_str().unwrap(),
+        ]);
+        let settings = Settings::new(&cli).unwrap();
+        let mut feed_map = HashMap::new();
+        let report = process_feeds(
+            &cli,
+            &settings,
+            &mut feed_map,
+            &mut HashMap::new(),
+            &mut HashMap::new(),
+        );
+        assert_eq!(report.count(FeedStatus::Failed), 0);
+        let items = |name: &str| {
+            fs::read_to_string(path(name))
+                .unwrap()
+                .matches("<item>")
+                .count()
+        };
+        assert_eq!(items("old.rss"), 4);
+        assert_eq!(items("default.rss"), 0);
+        assert_eq!(feed_map[&urls[0]].priority(), 5);
+
+        // settings removed from the feeds file do not stay
+        fs::write(
+            path("feeds.json"),
+            format!(
+                r#"[["{}", "old.rss"], ["{}", "default.rss"]]"#,
+                urls[0], urls[1]
+            ),
+        )
+        .unwrap();
+        process_feeds(
+            &cli,
+            &settings,
+            &mut feed_map,
+            &mut HashMap::new(),
+            &mut HashMap::new(),
+        );
+        assert_eq!(feed_map[&urls[0]].priority(), 0);
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_fetch_threads() {
+        setup_test_logger();
+        let (server, paths) = feed_routes(Duration::ZERO);
+        let urls: Vec<String> = paths
+            .iter()
+            .map(|path| format!("{}{}", server.url, path))
+            .collect();
+        fs::write("testdata/threads_urls.txt", urls.join("\n")).unwrap();
+        let _ = fs::remove_file("testdata/threads_feeds.json");
+        let mut outputs = Vec::new();
+        for threads in ["1", "4"] {
+            let directory = std::env::temp_dir().join(format!("rssdeduper-threads-{}", threads));
+            let _ = fs::remove_dir_all(&directory);
+            fs::create_dir_all(&directory).unwrap();
+            let cli = Cli::parse_from([
+                "rssfeed",
+                "--urls",
+                "testdata/threads_urls.txt",
+                "--ff",
+                "testdata/threads_feeds.json",
+                "--td",
+                directory.to_str().unwrap(),
+                "--fetch-threads",
+                threads,
+            ]);
+            let settings = Settings::new(&cli).unwrap();
+            let report = process_feeds(
+                &cli,
+                &settings,
+                &mut HashMap::new(),
+                &mut HashMap::new(),
+                &mut HashMap::new(),
+            );
+            let files: Vec<String> = report
+                .feeds
+                .iter()
+                .map(|feed| fs::read_to_string(directory.join(&feed.filename)).unwrap())
+                .collect();
+            let statuses: Vec<_> = report
+                .feeds
+                .iter()
+                .map(|feed| (feed.url.clone(), feed.status, feed.dedup))
+                .collect();
+            outputs.push((statuses, files));
+            let _ = fs::remove_dir_all(&directory);
+        }
+        assert_eq!(outputs[0].0.len(), 6);
+        assert!(outputs[0]
+            .0
+            .iter()
+            .all(|(_, status, _)| *status == FeedStatus::Updated));
+        assert_eq!(outputs[0], outputs[1]);
+        assert_eq!(server.requests().len(), 12);
+        let _ = fs::remove_file("testdata/threads_urls.txt");
+        let _ = fs::remove_file("testdata/threads_feeds.json");
+    }
+
+    // the feeds written by background threads are the same as those written in the loop and
+    // all are written before process_feeds returns
+    #[test]
+    fn test_write_threads() {
+        setup_test_logger();
+        let (server, paths) = feed_routes(Duration::ZERO);
+        let urls: Vec<String> = paths
+            .iter()
+            .map(|path| format!("{}{}", server.url, path))
+            .collect();
+        fs::write("testdata/write_threads_urls.txt", urls.join("\n")).unwrap();
+        let _ = fs::remove_file("testdata/write_threads_feeds.json");
+        let mut outputs = Vec::new();
+        for threads in ["0", "3"] {
+            let directory =
+                std::env::temp_dir().join(format!("rssdeduper-write-threads-{}", threads));
+            let _ = fs::remove_dir_all(&directory);
+            fs::create_dir_all(&directory).unwrap();
+            let cli = Cli::parse_from([
+                "rssfeed",
+                "--urls",
+                "testdata/write_threads_urls.txt",
+                "--ff",
+                "testdata/write_threads_feeds.json",
+                "--td",
+                directory.to_str().unwrap(),
+                "--write-threads",
+                threads,
+            ]);
+            let settings = Settings::new(&cli).unwrap();
+            let mut feed_map = HashMap::new();
+            let mut known_feeds = HashMap::new();
+            let mut statuses = Vec::new();
+            let mut files = Vec::new();
+            // the second iteration writes the same content again
+            for _ in 0..2 {
+                let report = process_feeds(
+                    &cli,
+                    &settings,
+                    &mut feed_map,
+                    &mut known_feeds,
+                    &mut HashMap::new(),
+                );
+                statuses.extend(
+                    report
+                        .feeds
+                        .iter()
+                        .map(|feed| (feed.url.clone(), feed.status)),
+                );
+                files.extend(
+                    report
+                        .feeds
+                        .iter()
+                        .map(|feed| fs::read_to_string(directory.join(&feed.filename)).unwrap()),
+                );
+            }
+            outputs.push((statuses, files));
+            let _ = fs::remove_dir_all(&directory);
+        }
+        let statuses = &outputs[0].0;
+        assert_eq!(statuses.len(), 12);
+        assert!(statuses[..6]
+            .iter()
+            .all(|(_, status)| *status == FeedStatus::Updated));
+        assert_eq!(outputs[0], outputs[1]);
+        let _ = fs::remove_file("testdata/write_threads_urls.txt");
+        let _ = fs::remove_file("testdata/write_threads_feeds.json");
+    }
+
+    // reading the feeds of a server that answers after 20 ms, sequentially and with 6 threads
+    fn bench_read_all(b: &mut test::Bencher, threads: usize) {
+        let (server, paths) = feed_routes(Duration::from_millis(20));
+        let fetcher = fetch::Fetcher::new(&fetch::FetchOptions::default()).unwrap();
+        b.iter(|| {
+            let mut feeds: Vec<rss::Feed> = paths
+                .iter()
+                .map(|path| rss::Feed::new(&format!("{}{}", server.url, path), ""))
+                .collect();
+            let mut reading: Vec<&mut rss::Feed> = feeds.iter_mut().collect();
+            rss::read_all(
+                &mut reading,
+                &fetcher,
+                rss::ContentNormalization::default(),
+                &rss::DedupOptions::default(),
+                threads,
+            )
+        });
+    }
+
+    #[bench]
+    fn bench_read_all_sequential(b: &mut test::Bencher) {
+        bench_read_all(b, 1);
+    }
+
+    #[bench]
+    fn bench_read_all_parallel(b: &mut test::Bencher) {
+        bench_read_all(b, 6);
+    }
+
+    #[test]
+    fn test_gone_feed() {
+        setup_test_logger();
+        let server = TestServer::start(vec![response("410 Gone", &[], ""); 2]);
+        fs::write("testdata/gone_urls.txt", format!("{}\n", server.url)).unwrap();
+        let _ = fs::remove_file("testdata/gone_feeds.gone.json");
+        let cli = Cli::parse_from([
+            "rssfeed",
+            "--urls",
+            "testdata/gone_urls.txt",
+            "--ff",
+            "testdata/gone_feeds.json",
+            "--td",
+            "testdata/",
+        ]);
+        let settings = Settings::new(&cli).unwrap();
+        let iterate = |feed_map: &mut HashMap<String, rss::Feed>| {
+            process_feeds(
+                &cli,
+                &settings,
+                feed_map,
+                &mut HashMap::new(),
+                &mut HashMap::new(),
+            )
+            .feeds[0]
+                .status
+        };
+        let mut feed_map = HashMap::new();
+        assert_eq!(iterate(&mut feed_map), FeedStatus::Gone);
+        assert_eq!(iterate(&mut feed_map), FeedStatus::Gone);
+        assert_eq!(server.requests().len(), 1);
+        let gone = utilities::read_gone_feeds("testdata/gone_feeds.gone.json").unwrap();
+        assert!(gone.contains(&server.url));
+        // after a restart the feed is still not read
+        assert_eq!(iterate(&mut HashMap::new()), FeedStatus::Gone);
+        assert_eq!(server.requests().len(), 1);
+        let _ = fs::remove_file("testdata/gone_urls.txt");
+        let _ = fs::remove_file("testdata/gone_feeds.json");
+        let _ = fs::remove_file("testdata/gone_feeds.gone.json");
+    }
+
+    #[test]
+    fn test_respect_cache_control() {
+        setup_test_logger();
+        let server = TestServer::start(vec![
+            response(
+                "200 OK",
+                &[("Cache-Control", "max-age=3600")],
+                include_str!("../testdata/channel1.rss"),
+            );
+            2
+        ]);
+        fs::write(
+            "testdata/cache_control_urls.txt",
+            format!("{}\n", server.url),
+        )
+        .unwrap();
+        let cli = Cli::parse_from([
+            "rssfeed",
+            "--urls",
+            "testdata/cache_control_urls.txt",
+            "--ff",
+            "testdata/cache_control_feeds.json",
+            "--td",
+            "testdata/",
+            "--respect-cache-control",
+        ]);
+        let settings = Settings::new(&cli).unwrap();
+        let mut feed_map = HashMap::new();
+        let mut iterate = || {
+            process_feeds(
+                &cli,
+                &settings,
+                &mut feed_map,
+                &mut HashMap::new(),
+                &mut HashMap::new(),
+            )
+            .feeds[0]
+                .status
+        };
+        assert_eq!(iterate(), FeedStatus::Updated);
+        assert_eq!(iterate(), FeedStatus::NotUpdated);
+        assert_eq!(server.requests().len(), 1);
+        let feeds = opml::read_feeds(
+            "testdata/cache_control_feeds.json",
+            opml::FeedsFileFormat::Json,
+        )
+        .unwrap();
+        let _ = fs::remove_file(format!("testdata/{}", feeds[0].1));
+        let _ = fs::remove_file("testdata/cache_control_urls.txt");
+        let _ = fs::remove_file("testdata/cache_control_feeds.json");
+    }
+
+    #[test]
+    fn test_dedup_across_iterations() {
+        setup_test_logger();
+        let feed1 = include_str!("../testdata/channel1.rss");
+        let feed2 = include_str!("../testdata/channel2.rss");
+        // the item of the Kirchen article is in both feeds
+        let kirchen = "c8990bf7-8c6a-435c-9612-1ad7ee9a60ed";
+        for (switch, kept_in_iteration2) in [("on", false), ("off", true)] {
+            // iteration 2: feed 1 is unchanged and feed 2 is updated but still lists the item
+            let server = TestServer::start(vec![
+                response("200 OK", &[], feed1),
+                response("200 OK", &[], feed2),
+                response("200 OK", &[], feed1),
+                response("200 OK", &[], &feed2.replace("Militär", "Armee")),
+            ]);
+            fs::write(
+                "testdata/across_urls.txt",
+                format!("{}?1\n{}?2\n", server.url, server.url),
+            )
+            .unwrap();
+            let _ = fs::remove_file("testdata/across_feeds.json");
+            let cli = Cli::parse_from([
+                "rssfeed",
+                "--urls",
+                "testdata/across_urls.txt",
+                "--ff",
+                "testdata/across_feeds.json",
+                "--td",
+                "testdata/",
+                "--ma",
+                "0",
+                "--dedup-across-iterations",
+                switch,
+            ]);
+            let settings = Settings::new(&cli).unwrap();
+            let mut feed_map = HashMap::new();
+            let mut known_feeds = HashMap::new();
+            let report = process_feeds(
+                &cli,
+                &settings,
+                &mut feed_map,
+                &mut known_feeds,
+                &mut HashMap::new(),
+            );
+            let filename = format!("testdata/{}", report.feeds[1].filename);
+            assert!(!fs::read_to_string(&filename).unwrap().contains(kirchen));
+
+            let report = process_feeds(
+                &cli,
+                &settings,
+                &mut feed_map,
+                &mut known_feeds,
+                &mut HashMap::new(),
+            );
+            assert_eq!(report.feeds[0].status, FeedStatus::NotUpdated);
+            assert_ne!(report.feeds[1].status, FeedStatus::NotUpdated);
+            let content = fs::read_to_string(&filename).unwrap();
+            for report_feed in &report.feeds {
+                let _ = fs::remove_file(format!("testdata/{}", report_feed.filename));
+            }
+            assert_eq!(content.contains(kirchen), kept_in_iteration2);
+        }
+        let _ = fs::remove_file("testdata/across_urls.txt");
+        let _ = fs::remove_file("testdata/across_feeds.json");
+    }
 }
@@ -1,29 +1,163 @@
-use super::ids::{convert_url_to_unique_filename, generate_uuid};
+use super::fetch::RequestHeaders;
+use super::ids::{convert_url_to_unique_filename, generate_uuid, FeedNameTemplate};
+use super::rss::{emitter_config, ChangeDetection};
 
-use log::{error, info};
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::BufReader;
-use xmltree::{Element, EmitterConfig};
+use xmltree::{Element, XMLNode};
 
 pub struct OpmlDom {
     opmlroot: Element,
     filename: String,
     feeds: Vec<(String, String)>,
+    /// template of the filenames of new feeds
+    name_template: Option<FeedNameTemplate>,
 }
 
 // serialize and deserialize feeds to file
 #[derive(Serialize, Deserialize, Debug)]
-struct Data(Vec<(String, String)>);
+struct Data(Vec<JsonEntry>);
 
+// version of the schema of JSON feeds files with settings; files without settings stay a plain
+// array of pairs that older versions of rssdeduper can read
+const FEEDS_FILE_VERSION: u64 = 2;
+
+// a JSON feeds file with settings, {"version": 2, "feeds": [...]}
+#[derive(Serialize, Deserialize, Debug)]
+struct VersionedData {
+    version: u64,
+    feeds: Data,
+}
+
+// one feed of a JSON feeds file, a [url, filename] pair or, with settings, a triple
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum JsonEntry {
+    Pair(String, String),
+    WithSettings(String, String, FeedSettings),
+}
+
+// one feed of a TOML or NDJSON feeds file
+#[derive(Serialize, Deserialize, Debug)]
+struct FeedEntry {
+    url: String,
+    filename: String,
+    #[serde(default, skip_serializing_if = "FeedSettings::is_empty")]
+    settings: FeedSettings,
+}
+
+/// settings of one feed in the feeds file that replace the global ones, for users that edit
+/// the feeds file instead of the OPML file managed by their newsreader
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FeedSettings {
+    /// maximum age of the items of the feed in hours, 0 means unlimited
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<u64>,
+    /// replaces the priority attribute of the OPML file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+}
+
+impl FeedSettings {
+    pub fn is_empty(&self) -> bool {
+        *self == FeedSettings::default()
+    }
+}
+
+/// settings of one feed from the attributes of its outline in the source OPML file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutlineSettings {
+    /// how changes of the feed are detected, from the changeDetect attribute
+    pub change_detection: ChangeDetection,
+    /// request headers from the userAgent and acceptHeader attributes
+    pub request_headers: RequestHeaders,
+    /// the priority attribute, 0 if it is missing
+    pub priority: i32,
+}
+
+// the [[feed]] tables of a TOML feeds file
+#[derive(Serialize, Deserialize, Debug)]
+struct FeedTable {
+    #[serde(default)]
+    feed: Vec<FeedEntry>,
+}
+
+/// format of the feeds file that maps the feed urls to their filenames
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedsFileFormat {
+    /// a JSON array of [url, filename] pairs, or [url, filename, settings] for feeds with
+    /// settings
+    #[default]
+    Json,
+    /// [[feed]] tables with url and filename, easy to edit by hand
+    Toml,
+    /// one JSON object with url and filename per line, easy to append to
+    Ndjson,
+}
+
+impl FeedsFileFormat {
+    // the format given by the extension of filename, JSON for any other extension
+    pub fn from_filename(filename: &str) -> Self {
+        match std::path::Path::new(filename)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("toml") => FeedsFileFormat::Toml,
+            Some("ndjson") | Some("jsonl") => FeedsFileFormat::Ndjson,
+            _ => FeedsFileFormat::Json,
+        }
+    }
+}
+
+/// order of the feed outlines within each folder of the target OPML file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OpmlSort {
+    /// keep the order of the source OPML file
+    #[default]
+    None,
+    /// by title, ignoring case and the DD_ prefix
+    Title,
+    /// by xmlUrl
+    Url,
+}
+
+/// options for generating the target OPML file from the source OPML file
+#[derive(Clone, Debug, Default)]
+pub struct TargetOptions {
+    /// names of the categories whose feeds are deduplicated, all feeds if empty
+    pub categories: Vec<String>,
+    /// order of the feeds within each folder
+    pub sort: OpmlSort,
+    /// only add the new and remove the deleted feeds of the source OPML in the existing target
+    /// OPML instead of regenerating it, so all other outlines stay as they are
+    pub incremental: bool,
+    /// write the target OPML file without indentation and line breaks
+    pub compact: bool,
+    /// template of the filenames of new feeds, a uuid followed by their url if not set
+    pub name_template: Option<FeedNameTemplate>,
+}
+
+// a feed listed in several categories has an outline in each, all of them get the filename of
+// the first one, so the feed is served once but shown in each category
 fn modify_text_title_and_xmlurl_and_collect_changes(
     element: &mut Element,
     new_url_prefix: String,
     collector: &mut Vec<(String, String)>,
     previous_feeds: &HashMap<String, String>,
+    name_template: Option<&FeedNameTemplate>,
 ) {
     if element.name == "outline" {
+        let title = element
+            .attributes
+            .get("title")
+            .or_else(|| element.attributes.get("text"))
+            .cloned()
+            .unwrap_or_default();
         let mut newfeed = true;
         if let Some(title) = element.attributes.get_mut("title") {
             if title.starts_with("DD_") {
@@ -40,7 +174,17 @@ fn modify_text_title_and_xmlurl_and_collect_changes(
         if let Some(xmlurl) = element.attributes.get_mut("xmlUrl") {
             if newfeed {
                 let old_xmlurl = xmlurl.clone();
-                let new_filename = convert_url_to_unique_filename(xmlurl, &generate_uuid());
+                if let Some((_, filename)) = collector.iter().find(|(url, _)| *url == old_xmlurl) {
+                    *xmlurl = new_url_prefix + filename.as_str();
+                    return;
+                }
+                let new_filename = match name_template {
+                    Some(template) => template.filename(xmlurl, &title, |filename| {
+                        previous_feeds.contains_key(filename)
+                            || collector.iter().any(|(_, taken)| taken == filename)
+                    }),
+                    None => convert_url_to_unique_filename(xmlurl, &generate_uuid()),
+                };
                 *xmlurl = new_url_prefix + new_filename.as_str();
                 info!("Added new feed {} with url {}", &old_xmlurl, &new_filename);
                 collector.push((old_xmlurl, new_filename));
@@ -52,6 +196,9 @@ fn modify_text_title_and_xmlurl_and_collect_changes(
                     .unwrap_or(xmlurl)
                     .to_string();
                 // lookup the sourceurl in the previous feeds
+                if collector.iter().any(|(_, filename)| *filename == feedfile) {
+                    return;
+                }
                 if let Some(sourceurl) = previous_feeds.get(feedfile.as_str()) {
                     collector.push((sourceurl.clone(), feedfile.to_string()));
                 } else {
@@ -77,12 +224,315 @@ where
     }
 }
 
-pub fn read_feeds(filename: &str) -> Result<Vec<(String, String)>, String> {
+// sort the feed outlines (with xmlUrl) among the children of element and recursively in all
+// folders; the feeds take the places of the feeds before, so folders keep their position
+fn sort_outlines(element: &mut Element, sort: OpmlSort) {
+    if sort == OpmlSort::None {
+        return;
+    }
+    let is_feed = |node: &XMLNode| {
+        node.as_element().is_some_and(|outline| {
+            outline.name == "outline" && outline.attributes.contains_key("xmlUrl")
+        })
+    };
+    let key = |node: &XMLNode| {
+        let outline = node.as_element().unwrap();
+        let attribute = |name: &str| outline.attributes.get(name).map(String::as_str);
+        let key = match sort {
+            OpmlSort::Title => attribute("title")
+                .or_else(|| attribute("text"))
+                .map(|title| title.strip_prefix("DD_").unwrap_or(title)),
+            _ => attribute("xmlUrl"),
+        };
+        key.unwrap_or_default().to_lowercase()
+    };
+    let mut feeds: Vec<XMLNode> = element
+        .children
+        .iter()
+        .filter(|node| is_feed(node))
+        .cloned()
+        .collect();
+    // stable sort, so feeds with the same key keep their order
+    feeds.sort_by_cached_key(key);
+    let mut feeds = feeds.into_iter();
+    for node in element.children.iter_mut() {
+        if is_feed(node) {
+            *node = feeds.next().unwrap();
+        } else if let Some(child) = node.as_mut_element() {
+            sort_outlines(child, sort);
+        }
+    }
+}
+
+// the name of an outline, its title or text without the DD_ prefix of a patched OPML
+fn outline_name(outline: &Element) -> Option<&str> {
+    ["title", "text"]
+        .iter()
+        .find_map(|name| outline.attributes.get(*name))
+        .map(|name| name.strip_prefix("DD_").unwrap_or(name).trim())
+}
+
+// collect the feed outlines (with xmlUrl) below element, each with the folder it is in
+fn feed_outlines<'a>(
+    element: &'a Element,
+    folder: Option<&'a Element>,
+    outlines: &mut Vec<(Option<&'a Element>, &'a Element)>,
+) {
+    for child in element.children.iter().filter_map(XMLNode::as_element) {
+        if child.name != "outline" {
+            feed_outlines(child, folder, outlines);
+        } else if child.attributes.contains_key("xmlUrl") {
+            outlines.push((folder, child));
+        } else {
+            feed_outlines(child, Some(child), outlines);
+        }
+    }
+}
+
+// the folder outline (without xmlUrl) named name below element
+fn find_folder_mut<'a>(element: &'a mut Element, name: &str) -> Option<&'a mut Element> {
+    if element.name == "outline"
+        && !element.attributes.contains_key("xmlUrl")
+        && outline_name(element) == Some(name)
+    {
+        return Some(element);
+    }
+    element
+        .children
+        .iter_mut()
+        .filter_map(XMLNode::as_mut_element)
+        .find_map(|child| find_folder_mut(child, name))
+}
+
+// remove the feed outlines whose xmlUrl is in xmlurls below element
+fn remove_feed_outlines(element: &mut Element, xmlurls: &HashSet<String>) {
+    element.children.retain(|child| {
+        child
+            .as_element()
+            .and_then(|outline| outline.attributes.get("xmlUrl"))
+            .is_none_or(|xmlurl| !xmlurls.contains(xmlurl))
+    });
+    for child in element
+        .children
+        .iter_mut()
+        .filter_map(XMLNode::as_mut_element)
+    {
+        remove_feed_outlines(child, xmlurls);
+    }
+}
+
+// whether element is a category, an outline without xmlUrl, named like one of categories;
+// names are compared ignoring case and the DD_ prefix of an already patched OPML
+fn is_selected_category(element: &Element, categories: &[String]) -> bool {
+    if element.name != "outline" || element.attributes.contains_key("xmlUrl") {
+        return false;
+    }
+    ["text", "title"]
+        .iter()
+        .filter_map(|name| element.attributes.get(*name))
+        .map(|name| name.strip_prefix("DD_").unwrap_or(name).trim())
+        .any(|name| {
+            categories
+                .iter()
+                .any(|category| category.trim().eq_ignore_ascii_case(name))
+        })
+}
+
+// like traverse_and_modify, but only the selected elements are modified: all if categories is
+// empty, otherwise the category outlines named in categories and everything nested in them
+fn traverse_and_modify_categories<F>(
+    element: &mut Element,
+    categories: &[String],
+    selected: bool,
+    modifier: &mut F,
+) where
+    F: FnMut(&mut Element),
+{
+    let selected = selected || categories.is_empty() || is_selected_category(element, categories);
+    if selected {
+        modifier(element);
+    }
+    for child in element.children.iter_mut() {
+        if let Some(child_element) = child.as_mut_element() {
+            traverse_and_modify_categories(child_element, categories, selected, modifier);
+        }
+    }
+}
+
+// the settings of the outlines in the OPML file from their changeDetect, userAgent,
+// acceptHeader and priority attributes, keyed by their xmlUrl; outlines without any of these
+// attributes are left out and use the defaults, an unknown changeDetect or a priority that is
+// not an integer is ignored
+pub fn read_outline_settings(filename: &str) -> Result<HashMap<String, OutlineSettings>, String> {
+    let mut opml = OpmlDom::new(filename)?;
+    let mut outline_settings = HashMap::new();
+    let mut collector = |element: &mut Element| {
+        if element.name != "outline" {
+            return;
+        }
+        let Some(xmlurl) = element.attributes.get("xmlUrl") else {
+            return;
+        };
+        let mut settings = OutlineSettings {
+            request_headers: RequestHeaders {
+                user_agent: element.attributes.get("userAgent").cloned(),
+                accept: element.attributes.get("acceptHeader").cloned(),
+            },
+            ..Default::default()
+        };
+        if let Some(value) = element.attributes.get("changeDetect") {
+            match ChangeDetection::parse(value) {
+                Some(mode) => settings.change_detection = mode,
+                None => warn!("Unknown changeDetect {} of feed {} ignored", value, xmlurl),
+            }
+        }
+        if let Some(value) = element.attributes.get("priority") {
+            match value.trim().parse() {
+                Ok(priority) => settings.priority = priority,
+                Err(_) => warn!("Invalid priority {} of feed {} ignored", value, xmlurl),
+            }
+        }
+        if settings != OutlineSettings::default() {
+            outline_settings.insert(xmlurl.clone(), settings);
+        }
+    };
+    traverse_and_modify(&mut opml.opmlroot, &mut collector);
+    Ok(outline_settings)
+}
+
+pub fn read_feeds(
+    filename: &str,
+    format: FeedsFileFormat,
+) -> Result<Vec<(String, String)>, String> {
+    Ok(read_feed_entries(filename, format)?
+        .into_iter()
+        .map(|entry| (entry.url, entry.filename))
+        .collect())
+}
+
+// the settings of the feeds in the feeds file, keyed by their url; feeds without settings are
+// left out
+pub fn read_feed_settings(
+    filename: &str,
+    format: FeedsFileFormat,
+) -> Result<HashMap<String, FeedSettings>, String> {
+    Ok(read_feed_entries(filename, format)?
+        .into_iter()
+        .filter(|entry| !entry.settings.is_empty())
+        .map(|entry| (entry.url, entry.settings))
+        .collect())
+}
+
+fn read_feed_entries(filename: &str, format: FeedsFileFormat) -> Result<Vec<FeedEntry>, String> {
     let file_content = std::fs::read_to_string(filename)
         .map_err(|e| format!("Cannot read feeds file {}: {}", filename, e))?;
-    let deserialized: Data = serde_json::from_str(&file_content)
-        .map_err(|e| format!("Cannot deserialize feeds file {}: {}", filename, e))?;
-    Ok(deserialized.0)
+    let deserialize_error =
+        |e: &dyn std::fmt::Display| format!("Cannot deserialize feeds file {}: {}", filename, e);
+    let entries: Vec<FeedEntry> = match format {
+        FeedsFileFormat::Json => {
+            let value: serde_json::Value =
+                serde_json::from_str(&file_content).map_err(|e| deserialize_error(&e))?;
+            let deserialized: Data = if value.is_object() {
+                let versioned: VersionedData =
+                    serde_json::from_value(value).map_err(|e| deserialize_error(&e))?;
+                if versioned.version > FEEDS_FILE_VERSION {
+                    return Err(format!(
+                        "Feeds file {} has version {} of the schema, this version of rssdeduper reads up to version {}",
+                        filename, versioned.version, FEEDS_FILE_VERSION
+                    ));
+                }
+                versioned.feeds
+            } else {
+                serde_json::from_value(value).map_err(|e| deserialize_error(&e))?
+            };
+            deserialized
+                .0
+                .into_iter()
+                .map(|entry| match entry {
+                    JsonEntry::Pair(url, filename) => FeedEntry {
+                        url,
+                        filename,
+                        settings: FeedSettings::default(),
+                    },
+                    JsonEntry::WithSettings(url, filename, settings) => FeedEntry {
+                        url,
+                        filename,
+                        settings,
+                    },
+                })
+                .collect()
+        }
+        FeedsFileFormat::Toml => {
+            let deserialized: FeedTable =
+                toml::from_str(&file_content).map_err(|e| deserialize_error(&e))?;
+            deserialized.feed
+        }
+        FeedsFileFormat::Ndjson => file_content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                serde_json::from_str(line)
+                    .map_err(|e| deserialize_error(&format!("line {}: {}", index + 1, e)))
+            })
+            .collect::<Result<_, _>>()?,
+    };
+    Ok(entries)
+}
+
+// write the feeds with the settings of their urls, so settings edited into the feeds file
+// survive its regeneration
+pub fn write_feeds(
+    filename: &str,
+    feeds: &[(String, String)],
+    settings: &HashMap<String, FeedSettings>,
+    format: FeedsFileFormat,
+) -> Result<(), String> {
+    info!("Writing feeds {:?} file {}", format, filename);
+    let entries = || {
+        feeds.iter().map(|(url, filename)| FeedEntry {
+            url: url.clone(),
+            filename: filename.clone(),
+            settings: settings.get(url).copied().unwrap_or_default(),
+        })
+    };
+    let serialized = match format {
+        FeedsFileFormat::Json => {
+            let data = Data(
+                entries()
+                    .map(|entry| {
+                        if entry.settings.is_empty() {
+                            JsonEntry::Pair(entry.url, entry.filename)
+                        } else {
+                            JsonEntry::WithSettings(entry.url, entry.filename, entry.settings)
+                        }
+                    })
+                    .collect(),
+            );
+            if data
+                .0
+                .iter()
+                .any(|entry| matches!(entry, JsonEntry::WithSettings(..)))
+            {
+                serde_json::to_string_pretty(&VersionedData {
+                    version: FEEDS_FILE_VERSION,
+                    feeds: data,
+                })
+                .unwrap()
+            } else {
+                serde_json::to_string_pretty(&data).unwrap()
+            }
+        }
+        FeedsFileFormat::Toml => toml::to_string(&FeedTable {
+            feed: entries().collect(),
+        })
+        .map_err(|e| format!("Cannot serialize feeds: {}", e))?,
+        FeedsFileFormat::Ndjson => entries()
+            .map(|entry| serde_json::to_string(&entry).unwrap() + "\n")
+            .collect(),
+    };
+    std::fs::write(filename, serialized).map_err(|e| format!("Cannot write feeds: {}", e))?;
+    Ok(())
 }
 
 impl OpmlDom {
@@ -98,11 +548,22 @@ impl OpmlDom {
             opmlroot,
             filename: filename.to_string(),
             feeds: Vec::new(),
+            name_template: None,
         })
     }
 
-    // must not be called more than once!
-    pub fn modify(&mut self, new_url_prefix: String, previous_feeds: &HashMap<String, String>) {
+    pub fn set_name_template(&mut self, name_template: Option<FeedNameTemplate>) {
+        self.name_template = name_template;
+    }
+
+    // must not be called more than once! only the feeds in categories are patched and
+    // collected, all feeds if categories is empty
+    pub fn modify(
+        &mut self,
+        new_url_prefix: String,
+        previous_feeds: &HashMap<String, String>,
+        categories: &[String],
+    ) {
         assert!(self.feeds.is_empty()); // must not be called more than once
         info!(
             "Patching OPML file {} with url prefix {}",
@@ -114,28 +575,100 @@ impl OpmlDom {
                 new_url_prefix.clone(),
                 &mut self.feeds,
                 previous_feeds,
+                self.name_template.as_ref(),
             )
         };
-        traverse_and_modify(&mut self.opmlroot, &mut modifier);
+        traverse_and_modify_categories(&mut self.opmlroot, categories, false, &mut modifier);
+    }
+
+    // the (source url, filename) pairs collected by modify
+    pub fn feeds(&self) -> &[(String, String)] {
+        &self.feeds
+    }
+
+    // apply the changes of this source OPML patched by modify to target, the target OPML
+    // generated before for the feeds previous: the outlines of the feeds this OPML no longer
+    // lists are removed and the outlines of its new feeds are appended to the folder of the same
+    // name, which is added if the target does not have it yet; all other outlines are left as
+    // they are; the feeds of target are then the remaining feeds of previous and the new ones
+    pub fn update_target(
+        &self,
+        target: &mut OpmlDom,
+        previous: &[(String, String)],
+        url_prefix: &str,
+    ) {
+        let listed: HashSet<&String> = self.feeds.iter().map(|(_, filename)| filename).collect();
+        let known: HashSet<&String> = previous.iter().map(|(_, filename)| filename).collect();
+        let removed: HashSet<String> = previous
+            .iter()
+            .filter(|(_, filename)| !listed.contains(filename))
+            .map(|(_, filename)| format!("{}{}", url_prefix, filename))
+            .collect();
+        let added: Vec<&(String, String)> = self
+            .feeds
+            .iter()
+            .filter(|(_, filename)| !known.contains(filename))
+            .collect();
+        info!(
+            "Updating OPML file {} with {} new and {} removed feeds",
+            target.filename,
+            added.len(),
+            removed.len()
+        );
+        remove_feed_outlines(&mut target.opmlroot, &removed);
+
+        let mut outlines = Vec::new();
+        feed_outlines(&self.opmlroot, None, &mut outlines);
+        for (_, filename) in &added {
+            let xmlurl = format!("{}{}", url_prefix, filename);
+            let Some((folder, outline)) = outlines
+                .iter()
+                .find(|(_, outline)| outline.attributes.get("xmlUrl") == Some(&xmlurl))
+            else {
+                continue;
+            };
+            let Some(body) = target.opmlroot.get_mut_child("body") else {
+                continue;
+            };
+            let outline = XMLNode::Element((*outline).clone());
+            match folder.and_then(|folder| Some((folder, outline_name(folder)?))) {
+                None => body.children.push(outline),
+                Some((folder, name)) => match find_folder_mut(body, name) {
+                    Some(target_folder) => target_folder.children.push(outline),
+                    None => {
+                        let mut new_folder = folder.clone();
+                        new_folder.children = vec![outline];
+                        body.children.push(XMLNode::Element(new_folder));
+                    }
+                },
+            }
+        }
+        target.feeds = previous
+            .iter()
+            .filter(|(_, filename)| listed.contains(filename))
+            .chain(added)
+            .cloned()
+            .collect();
     }
 
-    pub fn save_feeds(&mut self, filename: &str) -> Result<(), String> {
-        info!("Writing feeds json file {}", filename);
-        let data = Data(self.feeds.clone());
-        let serialized = serde_json::to_string_pretty(&data).unwrap();
-        std::fs::write(filename, serialized).map_err(|e| format!("Cannot write feeds: {}", e))?;
-        Ok(())
+    pub fn save_feeds(
+        &mut self,
+        filename: &str,
+        settings: &HashMap<String, FeedSettings>,
+        format: FeedsFileFormat,
+    ) -> Result<(), String> {
+        write_feeds(filename, &self.feeds, settings, format)
     }
 
-    pub fn write(&self, filename: &str) -> Result<(), String> {
+    // write the OPML file with the feed outlines of each folder in the sort order of options
+    pub fn write(&self, filename: &str, options: &TargetOptions) -> Result<(), String> {
         info!("Writing OPML file {}", filename);
-        let config = EmitterConfig::new()
-            .indent_string("    ")
-            .line_separator("\n")
-            .perform_indent(true)
-            .normalize_empty_elements(true);
-        self.opmlroot
-            .write_with_config(File::create(filename).unwrap(), config)
+        let mut opmlroot = self.opmlroot.clone();
+        sort_outlines(&mut opmlroot, options.sort);
+        let file = File::create(filename)
+            .map_err(|e| format!("OPML file {} cannot be created: {}", filename, e))?;
+        opmlroot
+            .write_with_config(file, emitter_config(options.compact))
             .map_err(|e| format!("OPML file {} cannot be written: {}", filename, e))
     }
 }
@@ -164,10 +697,11 @@ mod tests {
                 "http://replace.with.my.domain/rssfeeds/".to_string(),
                 &mut collector,
                 &previousfeeds,
+                None,
             )
         };
         traverse_and_modify(&mut opml.opmlroot, &mut modifier);
-        let result = opml.write("testdata/feedly-target.opml");
+        let result = opml.write("testdata/feedly-target.opml", &TargetOptions::default());
         assert!(result.is_ok());
         assert_eq!(collector.len(), 42);
         assert!(std::fs::remove_file("testdata/feedly-target.opml").is_ok());
@@ -181,22 +715,267 @@ mod tests {
         opml.modify(
             "http://replace.with.my.domain/rssfeeds/".to_string(),
             &previousfeeds,
+            &[],
+        );
+        let result = opml.save_feeds(
+            "testdata/feeds.json",
+            &HashMap::new(),
+            FeedsFileFormat::Json,
         );
-        let result = opml.save_feeds("testdata/feeds.json");
         assert!(result.is_ok());
-        let feeds = read_feeds("testdata/feeds.json");
+        let feeds = read_feeds("testdata/feeds.json", FeedsFileFormat::Json);
         assert!(feeds.is_ok());
         assert_eq!(feeds.unwrap().len(), 42);
     }
 
+    #[test]
+    fn test_modify_categories() {
+        setup_test_logger();
+        let mut opml = OpmlDom::new("testdata/feedly-source.opml").unwrap();
+        opml.modify(
+            "http://replace.with.my.domain/rssfeeds/".to_string(),
+            &HashMap::new(),
+            &["news".to_string(), "FAZ".to_string()],
+        );
+        assert_eq!(opml.feeds.len(), 14);
+        assert!(opml
+            .feeds
+            .iter()
+            .any(|(url, _)| url == "http://blog.rust-lang.org/feed.xml"));
+        assert!(!opml
+            .feeds
+            .iter()
+            .any(|(url, _)| url == "https://duino4projects.com/feed/"));
+        // the other feeds keep their original url
+        let mut content = Vec::new();
+        opml.opmlroot.write(&mut content).unwrap();
+        let content = String::from_utf8(content).unwrap();
+        assert!(content.contains("xmlUrl=\"https://duino4projects.com/feed/\""));
+        assert!(content.contains("title=\"DD_News\""));
+        assert!(content.contains("title=\"Arduino\""));
+    }
+
+    #[test]
+    fn test_modify_feed_in_several_categories() {
+        setup_test_logger();
+        let prefix = "http://replace.with.my.domain/rssfeeds/";
+        let mut opml = OpmlDom::new("testdata/multiple-categories.opml").unwrap();
+        opml.modify(prefix.to_string(), &HashMap::new(), &[]);
+        // one served file for the feed in both categories
+        assert_eq!(opml.feeds.len(), 2);
+        let (_, filename) = opml
+            .feeds
+            .iter()
+            .find(|(url, _)| url == "https://www.tagesschau.de/xml/rss2/")
+            .unwrap();
+        let mut content = Vec::new();
+        opml.opmlroot.write(&mut content).unwrap();
+        let content = String::from_utf8(content).unwrap();
+        let served_url = format!("xmlUrl=\"{}{}\"", prefix, filename);
+        assert_eq!(content.matches(&served_url).count(), 2);
+
+        // the re-imported target OPML still lists the feed once
+        let target = "testdata/multiple-categories-target.opml";
+        opml.write(target, &TargetOptions::default()).unwrap();
+        let previous: HashMap<String, String> = opml
+            .feeds
+            .iter()
+            .map(|(url, filename)| (filename.clone(), url.clone()))
+            .collect();
+        let mut reimported = OpmlDom::new(target).unwrap();
+        reimported.modify(prefix.to_string(), &previous, &[]);
+        assert!(std::fs::remove_file(target).is_ok());
+        assert_eq!(reimported.feeds, opml.feeds);
+    }
+
+    #[test]
+    fn test_modify_with_name_template() {
+        setup_test_logger();
+        let mut opml = OpmlDom::new("testdata/multiple-categories.opml").unwrap();
+        opml.set_name_template(Some(FeedNameTemplate::new("{title-slug}.rss").unwrap()));
+        opml.modify(
+            "http://replace.with.my.domain/rssfeeds/".to_string(),
+            &HashMap::new(),
+            &[],
+        );
+        assert_eq!(
+            opml.feeds,
+            vec![
+                (
+                    "https://www.tagesschau.de/xml/rss2/".to_string(),
+                    "tagesschau-de.rss".to_string()
+                ),
+                (
+                    "https://www.spiegel.de/schlagzeilen/index.rss".to_string(),
+                    "spiegel-schlagzeilen.rss".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_feeds_file_versions() {
+        setup_test_logger();
+        let filename = std::env::temp_dir().join("rssdeduper-feeds-versions.json");
+        let filename = filename.to_str().unwrap();
+        let feeds = vec![(
+            "https://www.faz.net/rss/aktuell/".to_string(),
+            "faz.rss".to_string(),
+        )];
+        // without settings the file stays a plain array of pairs
+        write_feeds(filename, &feeds, &HashMap::new(), FeedsFileFormat::Json).unwrap();
+        let content = std::fs::read_to_string(filename).unwrap();
+        assert!(content.trim_start().starts_with('['));
+        // a plain array with settings, as edited by hand, is read as well
+        std::fs::write(
+            filename,
+            r#"[["https://www.faz.net/rss/aktuell/", "faz.rss", {"max_age": 72}]]"#,
+        )
+        .unwrap();
+        assert_eq!(read_feeds(filename, FeedsFileFormat::Json).unwrap(), feeds);
+        assert_eq!(
+            read_feed_settings(filename, FeedsFileFormat::Json).unwrap()[&feeds[0].0].max_age,
+            Some(72)
+        );
+        std::fs::write(
+            filename,
+            r#"{"version": 3, "feeds": [["https://www.faz.net/rss/aktuell/", "faz.rss"]]}"#,
+        )
+        .unwrap();
+        let error = read_feeds(filename, FeedsFileFormat::Json).unwrap_err();
+        assert!(error.contains("version 3"));
+        let _ = std::fs::remove_file(filename);
+    }
+
+    #[test]
+    fn test_feeds_file_formats() {
+        setup_test_logger();
+        let feeds = vec![
+            (
+                "https://www.faz.net/rss/aktuell/".to_string(),
+                "51f9e919-e8ae-4ebd-86c0-3c40a071c198https_www_faz_net_rss_aktuell_.rss"
+                    .to_string(),
+            ),
+            (
+                "https://www.example.com/feed?a=1&b=\"2\"".to_string(),
+                "example.rss".to_string(),
+            ),
+        ];
+        for (filename, format) in [
+            ("testdata/formats.json", FeedsFileFormat::Json),
+            ("testdata/formats.toml", FeedsFileFormat::Toml),
+            ("testdata/formats.ndjson", FeedsFileFormat::Ndjson),
+        ] {
+            assert_eq!(FeedsFileFormat::from_filename(filename), format);
+            let settings = HashMap::from([(
+                feeds[1].0.clone(),
+                FeedSettings {
+                    max_age: Some(72),
+                    priority: None,
+                },
+            )]);
+            write_feeds(filename, &feeds, &settings, format).unwrap();
+            let read = read_feeds(filename, format);
+            let read_settings = read_feed_settings(filename, format);
+            let content = std::fs::read_to_string(filename).unwrap();
+            let _ = std::fs::remove_file(filename);
+            assert_eq!(read.unwrap(), feeds);
+            assert_eq!(read_settings.unwrap(), settings);
+            if format == FeedsFileFormat::Ndjson {
+                assert_eq!(content.lines().count(), 2);
+            }
+            if format == FeedsFileFormat::Json {
+                assert!(content.contains("\"version\": 2"));
+            }
+        }
+        assert_eq!(
+            FeedsFileFormat::from_filename("feeds.txt"),
+            FeedsFileFormat::Json
+        );
+    }
+
+    #[test]
+    fn test_read_outline_settings() {
+        setup_test_logger();
+        let outline_settings = read_outline_settings("testdata/changedetect.opml").unwrap();
+        assert_eq!(outline_settings.len(), 3);
+        assert_eq!(
+            outline_settings["https://www.faz.net/rss/aktuell/"].change_detection,
+            ChangeDetection::Always
+        );
+        assert_eq!(
+            outline_settings["https://www.spiegel.de/schlagzeilen/index.rss"],
+            OutlineSettings {
+                change_detection: ChangeDetection::BuildDate,
+                request_headers: RequestHeaders {
+                    user_agent: Some("Mozilla/5.0 (X11; Linux x86_64)".to_string()),
+                    accept: None,
+                },
+                priority: 0,
+            }
+        );
+        // the unknown changeDetect is ignored, the priority kept
+        assert_eq!(
+            outline_settings["https://www.tagesschau.de/xml/rss2/"],
+            OutlineSettings {
+                priority: 10,
+                ..Default::default()
+            }
+        );
+    }
+
     #[test]
     fn test_read_and_write() {
         setup_test_logger();
         let opml = OpmlDom::new("testdata/feedly-source.opml").unwrap();
-        let result = opml.write("testdata/new-feedly.opml");
+        let result = opml.write("testdata/new-feedly.opml", &TargetOptions::default());
         assert!(result.is_ok());
         let opml2 = OpmlDom::new("testdata/new-feedly.opml");
         assert!(opml2.is_ok());
         assert!(std::fs::remove_file("testdata/new-feedly.opml").is_ok());
     }
+
+    #[test]
+    fn test_write_sorted() {
+        setup_test_logger();
+        let mut opml = OpmlDom::new("testdata/feedly-source.opml").unwrap();
+        opml.modify(
+            "http://replace.with.my.domain/rssfeeds/".to_string(),
+            &HashMap::new(),
+            &[],
+        );
+        let titles = |filename: &str| -> Vec<Vec<String>> {
+            let opml = OpmlDom::new(filename).unwrap();
+            let body = opml.opmlroot.get_child("body").unwrap();
+            body.children
+                .iter()
+                .filter_map(XMLNode::as_element)
+                .map(|folder| {
+                    folder
+                        .children
+                        .iter()
+                        .filter_map(XMLNode::as_element)
+                        .map(|feed| feed.attributes["title"].to_lowercase())
+                        .collect()
+                })
+                .collect()
+        };
+        opml.write("testdata/sorted-feedly.opml", &TargetOptions::default())
+            .unwrap();
+        let unsorted = titles("testdata/sorted-feedly.opml");
+        let options = TargetOptions {
+            sort: OpmlSort::Title,
+            ..Default::default()
+        };
+        opml.write("testdata/sorted-feedly.opml", &options).unwrap();
+        let sorted = titles("testdata/sorted-feedly.opml");
+        assert!(std::fs::remove_file("testdata/sorted-feedly.opml").is_ok());
+        // the folders keep their order and their feeds, which are alphabetized
+        assert_eq!(sorted.len(), unsorted.len());
+        assert_ne!(sorted, unsorted);
+        for (mut unsorted_feeds, sorted_feeds) in unsorted.into_iter().zip(sorted) {
+            unsorted_feeds.sort();
+            assert_eq!(unsorted_feeds, sorted_feeds);
+        }
+    }
 }
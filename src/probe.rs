@@ -0,0 +1,101 @@
+use super::fetch::Fetcher;
+use super::ids;
+use super::rss::{describe_parse_error, feed_items, item_link, ContentNormalization, Feed};
+
+use xmltree::Element;
+
+// fetch the feed at url and describe for each item (or Atom entry) the (id, host) key used for deduplication
+// and the rule that produced the id, to check the id extraction before adding a feed
+pub fn probe(url: &str, fetcher: &Fetcher, id_options: &ids::IdOptions) -> Result<String, String> {
+    let mut feed = Feed::new(url, "");
+    feed.read(fetcher, ContentNormalization::default())?;
+    let rssroot = Element::parse(feed.content().as_bytes()).map_err(|e| {
+        format!(
+            "RSS feed {} XML parse error at {}",
+            url,
+            describe_parse_error(feed.content(), &e)
+        )
+    })?;
+    let mut output = String::new();
+    for item in feed_items(&rssroot) {
+        let text = |name: &str| {
+            item.get_child(name)
+                .and_then(|child| child.get_text())
+                .map(|text| text.trim().to_string())
+                .unwrap_or_default()
+        };
+        let link = item_link(item, &[]).unwrap_or_default();
+        output.push_str(&format!("{}\n  link: {}\n", text("title"), link));
+        let title_key =
+            ids::extract_unique_id_and_host_from_title(&link, &text("title"), id_options)
+                .map(|(id, host)| (id, host, ids::IdRule::Title));
+        match title_key
+            .or_else(|| ids::extract_unique_id_host_and_rule_from_url_string(&link, id_options))
+        {
+            Some((id, host, rule)) => {
+                output.push_str(&format!("  key:  ({}, {}) by {}\n", id, host, rule))
+            }
+            None => output.push_str("  key:  none, the item is not deduplicated\n"),
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fetch::FetchOptions;
+    use super::super::testserver::{response, TestServer};
+    use super::super::utilities::setup_test_logger;
+    use super::*;
+
+    #[test]
+    fn test_probe() {
+        setup_test_logger();
+        let server = TestServer::start(vec![response(
+            "200 OK",
+            &[],
+            include_str!("../testdata/channel1.rss"),
+        )]);
+        let fetcher = Fetcher::new(&FetchOptions::default()).unwrap();
+        let output = probe(&server.url, &fetcher, &ids::IdOptions::default()).unwrap();
+        let keys: Vec<_> = output
+            .lines()
+            .filter_map(|line| line.strip_prefix("  key:  "))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                "(c8990bf7-8c6a-435c-9612-1ad7ee9a60ed, www.stuttgarter-zeitung.de) by uuid",
+                "(d46eec6a-8d67-457f-a8fb-b71a21481ae0, www.stuttgarter-zeitung.de) by uuid",
+                "(19314690, www.faz.net) by number",
+                "(19314690, www.faz.net) by number",
+            ]
+        );
+        assert!(output.starts_with("Deutschlands Kirchen am Kipppunkt"));
+    }
+
+    #[test]
+    fn test_probe_atom() {
+        setup_test_logger();
+        let server = TestServer::start(vec![response(
+            "200 OK",
+            &[],
+            include_str!("../testdata/atomchannel1.xml"),
+        )]);
+        let fetcher = Fetcher::new(&FetchOptions::default()).unwrap();
+        let output = probe(&server.url, &fetcher, &ids::IdOptions::default()).unwrap();
+        // the link of an entry is the href of its alternate link
+        let links: Vec<_> = output
+            .lines()
+            .filter_map(|line| line.strip_prefix("  link: "))
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                "https://blog.rust-lang.org/2023/11/16/Rust-1.74.0.html",
+                "https://blog.rust-lang.org/2023/10/05/Rust-1.73.0.html",
+            ]
+        );
+        assert!(output.starts_with("Announcing Rust 1.74.0"));
+    }
+}
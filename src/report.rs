@@ -0,0 +1,169 @@
+use super::rss::write_temporary_file;
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+/// summary of one iteration over all feeds
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct IterationReport {
+    /// start of the iteration in RFC 3339 format
+    pub started: String,
+    pub duration_ms: u64,
+    pub feeds: Vec<FeedReport>,
+    /// effective configuration of the run
+    #[serde(skip_serializing_if = "serde_json::Value::is_null", default)]
+    pub config: serde_json::Value,
+    /// why the iteration stopped before processing any feed, e.g. the source file cannot be
+    /// read with --on-source-error exit
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub source_error: Option<String>,
+}
+
+/// result of processing one feed in an iteration
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FeedReport {
+    pub url: String,
+    pub filename: String,
+    pub status: FeedStatus,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub dedup: Option<DedupStats>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub error: Option<String>,
+}
+
+/// number of items in a feed before and after deduplication
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DedupStats {
+    /// items in the fetched feed
+    pub items: usize,
+    /// items removed because they are duplicates or too old
+    pub removed: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedStatus {
+    /// the feed was deduplicated and its file changed
+    Updated,
+    /// the feed was deduplicated but its file did not change
+    Unchanged,
+    /// the feed has not been updated upstream since the last iteration
+    NotUpdated,
+    /// the feed could not be read, deduplicated or written
+    Failed,
+    /// the feed is not read because it failed too often
+    Disabled,
+    /// the feed is not read because its server answered 410 Gone
+    Gone,
+}
+
+/// map from the filename of each served feed to its last update, for clients polling the
+/// served directory that only want to fetch the changed feeds
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Manifest(pub BTreeMap<String, ManifestEntry>);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    /// time of the last iteration that changed the file in RFC 3339 format
+    pub updated: String,
+    /// items in the file
+    pub items: usize,
+}
+
+impl Manifest {
+    pub fn read(filename: &str) -> Result<Self, String> {
+        let json = fs::read_to_string(filename)
+            .map_err(|e| format!("Manifest {} cannot be read: {}", filename, e))?;
+        serde_json::from_str(&json)
+            .map_err(|e| format!("Manifest {} cannot be parsed: {}", filename, e))
+    }
+
+    // record the feeds of report that were updated with the start of its iteration and their
+    // number of items in items, keyed by url; feeds that are not in report are dropped and the
+    // other feeds keep their entries
+    pub fn update(&mut self, report: &IterationReport, items: &HashMap<String, usize>) {
+        self.0
+            .retain(|filename, _| report.feeds.iter().any(|feed| &feed.filename == filename));
+        for feed in &report.feeds {
+            if feed.status == FeedStatus::Updated {
+                let entry = ManifestEntry {
+                    updated: report.started.clone(),
+                    items: items.get(&feed.url).copied().unwrap_or_default(),
+                };
+                self.0.insert(feed.filename.clone(), entry);
+            }
+        }
+    }
+
+    // write the manifest as pretty printed JSON, replacing the file atomically
+    pub fn write(&self, filename: &str) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Manifest cannot be serialized: {}", e))?;
+        let tmp_filename = write_temporary_file(filename, json.as_bytes())?;
+        fs::rename(&tmp_filename, filename)
+            .map_err(|e| format!("File {} cannot be renamed: {}", tmp_filename, e))
+    }
+}
+
+impl IterationReport {
+    pub fn count(&self, status: FeedStatus) -> usize {
+        self.feeds
+            .iter()
+            .filter(|feed| feed.status == status)
+            .count()
+    }
+
+    // write the report as pretty printed JSON, replacing the file atomically
+    pub fn write(&self, filename: &str) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Report cannot be serialized: {}", e))?;
+        let tmp_filename = write_temporary_file(filename, json.as_bytes())?;
+        fs::rename(&tmp_filename, filename)
+            .map_err(|e| format!("File {} cannot be renamed: {}", tmp_filename, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_report() {
+        let report = IterationReport {
+            started: "2023-11-15T07:11:10+00:00".to_string(),
+            duration_ms: 42,
+            feeds: vec![
+                FeedReport {
+                    url: "https://www.faz.net/rss/aktuell/".to_string(),
+                    filename: "faz.rss".to_string(),
+                    status: FeedStatus::Updated,
+                    dedup: Some(DedupStats {
+                        items: 4,
+                        removed: 1,
+                    }),
+                    error: None,
+                },
+                FeedReport {
+                    url: "https://www.spiegel.de/schlagzeilen/index.rss".to_string(),
+                    filename: "spiegel.rss".to_string(),
+                    status: FeedStatus::Failed,
+                    dedup: None,
+                    error: Some("Feed cannot be read".to_string()),
+                },
+            ],
+            config: serde_json::json!({ "ma": 24 }),
+            source_error: None,
+        };
+        let filename = "testdata/report.json";
+        report.write(filename).unwrap();
+        let json = fs::read_to_string(filename).unwrap();
+        let _ = fs::remove_file(filename);
+        assert!(!json.contains("\"dedup\": null"));
+        let read: IterationReport = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, report);
+        assert_eq!(read.count(FeedStatus::Updated), 1);
+        assert_eq!(read.count(FeedStatus::Failed), 1);
+        assert_eq!(read.count(FeedStatus::Unchanged), 0);
+    }
+}
@@ -1,77 +1,1402 @@
+use super::fetch::{cache_lifetime, etags_match_weakly, Fetcher, RequestHeaders, Validators};
 use super::ids;
-use std::collections::HashMap;
+use super::report::DedupStats;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::Write;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::{mpsc, Mutex};
+use std::thread;
 
 use chrono::{DateTime, Duration, Local, Utc};
-use log::{debug, info};
-use std::time::SystemTime;
-use xmltree::{Element, EmitterConfig};
+use encoding_rs::{Encoding, UTF_8};
+use lazy_static::lazy_static;
+use log::{debug, error, info, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info_span, Span};
+use url::Url;
+use xml::common::Position;
+use xml::reader::{EventReader, XmlEvent};
+use xmltree::{Element, EmitterConfig, ParseError, XMLNode};
 
-/// map from tuple (host, id) to tuple (channel link, item element, timestamp added to map)
+const ATOM_NAMESPACE: &str = "http://www.w3.org/2005/Atom";
+const RSSDEDUPER_NAMESPACE: &str = "https://github.com/Bodobolero/rssdeduper";
+
+lazy_static! {
+    // start tag with attributes, as markup or escaped in <description> and content:encoded
+    static ref TAG_WITH_ATTRIBUTES_REGEX: Regex = Regex::new(
+        r#"(<|&lt;)([A-Za-z][\w:.-]*)((?:\s+[\w:.-]+\s*=\s*"[^"]*")+)\s*(/?)(>|&gt;)"#
+    ).unwrap();
+    static ref ATTRIBUTE_REGEX: Regex = Regex::new(r#"([\w:.-]+)\s*=\s*"([^"]*)""#).unwrap();
+    static ref WHITESPACE_REGEX: Regex = Regex::new(r"\s+").unwrap();
+    // feed files whose write timed out and is still running in the background
+    static ref WRITES_IN_FLIGHT: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// map from tuple (host, id) to tuple (channel link, item element, timestamp added to map);
+/// the modified time of an item is read from its stored element, which keeps its dc:modified
+/// or atom:updated child
 pub type ExistingItemsMap = HashMap<(String, String), (String, Element, SystemTime)>;
 
+/// map from the content signature of an item to tuple (channel link, element with only the
+/// date children of the item, timestamp added to map), for signature_dedup
+pub type SignatureMap = HashMap<String, (String, Element, SystemTime)>;
+
+/// options controlling how items are deduplicated
+#[derive(Clone, Debug, Default)]
+pub struct DedupOptions {
+    /// maximum age of items in hours, 0 means unlimited
+    pub max_age: u64,
+    /// whether common XML defects of feeds are repaired before parsing
+    pub parse_strictness: ParseStrictness,
+    /// age items dated in the future from when they were first seen instead of their date
+    pub clamp_future_dates: bool,
+    /// attribute items to the feed given in their <source url="..."> element instead of
+    /// the channel they were found in
+    pub use_source: bool,
+    /// keep <!DOCTYPE> declarations when parsing, which exposes the parser to entity expansion
+    pub allow_dtd: bool,
+    /// feeds larger than this many bytes are not parsed, 0 means unlimited
+    pub max_document_bytes: usize,
+    /// feeds with more elements than this are not parsed, 0 means unlimited
+    pub max_document_elements: usize,
+    /// feeds whose parsing takes longer are not deduplicated, none means unlimited
+    pub parse_timeout: Option<std::time::Duration>,
+    /// map from feed url to the channel identity used instead of the feed's <channel><link>,
+    /// for sites whose sections all link to the same homepage
+    pub channel_identities: HashMap<String, String>,
+    /// what identifies the channel of feeds without a channel identity
+    pub channel_key: ChannelKey,
+    /// which item to keep if several items of a channel have the same <guid>
+    pub duplicate_guids: KeepDuplicate,
+    /// keep the date of the first appearance of an item on the served item, also if the
+    /// content of a later duplicate is kept, so readers do not notify about it again
+    pub freeze_pubdate: bool,
+    /// order of the items in the deduplicated feed
+    pub item_order: ItemOrder,
+    /// how the <lastBuildDate> of the channel is set in the output
+    pub last_build_date: LastBuildDate,
+    /// item titles longer than this many characters are truncated in the output, 0 means
+    /// unlimited
+    pub max_title_length: usize,
+    /// names of the item children whose text (or href attribute) is the item url the id is
+    /// extracted from, tried in order; empty means <link>
+    pub link_sources: Vec<String>,
+    /// set the channel <generator> to rssdeduper/<version>
+    pub generator: bool,
+    /// add a <rssdeduper:dedup> element with the time of the deduplication and the number of
+    /// removed items to the channel
+    pub dedup_metadata: bool,
+    /// add a <rssdeduper:dedup> element with the time it was first seen and the channel it is
+    /// attributed to to each item
+    pub dedup_provenance: bool,
+    /// key video items by their yt:videoId or media:content url instead of their link, so the
+    /// same video is deduplicated across channels that link it differently
+    pub dedup_media: bool,
+    /// also treat items as duplicates whose normalized title, start of description and
+    /// enclosure match an item seen before, for reposts with a new link and guid
+    pub signature_dedup: bool,
+    /// serve the new version of a known item whose dc:modified or atom:updated date is newer
+    /// than the one of the stored version instead of the stored version, and store it
+    pub update_modified_items: bool,
+    /// only deduplicate items by their <guid>, items without a guid are always kept
+    pub replace_on_guid_only: bool,
+    /// merge the items of the previously served file that the fetched feed no longer lists,
+    /// if they are not older than max_age
+    pub retain_dropped_items: bool,
+    /// how item ids are extracted from the item links
+    pub id_options: ids::IdOptions,
+    /// whether the host is part of the dedup key of items
+    pub item_dedup_scope: DedupScope,
+    /// remove all elements with a namespace prefix (content:, media:, dc:, ...) and all namespace
+    /// declarations from the output, for readers that only understand plain RSS 2.0
+    pub strip_namespaces: bool,
+    /// url prefix of the served feeds, if set the channel <link> and the atom:link rel="self"
+    /// of the output are rewritten to the served url of the feed
+    pub base_url: Option<String>,
+    /// serialize the output without indentation and line breaks, for the smallest files
+    pub compact: bool,
+}
+
+/// whether items are only duplicates of items of the same host or of any host
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupScope {
+    /// items with the same id are duplicates if they are from the same host
+    #[default]
+    Host,
+    /// items with the same id are duplicates whatever their host, e.g. syndicated articles
+    Global,
+}
+
+/// what identifies the channel items are attributed to, so a feed whose channel link changes
+/// (e.g. on a site migration) does not lose its items to its old identity
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChannelKey {
+    /// the channel link of the feed
+    #[default]
+    Link,
+    /// only the host of the channel link, for links that change their path; all feeds on one
+    /// host share a channel, so duplicates across them are not removed
+    Host,
+    /// the url of the feed in the feeds file, which does not change with the feed content
+    FeedUrl,
+}
+
+/// which of several items with the same <guid> within one channel is kept
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeepDuplicate {
+    /// keep the first item in document order
+    #[default]
+    First,
+    /// keep the item with the newest date
+    Newest,
+}
+
+/// order of the items in a deduplicated feed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemOrder {
+    /// keep the order of the fetched feed
+    #[default]
+    Source,
+    /// newest item first
+    Newest,
+    /// oldest item first
+    Oldest,
+    /// newest item first, items with the same date or without a date by their dedup key, so
+    /// a feed that only reorders its items is served unchanged
+    Stable,
+}
+
+/// how feeds with XML defects are parsed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParseStrictness {
+    /// reject feeds that are not well-formed XML
+    #[default]
+    Strict,
+    /// escape bare ampersands and remove control characters that XML does not allow before
+    /// parsing
+    Lenient,
+}
+
+/// how the channel <lastBuildDate> of a deduplicated feed is set
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LastBuildDate {
+    /// keep the one of the fetched feed
+    #[default]
+    Keep,
+    /// the date of the newest item left after deduplication
+    Newest,
+    /// the time the feed is deduplicated, so the file changes in every iteration
+    Now,
+}
+
+/// how fetched content is normalized before it is hashed to detect changes of feeds without
+/// <lastBuildDate>, the served feed is not affected
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContentNormalization {
+    /// hash the content as fetched
+    Off,
+    /// collapse runs of whitespace
+    #[default]
+    Whitespace,
+    /// collapse runs of whitespace and sort the attributes of tags
+    Attributes,
+}
+
+/// how a feed is detected as updated upstream, set per feed with the changeDetect attribute of
+/// its OPML outline
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChangeDetection {
+    /// conditional GET with the ETag, then <lastBuildDate>, then the content hash
+    #[default]
+    Auto,
+    /// only the ETag, a feed without ETag is always processed
+    Etag,
+    /// only <lastBuildDate>, a feed without it is always processed
+    BuildDate,
+    /// only the hash of the normalized content
+    Hash,
+    /// the feed is processed in every iteration
+    Always,
+}
+
+impl ChangeDetection {
+    // the value of a changeDetect attribute, None if it is unknown
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "etag" => Some(Self::Etag),
+            "buildDate" => Some(Self::BuildDate),
+            "hash" => Some(Self::Hash),
+            "always" => Some(Self::Always),
+            _ => None,
+        }
+    }
+}
+
+// body decoded with the charset of its content_type like reqwest's Response::text, UTF-8 if
+// there is none; a byte order mark takes precedence
+fn decode_body(body: &[u8], content_type: Option<&str>) -> String {
+    let encoding = content_type
+        .and_then(|content_type| {
+            content_type.split(';').skip(1).find_map(|parameter| {
+                let (name, value) = parameter.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("charset")
+                    .then(|| value.trim().trim_matches('"'))
+            })
+        })
+        .and_then(|charset| Encoding::for_label(charset.as_bytes()))
+        .unwrap_or(UTF_8);
+    encoding.decode(body).0.into_owned()
+}
+
+// SHA-256 of content after normalization, so cosmetic reflows by the origin do not count as
+// changes
+fn content_hash(content: &str, normalization: ContentNormalization) -> String {
+    let normalized = match normalization {
+        ContentNormalization::Off => Cow::Borrowed(content),
+        ContentNormalization::Whitespace => Cow::Owned(collapse_whitespace(content)),
+        ContentNormalization::Attributes => {
+            let sorted =
+                TAG_WITH_ATTRIBUTES_REGEX.replace_all(content, |caps: &regex::Captures| {
+                    let mut attributes: Vec<_> = ATTRIBUTE_REGEX
+                        .captures_iter(&caps[3])
+                        .map(|attribute| format!("{}=\"{}\"", &attribute[1], &attribute[2]))
+                        .collect();
+                    attributes.sort();
+                    format!(
+                        "{}{} {}{}{}",
+                        &caps[1],
+                        &caps[2],
+                        attributes.join(" "),
+                        &caps[4],
+                        &caps[5]
+                    )
+                });
+            Cow::Owned(collapse_whitespace(&sorted))
+        }
+    };
+    format!("{:x}", Sha256::digest(normalized.as_bytes()))
+}
+
+// characters of the normalized description that are part of the signature
+const SIGNATURE_TEXT_LENGTH: usize = 200;
+
+// the key of item in the signatures: the hash of its title and the start of its description,
+// both in lowercase with runs of other characters than letters and digits collapsed to a space,
+// and of the url of its enclosure; None for items without title and description, which would
+// all have the same signature
+fn signature_key(item: &Element) -> Option<String> {
+    let normalized = |name: &str| {
+        item.get_child(name)
+            .and_then(|child| child.get_text())
+            .map(|text| {
+                text.to_lowercase()
+                    .split(|c: char| !c.is_alphanumeric())
+                    .filter(|word| !word.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .unwrap_or_default()
+    };
+    let title = normalized("title");
+    let text: String = normalized("description")
+        .chars()
+        .take(SIGNATURE_TEXT_LENGTH)
+        .collect();
+    if title.is_empty() && text.is_empty() {
+        return None;
+    }
+    let enclosure = item
+        .get_child("enclosure")
+        .and_then(|enclosure| enclosure.attributes.get("url"))
+        .map(String::as_str)
+        .unwrap_or_default();
+    let signature = Sha256::digest(format!("{}\n{}\n{}", title, text, enclosure).as_bytes());
+    Some(format!("{:x}", signature))
+}
+
+// the signature entry for item, only with its date children so it ages like the item without
+// storing it twice
+fn signature_entry(item: &Element) -> Element {
+    let mut entry = Element::new("item");
+    entry.children = item
+        .children
+        .iter()
+        .filter(|child| {
+            child
+                .as_element()
+                .is_some_and(|child| ITEM_DATE_CHILDREN.contains(&child.name.as_str()))
+        })
+        .cloned()
+        .collect();
+    entry
+}
+
+// collapse runs of whitespace within text to a single space and drop whitespace between
+// elements; content that is no well-formed XML only gets its runs of whitespace collapsed
+fn collapse_whitespace(content: &str) -> String {
+    let Ok(mut root) = Element::parse(content.as_bytes()) else {
+        return WHITESPACE_REGEX.replace_all(content, " ").into_owned();
+    };
+    collapse_text_whitespace(&mut root);
+    let mut collapsed = Vec::new();
+    match root.write_with_config(&mut collapsed, emitter_config(true)) {
+        Ok(()) => String::from_utf8(collapsed).unwrap(),
+        Err(_) => WHITESPACE_REGEX.replace_all(content, " ").into_owned(),
+    }
+}
+
+// the parser already drops whitespace-only text between elements
+fn collapse_text_whitespace(element: &mut Element) {
+    for child in element.children.iter_mut() {
+        match child {
+            XMLNode::Element(child) => collapse_text_whitespace(child),
+            XMLNode::Text(text) | XMLNode::CData(text) => {
+                *text = WHITESPACE_REGEX.replace_all(text, " ").into_owned()
+            }
+            _ => (),
+        }
+    }
+}
+
+/// options controlling how feed files are written
+#[derive(Clone, Debug, Default)]
+pub struct WriteOptions {
+    /// write a sidecar <file>.sha256 with the SHA-256 digest of the feed file
+    pub checksum: bool,
+    /// what to do if the feed has no items
+    pub on_empty: OnEmpty,
+    /// further directories that get a copy of each feed file
+    pub mirror_directories: Vec<String>,
+    /// number of previous versions kept as <file>.bak, <file>.bak.1, ... when a feed file
+    /// is overwritten
+    pub backups: usize,
+    /// fail instead of writing to the file a symlinked feed file points to
+    pub no_follow_symlinks: bool,
+    /// give up waiting for a feed that takes longer to write, it is written in the background
+    pub timeout: Option<std::time::Duration>,
+}
+
+/// how a feed without items is written
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnEmpty {
+    /// write the empty feed
+    #[default]
+    Write,
+    /// do not write the empty feed
+    Skip,
+    /// keep the previous feed file, write the empty feed only if there is none
+    KeepPrevious,
+}
+
 pub struct Feed {
     url: String,
     filename: String,
     content: String,
+    /// the body of the last response as it was received, if it differs from the content it was
+    /// decoded and trimmed to
+    body: Option<Vec<u8>>,
     last_build_date: String,
+    /// hash of the normalized content, to detect changes of feeds without <lastBuildDate>
+    content_hash: String,
+    /// ETag of the last response, sent back for a conditional GET
+    etag: Option<String>,
+    /// validators of the last GET, compared with those of a HEAD request in head-first mode
+    validators: Option<Validators>,
+    change_detection: ChangeDetection,
+    /// headers of the requests for this feed that replace the global ones
+    request_headers: RequestHeaders,
+    /// feeds with a higher priority are fetched and deduplicated first
+    priority: i32,
+    /// maximum age of its items in hours that replaces the one of the options
+    max_age: Option<u64>,
+    /// number of consecutive iterations in which the feed failed
+    failures: u32,
+    /// set when the feed is disabled after too many failures, the time it is enabled again or
+    /// None if it stays disabled until restart
+    disabled: Option<Option<Instant>>,
+    /// the server answered 410 Gone, the feed is not read anymore
+    gone: bool,
+    /// the last response is fresh until then according to its Cache-Control max-age or Expires
+    fresh_until: Option<Instant>,
+    /// the content parsed ahead of deduplication by read_all
+    parsed: Option<Element>,
+    /// time it took to parse the content ahead, counted against the parse timeout
+    parse_time: std::time::Duration,
+}
+
+// item children that carry a date, in order of preference: RSS pubDate, dc:date and the
+// Atom published/updated used by hybrid feeds
+const ITEM_DATE_CHILDREN: [&str; 4] = ["pubDate", "date", "published", "updated"];
+
+// the date children of an item that tell when it was published, not when it was modified
+const PUBLICATION_DATE_CHILDREN: [&str; 3] = ["pubDate", "date", "published"];
+
+// Atom entries are dated by when they were last updated, which each entry has, before when
+// they were published
+const ENTRY_DATE_CHILDREN: [&str; 2] = ["updated", "published"];
+
+// whether element is the Atom element name, an Atom <feed> is deduplicated like an RSS
+// <channel> and its <entry> elements like <item> elements
+fn is_atom(element: &Element, name: &str) -> bool {
+    element.name == name && element.namespace.as_deref() == Some(ATOM_NAMESPACE)
+}
+
+// whether element is an RSS <item> or an Atom <entry>
+fn is_item(element: &Element) -> bool {
+    element.name == "item" || is_atom(element, "entry")
+}
+
+// the element whose children are the items of a parsed feed: the <channel> of RSS or the
+// <feed> root of Atom
+fn item_container(rssroot: &Element) -> Option<&Element> {
+    if is_atom(rssroot, "feed") {
+        Some(rssroot)
+    } else {
+        rssroot.get_child("channel")
+    }
+}
+
+fn item_container_mut(rssroot: &mut Element) -> Option<&mut Element> {
+    if is_atom(rssroot, "feed") {
+        Some(rssroot)
+    } else {
+        rssroot.get_mut_child("channel")
+    }
+}
+
+// all item containers of a parsed feed: each <channel> of RSS or the <feed> root of Atom
+fn item_containers_mut(rssroot: &mut Element) -> Vec<&mut Element> {
+    if is_atom(rssroot, "feed") {
+        return vec![rssroot];
+    }
+    rssroot
+        .children
+        .iter_mut()
+        .filter_map(XMLNode::as_mut_element)
+        .filter(|channel| channel.name == "channel")
+        .collect()
+}
+
+// the items of all channels of a parsed RSS feed or the entries of an Atom feed
+pub(crate) fn feed_items(rssroot: &Element) -> Vec<&Element> {
+    if is_atom(rssroot, "feed") {
+        return rssroot
+            .children
+            .iter()
+            .filter_map(XMLNode::as_element)
+            .filter(|entry| is_item(entry))
+            .collect();
+    }
+    rssroot
+        .children
+        .iter()
+        .filter_map(XMLNode::as_element)
+        .filter(|channel| channel.name == "channel")
+        .flat_map(|channel| channel.children.iter().filter_map(XMLNode::as_element))
+        .filter(|item| item.name == "item")
+        .collect()
+}
+
+// the channel of an Atom feed: its web page, not its rel="self" link, else its id
+fn atom_channel(feed: &Element, options: &DedupOptions) -> Option<String> {
+    item_link(feed, &[])
+        .map(|link| link_channel(&link, options))
+        .or_else(|| Some(feed.get_child("id")?.get_text()?.trim().to_string()))
+}
+
+// items dated more than this many minutes ahead of now are considered to be clock-skewed
+const FUTURE_DATE_TOLERANCE_MINUTES: i64 = 15;
+
+// how far the date of item is ahead of nowutc if that is more than the tolerance, a sign of a
+// feed with a wrong timezone or of a scheduled post
+fn future_date_skew(item: &Element, nowutc: &DateTime<Utc>) -> Option<Duration> {
+    let skew = item_date(item)?.signed_duration_since(*nowutc);
+    (skew > Duration::minutes(FUTURE_DATE_TOLERANCE_MINUTES)).then_some(skew)
 }
 
-// max_age in hours
-fn check_pub_date_not_older_than(pub_date: &str, max_age: u64, nowutc: &DateTime<Utc>) -> bool {
+// max_age in hours; items without any date are aged by first_seen, the time they were first
+// added to the existing items, and kept if that is unknown; items whose date cannot be parsed
+// are not kept; with clamp_future_dates items dated in the future are aged by first_seen or,
+// if that is unknown, now instead of never aging
+fn check_item_not_older_than(
+    item: &Element,
+    first_seen: Option<SystemTime>,
+    max_age: u64,
+    nowutc: &DateTime<Utc>,
+    clamp_future_dates: bool,
+) -> bool {
     if max_age == 0 {
         // 0 == unlimited
         return true;
     }
-    if let Ok(pub_date_utc) =
-        DateTime::parse_from_rfc2822(pub_date).map(|dt| dt.with_timezone(&Utc))
+    let effective_date = if ITEM_DATE_CHILDREN
+        .iter()
+        .all(|name| item.get_child(*name).is_none())
     {
-        if nowutc.signed_duration_since(pub_date_utc) <= Duration::hours(max_age as i64) {
+        match first_seen {
+            Some(first_seen) => Some(DateTime::<Utc>::from(first_seen)),
+            None => return true,
+        }
+    } else if clamp_future_dates && future_date_skew(item, nowutc).is_some() {
+        Some(first_seen.map_or(*nowutc, DateTime::<Utc>::from))
+    } else {
+        item_date(item)
+    };
+    if let Some(effective_date) = effective_date {
+        if nowutc.signed_duration_since(effective_date) <= Duration::hours(max_age as i64) {
             return true;
         }
     }
     false
 }
 
+// RFC 2822 as used by RSS or RFC 3339 as used by dc:date and Atom
+fn parse_date(date: &str) -> Option<DateTime<Utc>> {
+    let date = date.trim();
+    DateTime::parse_from_rfc2822(date)
+        .or_else(|_| DateTime::parse_from_rfc3339(date))
+        .map(|dt| dt.with_timezone(&Utc))
+        .ok()
+}
+
+// the date an item was last modified according to its dc:modified or atom:updated element,
+// None if it has none
+fn item_modified(item: &Element) -> Option<DateTime<Utc>> {
+    ["modified", "updated"].iter().find_map(|name| {
+        item.get_child(*name)
+            .and_then(|date| date.get_text())
+            .and_then(|date| parse_date(&date))
+    })
+}
+
+// the best available date of an item, None if it has no valid date
+fn item_date(item: &Element) -> Option<DateTime<Utc>> {
+    let children: &[&str] = if is_atom(item, "entry") {
+        &ENTRY_DATE_CHILDREN
+    } else {
+        &ITEM_DATE_CHILDREN
+    };
+    children.iter().find_map(|name| {
+        item.get_child(*name)
+            .and_then(|date| date.get_text())
+            .and_then(|date| parse_date(&date))
+    })
+}
+
+// set the <lastBuildDate> of each channel, and its <pubDate> if it has one, to the date of its
+// newest item or to now, the <updated> of an Atom feed likewise; a new date element goes before
+// the first item, channels without dated items keep theirs with Newest
+fn set_last_build_date(rssroot: &mut Element, mode: LastBuildDate) {
+    if mode == LastBuildDate::Keep {
+        return;
+    }
+    for channel in item_containers_mut(rssroot) {
+        let date = match mode {
+            LastBuildDate::Keep => None,
+            LastBuildDate::Newest => channel
+                .children
+                .iter()
+                .filter_map(XMLNode::as_element)
+                .filter(|item| is_item(item))
+                .filter_map(item_date)
+                .max(),
+            LastBuildDate::Now => Some(Utc::now()),
+        };
+        let Some(date) = date else {
+            continue;
+        };
+        let (name, text) = if is_atom(channel, "feed") {
+            ("updated", XMLNode::Text(date.to_rfc3339()))
+        } else {
+            let text = XMLNode::Text(date.to_rfc2822());
+            if let Some(pub_date) = channel.get_mut_child("pubDate") {
+                pub_date.children = vec![text.clone()];
+            }
+            ("lastBuildDate", text)
+        };
+        match channel.get_mut_child(name) {
+            Some(last_build_date) => last_build_date.children = vec![text],
+            None => {
+                let mut last_build_date = Element::new(name);
+                last_build_date.namespace = channel.namespace.clone();
+                last_build_date.children.push(text);
+                let position = channel
+                    .children
+                    .iter()
+                    .position(|child| child.as_element().is_some_and(is_item))
+                    .unwrap_or(channel.children.len());
+                channel
+                    .children
+                    .insert(position, XMLNode::Element(last_build_date));
+            }
+        }
+    }
+}
+
+// sort the items of each channel by date, items without date last; the items take the places
+// of the items before, so other children of the channel keep their position; the stable order
+// needs the channel link the items were deduplicated with for their dedup keys
+fn sort_items(rssroot: &mut Element, channel_link: &str, options: &DedupOptions) {
+    let order = options.item_order;
+    if order == ItemOrder::Source {
+        return;
+    }
+    for channel in item_containers_mut(rssroot) {
+        let is_item_node = |node: &XMLNode| node.as_element().is_some_and(is_item);
+        let mut items: Vec<XMLNode> = channel
+            .children
+            .iter()
+            .filter(|node| is_item_node(node))
+            .cloned()
+            .collect();
+        let date = |node: &XMLNode| node.as_element().and_then(item_date);
+        if order == ItemOrder::Stable {
+            let key = |node: &XMLNode| {
+                let item = node.as_element()?;
+                let link = item_link(item, &options.link_sources)?;
+                Some(item_key(item, &link, channel_link, options))
+            };
+            items.sort_by_cached_key(|node| (Reverse(date(node)), key(node)));
+        } else {
+            // stable sort, so items with the same date keep their order
+            items.sort_by(|a, b| match (date(a), date(b)) {
+                (Some(a), Some(b)) if order == ItemOrder::Newest => b.cmp(&a),
+                (Some(a), Some(b)) => a.cmp(&b),
+                (a, b) => b.is_some().cmp(&a.is_some()),
+            });
+        }
+        let mut items = items.into_iter();
+        for node in channel
+            .children
+            .iter_mut()
+            .filter(|node| is_item_node(node))
+        {
+            *node = items.next().unwrap();
+        }
+    }
+}
+
+// collapse items with the same <guid> (the <id> of Atom entries) within one channel (e.g.
+// overlapping pages of a paginated feed) to a single item; with freeze the kept item gets the
+// dates of the earliest item of its group
+fn remove_duplicate_guids(channel: &mut Element, keep: KeepDuplicate, freeze: bool) {
+    // guid -> (index of the kept item, index of the earliest item)
+    let mut kept: HashMap<String, (usize, usize)> = HashMap::new();
+    let mut removed: Vec<bool> = vec![false; channel.children.len()];
+    for (index, child) in channel.children.iter().enumerate() {
+        let Some(item) = child.as_element().filter(|element| is_item(element)) else {
+            continue;
+        };
+        let Some(guid) = item_guid(item) else {
+            continue;
+        };
+        if let Some((kept_index, earliest_index)) = kept.get_mut(&guid) {
+            let kept_item = channel.children[*kept_index].as_element().unwrap();
+            if keep == KeepDuplicate::Newest && item_date(item) > item_date(kept_item) {
+                removed[*kept_index] = true;
+                *kept_index = index;
+            } else {
+                removed[index] = true;
+            }
+            let earliest_item = channel.children[*earliest_index].as_element().unwrap();
+            if item_date(item).is_some()
+                && (item_date(earliest_item).is_none()
+                    || item_date(item) < item_date(earliest_item))
+            {
+                *earliest_index = index;
+            }
+            info!("Removing item with duplicate guid {} in same channel", guid);
+        } else {
+            kept.insert(guid, (index, index));
+        }
+    }
+    if freeze {
+        for (kept_index, earliest_index) in kept.into_values() {
+            if kept_index != earliest_index {
+                let earliest_item = channel.children[earliest_index]
+                    .as_element()
+                    .unwrap()
+                    .clone();
+                let kept_item = channel.children[kept_index].as_mut_element().unwrap();
+                copy_dates(&earliest_item, kept_item, &ITEM_DATE_CHILDREN);
+            }
+        }
+    }
+    let mut removed = removed.into_iter();
+    channel.children.retain(|_| !removed.next().unwrap());
+}
+
+// replace the date children of item with one of names by the ones of from with the same name
+fn copy_dates(from: &Element, item: &mut Element, names: &[&str]) {
+    for date in item
+        .children
+        .iter_mut()
+        .filter_map(XMLNode::as_mut_element)
+        .filter(|child| names.contains(&child.name.as_str()))
+    {
+        if let Some(from_date) = from.get_child(date.name.as_str()) {
+            *date = from_date.clone();
+        }
+    }
+}
+
+// truncate title to at most max_length characters including the ellipsis, at the last word
+// boundary if there is one; None if it is not longer than max_length
+fn truncate_title(title: &str, max_length: usize) -> Option<String> {
+    if title.chars().count() <= max_length {
+        return None;
+    }
+    let cut: String = title.chars().take(max_length.saturating_sub(1)).collect();
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(boundary) if !cut[..boundary].trim_end().is_empty() => &cut[..boundary],
+        _ => &cut,
+    };
+    Some(format!("{}…", cut.trim_end()))
+}
+
+// truncate the item titles of all channels to max_length characters, only in the output
+fn truncate_titles(rssroot: &mut Element, max_length: usize) {
+    if max_length == 0 {
+        return;
+    }
+    for item in item_containers_mut(rssroot)
+        .into_iter()
+        .flat_map(|channel| {
+            channel
+                .children
+                .iter_mut()
+                .filter_map(XMLNode::as_mut_element)
+        })
+        .filter(|item| is_item(item))
+    {
+        let Some(title) = item.get_mut_child("title") else {
+            continue;
+        };
+        let truncated = title
+            .get_text()
+            .and_then(|text| truncate_title(&text, max_length));
+        if let Some(truncated) = truncated {
+            title.children = vec![XMLNode::Text(truncated)];
+        }
+    }
+}
+
+// remove the elements with a namespace prefix and the namespaces of all other elements with
+// their declarations, so no prefix is left without its declaration
+fn strip_namespaces(element: &mut Element) {
+    element.namespace = None;
+    element.namespaces = None;
+    element.children.retain(|child| {
+        child
+            .as_element()
+            .is_none_or(|child| child.prefix.is_none())
+    });
+    for child in element
+        .children
+        .iter_mut()
+        .filter_map(XMLNode::as_mut_element)
+    {
+        strip_namespaces(child);
+    }
+}
+
+// point the channel <link> and atom:link rel="self" to served_url, item links are not touched
+fn rewrite_channel_links(rssroot: &mut Element, served_url: &str) {
+    let Some(channel) = item_container_mut(rssroot) else {
+        return;
+    };
+    for link in channel
+        .children
+        .iter_mut()
+        .filter_map(XMLNode::as_mut_element)
+        .filter(|child| child.name == "link")
+    {
+        if link.namespace.as_deref() == Some(ATOM_NAMESPACE) {
+            if link.attributes.get("rel").map(String::as_str) == Some("self") {
+                link.attributes
+                    .insert("href".to_string(), served_url.to_string());
+            }
+        } else {
+            link.children = vec![XMLNode::Text(served_url.to_string())];
+        }
+    }
+}
+
+// record in the channel that the feed was processed by rssdeduper: the <generator> and, with
+// metadata, a <rssdeduper:dedup timestamp="..." removed="..."/> element, both replacing
+// the ones of a previous run
+fn annotate_channel(
+    rssroot: &mut Element,
+    generator: bool,
+    metadata: Option<(DateTime<Utc>, usize)>,
+) {
+    let Some(channel) = item_container_mut(rssroot) else {
+        return;
+    };
+    if generator {
+        let text = XMLNode::Text(format!("rssdeduper/{}", env!("CARGO_PKG_VERSION")));
+        match channel.get_mut_child("generator") {
+            Some(generator) => generator.children = vec![text],
+            None => {
+                let mut generator = Element::new("generator");
+                generator.children.push(text);
+                channel.children.push(XMLNode::Element(generator));
+            }
+        }
+    }
+    if let Some((timestamp, removed)) = metadata {
+        set_dedup_element(
+            channel,
+            &[
+                ("timestamp", timestamp.to_rfc3339()),
+                ("removed", removed.to_string()),
+            ],
+        );
+    }
+}
+
+// append a <rssdeduper:dedup> element with attributes to parent, replacing the one it already
+// has, e.g. from the previous run or an upstream rssdeduper
+fn set_dedup_element(parent: &mut Element, attributes: &[(&str, String)]) {
+    parent.children.retain(|child| {
+        child.as_element().is_none_or(|child| {
+            child.name != "dedup" || child.namespace.as_deref() != Some(RSSDEDUPER_NAMESPACE)
+        })
+    });
+    let mut dedup = Element::new("dedup");
+    dedup.prefix = Some("rssdeduper".to_string());
+    dedup.namespace = Some(RSSDEDUPER_NAMESPACE.to_string());
+    let mut namespaces = xmltree::Namespace::empty();
+    namespaces.put("rssdeduper", RSSDEDUPER_NAMESPACE);
+    dedup.namespaces = Some(namespaces);
+    for (name, value) in attributes {
+        dedup.attributes.insert(name.to_string(), value.clone());
+    }
+    parent.children.push(XMLNode::Element(dedup));
+}
+
+// add a <rssdeduper:dedup firstSeen="..." channel="..."/> element to each item of rssroot
+// known to existing_items with the time it was first seen and the channel it is attributed to,
+// so the provenance of the served items can be traced
+fn annotate_items(
+    rssroot: &mut Element,
+    existing_items: &ExistingItemsMap,
+    channel: &str,
+    options: &DedupOptions,
+) {
+    let Some(channel_element) = item_container_mut(rssroot) else {
+        return;
+    };
+    for item in channel_element
+        .children
+        .iter_mut()
+        .filter_map(XMLNode::as_mut_element)
+        .filter(|item| is_item(item))
+    {
+        let Some(link) = item_link(item, &options.link_sources) else {
+            continue;
+        };
+        let Some((item_channel, _, first_seen)) =
+            existing_items.get(&item_key(item, &link, channel, options))
+        else {
+            continue;
+        };
+        let attributes = [
+            ("firstSeen", DateTime::<Utc>::from(*first_seen).to_rfc3339()),
+            ("channel", item_channel.clone()),
+        ];
+        set_dedup_element(item, &attributes);
+    }
+}
+
+// how feeds and OPML files are serialized: pretty printed with four spaces, or compact without
+// any whitespace between elements; the parser already drops whitespace-only text between
+// elements, so the compact output only keeps whitespace within text
+pub fn emitter_config(compact: bool) -> EmitterConfig {
+    EmitterConfig::new()
+        .indent_string("    ")
+        .line_separator("\n")
+        .perform_indent(!compact)
+        .normalize_empty_elements(true)
+}
+
+// an element with name that only contains text
+fn text_element(name: &str, text: &str) -> Element {
+    let mut element = Element::new(name);
+    element.children.push(XMLNode::Text(text.to_string()));
+    element
+}
+
+// the RSS <item> of an Atom entry: its title, link, summary (else content) as description,
+// date and id as guid
+fn entry_to_item(entry: &Element) -> Element {
+    let text = |name: &str| {
+        entry
+            .get_child(name)
+            .and_then(|child| child.get_text())
+            .map(|text| text.trim().to_string())
+            .filter(|text| !text.is_empty())
+    };
+    let mut item = Element::new("item");
+    for (name, text) in [
+        ("title", text("title")),
+        ("link", item_link(entry, &[])),
+        ("description", text("summary").or_else(|| text("content"))),
+        ("pubDate", item_date(entry).map(|date| date.to_rfc2822())),
+        ("guid", item_guid(entry)),
+    ] {
+        if let Some(text) = text {
+            item.children
+                .push(XMLNode::Element(text_element(name, &text)));
+        }
+    }
+    if let Some(guid) = item.get_mut_child("guid") {
+        guid.attributes
+            .insert("isPermaLink".to_string(), "false".to_string());
+    }
+    item
+}
+
+// RSS 2.0 document of a feed generated by rssdeduper itself, served under link
+fn generated_rss(
+    link: &str,
+    title: &str,
+    description: &str,
+    items: impl IntoIterator<Item = Element>,
+) -> Element {
+    let mut channel = Element::new("channel");
+    for (name, text) in [
+        ("title", title),
+        ("link", link),
+        ("description", description),
+    ] {
+        channel
+            .children
+            .push(XMLNode::Element(text_element(name, text)));
+    }
+    channel
+        .children
+        .extend(items.into_iter().map(XMLNode::Element));
+    let mut rssroot = Element::new("rss");
+    rssroot
+        .attributes
+        .insert("version".to_string(), "2.0".to_string());
+    rssroot.children.push(XMLNode::Element(channel));
+    rssroot
+}
+
+// remove a <!DOCTYPE ...> declaration including its internal subset, legitimate feeds do not
+// need it and its entity declarations allow entity expansion ("billion laughs") and external
+// entity attacks; references to the removed entities make the parser reject the document
+fn strip_doctype(content: &str) -> Cow<'_, str> {
+    let Some(start) = find_doctype(content) else {
+        return Cow::Borrowed(content);
+    };
+    let mut quote = None;
+    let mut depth = 0;
+    for (offset, c) in content[start..].char_indices() {
+        match (quote, c) {
+            (Some(q), _) if q == c => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '[') => depth += 1,
+            (None, ']') => depth -= 1,
+            (None, '>') if depth == 0 => {
+                let end = start + offset + 1;
+                return Cow::Owned(format!("{}{}", &content[..start], &content[end..]));
+            }
+            _ => {}
+        }
+    }
+    // unterminated declaration, drop everything after it so it cannot be parsed
+    Cow::Owned(content[..start].to_string())
+}
+
+// the offset of the first <!DOCTYPE of content that is not text of a CDATA section or comment
+fn find_doctype(content: &str) -> Option<usize> {
+    let mut offset = 0;
+    while let Some(found) = content[offset..].find("<!") {
+        let start = offset + found;
+        let rest = &content[start..];
+        let end_marker = if rest.starts_with("<!DOCTYPE") {
+            return Some(start);
+        } else if rest.starts_with("<![CDATA[") {
+            "]]>"
+        } else if rest.starts_with("<!--") {
+            "-->"
+        } else {
+            offset = start + 2;
+            continue;
+        };
+        offset = start + rest.find(end_marker)? + end_marker.len();
+    }
+    None
+}
+
+// repair the XML defects of content that are common in feeds: ampersands that do not start an
+// entity or character reference are escaped, except in CDATA sections and comments where they
+// are allowed, and control characters other than tab, line feed and carriage return are removed
+fn sanitize_xml(content: &str) -> Cow<'_, str> {
+    let is_reference = |rest: &str| {
+        let Some((end, _)) = rest.char_indices().take(33).find(|(_, c)| *c == ';') else {
+            return false;
+        };
+        let name = &rest[1..end];
+        if let Some(digits) = name.strip_prefix("#x") {
+            !digits.is_empty() && digits.chars().all(|c| c.is_ascii_hexdigit())
+        } else if let Some(digits) = name.strip_prefix('#') {
+            !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+        } else {
+            name.chars()
+                .next()
+                .is_some_and(|c| c.is_alphabetic() || c == '_')
+                && name
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        }
+    };
+    let is_invalid = |c: char| {
+        (c.is_control() && !matches!(c, '\t' | '\n' | '\r') && (c as u32) < 0x80)
+            || c == '\u{fffe}'
+            || c == '\u{ffff}'
+    };
+    if !content.contains('&') && !content.contains(is_invalid) {
+        return Cow::Borrowed(content);
+    }
+    let mut sanitized = String::with_capacity(content.len() + 64);
+    let mut rest = content;
+    while let Some(position) = rest.find(['&', '<']) {
+        sanitized.push_str(&rest[..position]);
+        rest = &rest[position..];
+        let verbatim_end = [("<![CDATA[", "]]>"), ("<!--", "-->")]
+            .iter()
+            .find(|(start, _)| rest.starts_with(start))
+            .map(|(_, end)| rest.find(end).map_or(rest.len(), |index| index + end.len()));
+        if let Some(end) = verbatim_end {
+            sanitized.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if rest.starts_with('&') && !is_reference(rest) {
+            sanitized.push_str("&amp;");
+            rest = &rest[1..];
+        } else {
+            sanitized.push_str(&rest[..1]);
+            rest = &rest[1..];
+        }
+    }
+    sanitized.push_str(rest);
+    sanitized.retain(|c| !is_invalid(c));
+    if sanitized == content {
+        Cow::Borrowed(content)
+    } else {
+        Cow::Owned(sanitized)
+    }
+}
+
+// the channel an item is attributed to for deduplication: its <source url="..."> if
+// use_source is set and the item has one, otherwise the channel it was found in
+fn item_channel(item: &Element, channel: &str, use_source: bool) -> String {
+    if use_source {
+        if let Some(url) = item
+            .get_child("source")
+            .and_then(|source| source.attributes.get("url"))
+        {
+            return url.to_string();
+        }
+    }
+    channel.to_string()
+}
+
+// describe an XML parse error of content with its line, column and the text around it, so
+// the offending markup can be found in a large feed
+pub fn describe_parse_error(content: &str, error: &ParseError) -> String {
+    let ParseError::MalformedXml(error) = error else {
+        return error.to_string();
+    };
+    let position = error.position();
+    let line = content
+        .lines()
+        .nth(position.row as usize)
+        .unwrap_or_default();
+    let excerpt: String = line
+        .chars()
+        .skip((position.column as usize).saturating_sub(40))
+        .take(80)
+        .collect();
+    format!(
+        "line {}, column {}: {} near `{}`",
+        position.row + 1,
+        position.column + 1,
+        error.msg(),
+        excerpt.trim()
+    )
+}
+
+// number of items in the channels of an rss element or of entries of an Atom feed
+fn count_items(rssroot: &Element) -> usize {
+    feed_items(rssroot).len()
+}
+
+/// receives each deduplication decision, so the tests check decisions without parsing the log;
+/// all methods do nothing by default
+pub(crate) trait DedupObserver {
+    /// the item with link is new and kept in channel
+    fn on_kept(&mut self, _link: &str, _channel: &str) {}
+    /// the item with link is removed from channel because previous_channel published it first
+    fn on_removed_cross_channel(&mut self, _link: &str, _channel: &str, _previous_channel: &str) {}
+    /// the item with link is removed because it is older than the maximum age
+    fn on_aged_out(&mut self, _link: &str) {}
+    /// the item with link was seen before in channel and is replaced by its first version
+    fn on_replaced(&mut self, _link: &str, _channel: &str) {}
+    /// the item with link was seen before in channel and is kept because it was modified since
+    fn on_updated(&mut self, _link: &str, _channel: &str) {}
+    /// the item with link is dated in the future by more than the tolerance
+    fn on_future_date(&mut self, _link: &str) {}
+}
+
+/// observer that ignores all decisions
+pub(crate) struct NoopObserver;
+
+impl DedupObserver for NoopObserver {}
+
+const YOUTUBE_NAMESPACE: &str = "http://www.youtube.com/xml/schemas/2015";
+const MEDIA_NAMESPACE: &str = "http://search.yahoo.com/mrss/";
+
+// the video of an item as (id, host) key: its yt:videoId, else the url of the first
+// media:content that is not an image, directly in the item or in a media:group
+fn media_key(item: &Element, options: &DedupOptions) -> Option<(String, String)> {
+    if let Some(video_id) = item
+        .get_child(("videoId", YOUTUBE_NAMESPACE))
+        .and_then(|video_id| video_id.get_text())
+    {
+        return Some((video_id.trim().to_string(), "www.youtube.com".to_string()));
+    }
+    let group = item.get_child(("group", MEDIA_NAMESPACE));
+    [Some(item), group]
+        .into_iter()
+        .flatten()
+        .flat_map(|parent| parent.children.iter().filter_map(XMLNode::as_element))
+        .filter(|content| {
+            content.name == "content" && content.namespace.as_deref() == Some(MEDIA_NAMESPACE)
+        })
+        .filter(|content| {
+            content.attributes.get("medium").map(String::as_str) != Some("image")
+                && !content
+                    .attributes
+                    .get("type")
+                    .is_some_and(|mime| mime.starts_with("image/"))
+        })
+        .find_map(|content| content.attributes.get("url"))
+        .and_then(|url| ids::extract_unique_id_and_host_from_url_string(url, &options.id_options))
+}
+
+// the url of an item from the first of the sources (child element names, <link> if empty) that
+// holds an absolute url, as text or href attribute (atom:link); if none does, the first value
+// found at all, and an empty link if the sources are present but empty, as the items were
+// always keyed; links with a rel other than alternate (self, replies, ...) do not count
+pub(crate) fn item_link(item: &Element, sources: &[String]) -> Option<String> {
+    let default_sources = ["link".to_string()];
+    let sources = if sources.is_empty() {
+        &default_sources[..]
+    } else {
+        sources
+    };
+    let children: Vec<&Element> = sources
+        .iter()
+        .flat_map(|source| {
+            item.children
+                .iter()
+                .filter_map(XMLNode::as_element)
+                .filter(move |child| &child.name == source)
+        })
+        .filter(|child| {
+            child
+                .attributes
+                .get("rel")
+                .is_none_or(|rel| rel == "alternate")
+        })
+        .collect();
+    let values: Vec<String> = children
+        .iter()
+        .filter_map(|child| {
+            child
+                .get_text()
+                .map(|text| text.trim().to_string())
+                .filter(|text| !text.is_empty())
+                .or_else(|| child.attributes.get("href").cloned())
+        })
+        .collect();
+    values
+        .iter()
+        .find(|value| Url::parse(value).is_ok())
+        .or(values.first())
+        .cloned()
+        .or_else(|| (!children.is_empty()).then(String::new))
+}
+
+// whether link is the value of a <guid isPermaLink="false">, an opaque id that is not a url
+// even if it looks like one; a guid without the attribute is a permalink
+fn is_opaque_guid(item: &Element, link: &str) -> bool {
+    item.children
+        .iter()
+        .filter_map(XMLNode::as_element)
+        .filter(|child| child.name == "guid")
+        .any(|guid| {
+            guid.attributes
+                .get("isPermaLink")
+                .is_some_and(|permalink| permalink.trim().eq_ignore_ascii_case("false"))
+                && guid.get_text().is_some_and(|text| text.trim() == link)
+        })
+}
+
+// the host of the channel link, or the channel itself if it is not a url (a channel identity)
+fn channel_host(channel: &str) -> String {
+    Url::parse(channel)
+        .ok()
+        .and_then(|url| url.host_str().map(ids::normalize_host))
+        .unwrap_or_else(|| channel.to_string())
+}
+
+// the trimmed text of the <guid> of item or the <id> of an Atom entry, None if it has none
+fn item_guid(item: &Element) -> Option<String> {
+    item.get_child("guid")
+        .or_else(|| {
+            is_atom(item, "entry")
+                .then(|| item.get_child("id"))
+                .flatten()
+        })
+        .and_then(|guid| guid.get_text())
+        .map(|guid| guid.trim().to_string())
+        .filter(|guid| !guid.is_empty())
+}
+
+// the (id, host) key an item with link found in channel is deduplicated by, with an empty host
+// for the global dedup scope
+fn item_key(item: &Element, link: &str, channel: &str, options: &DedupOptions) -> (String, String) {
+    let (id, host) = host_item_key(item, link, channel, options);
+    match options.item_dedup_scope {
+        DedupScope::Host => (id, host),
+        DedupScope::Global => (id, String::new()),
+    }
+}
+
+// the (id, host) key of an item within its host; opaque guids and links that are no urls are
+// used as they are, scoped by the host of the channel; with replace_on_guid_only an item with
+// a guid is keyed by the guid alone, a permalink by its url; an id found in the title by the
+// title pattern of the host of the link takes precedence over the id of the link
+fn host_item_key(
+    item: &Element,
+    link: &str,
+    channel: &str,
+    options: &DedupOptions,
+) -> (String, String) {
+    if options.replace_on_guid_only {
+        if let Some(guid) = item_guid(item) {
+            return match Url::parse(&guid) {
+                Ok(url) if !is_opaque_guid(item, &guid) => (
+                    url.to_string(),
+                    url.host_str().map(ids::normalize_host).unwrap_or_default(),
+                ),
+                _ => (guid, channel_host(channel)),
+            };
+        }
+    }
+    let opaque = || (link.to_string(), channel_host(channel));
+    options
+        .dedup_media
+        .then(|| media_key(item, options))
+        .flatten()
+        .or_else(|| {
+            let title = item.get_child("title")?.get_text()?;
+            ids::extract_unique_id_and_host_from_title(link, title.trim(), &options.id_options)
+        })
+        .or_else(|| is_opaque_guid(item, link).then(opaque))
+        .or_else(|| ids::extract_unique_id_and_host_from_url_string(link, &options.id_options))
+        .unwrap_or_else(opaque)
+}
+
 fn traverse_and_modify(
     element: &mut Element,
     existing_items: &mut ExistingItemsMap,
+    signatures: &mut SignatureMap,
     channel: &mut String,
-    max_age: u64, // in hours
+    channel_identity: Option<&str>,
+    options: &DedupOptions,
+    observer: &mut dyn DedupObserver,
 ) -> Result<(), String> {
     let now = SystemTime::now();
     let nowutc = Local::now().with_timezone(&Utc);
     // find our own channel name and save it
     if element.name == "channel" {
-        if let Some(link) = element.get_child("link") {
+        if let Some(identity) = channel_identity {
+            *channel = identity.to_string();
+        } else if let Some(link) = element.get_child("link") {
             if let Some(text) = link.get_text() {
-                *channel = text.to_string();
+                *channel = link_channel(&text, options);
             } else {
                 debug!("Channel link text is empty, trying href");
-                *channel = link
+                let href = link
                     .attributes
                     .get("href")
-                    .ok_or("Channel link href is empty, too")?
-                    .to_string();
+                    .ok_or("Channel link href is empty, too")?;
+                *channel = link_channel(href, options);
             }
         } else {
             return Err("Channel link is missing".to_string());
         }
+        remove_duplicate_guids(element, options.duplicate_guids, options.freeze_pubdate);
+    } else if is_atom(element, "feed") {
+        *channel = match channel_identity {
+            Some(identity) => identity.to_string(),
+            None => {
+                atom_channel(element, options).ok_or("Atom feed has neither a link nor an id")?
+            }
+        };
+        remove_duplicate_guids(element, options.duplicate_guids, options.freeze_pubdate);
     }
-    if element.name == "item" {
-        if let Some(link) = element.get_child("link") {
-            let id = ids::extract_unique_id_and_host_from_url_string(
-                &link.get_text().unwrap_or_default(),
-            )
-            .unwrap_or_default();
-            if let Some(existing) = existing_items.get(&id) {
-                if existing.0 == *channel {
+    // in strict mode items without a guid are neither recorded nor removed
+    let is_deduplicated =
+        |item: &Element| !options.replace_on_guid_only || item_guid(item).is_some();
+    if is_item(element) && is_deduplicated(element) {
+        let item_channel = item_channel(element, channel, options.use_source);
+        if let Some(link) = item_link(element, &options.link_sources) {
+            let id = item_key(element, &link, channel, options);
+            if let Some(existing) = existing_items.get_mut(&id) {
+                if existing.0 == item_channel
+                    && options.update_modified_items
+                    && item_modified(element) > item_modified(&existing.1)
+                {
+                    // the stored version keeps its first seen time so it ages as before
+                    info!(
+                        "Updating item {} modified since it was seen in channel {}",
+                        link, item_channel
+                    );
+                    observer.on_updated(&link, &item_channel);
+                    if options.freeze_pubdate {
+                        // the modification date is kept, so the item is not updated again
+                        copy_dates(&existing.1, element, &PUBLICATION_DATE_CHILDREN);
+                    }
+                    existing.1 = element.clone();
+                } else if existing.0 == item_channel {
                     info!(
                         "Replacing duplicate item {} in same channel {}",
-                        link.get_text().unwrap_or_default(),
-                        channel
+                        link, item_channel
                     );
+                    observer.on_replaced(&link, &item_channel);
                     element.children.clear();
                     for node in &existing.1.children {
                         element.children.push(node.clone());
@@ -79,49 +1404,75 @@ fn traverse_and_modify(
                 }
             } else {
                 // if not yet existing insert it
-                existing_items.insert(id, (channel.clone(), element.clone(), now));
+                existing_items.insert(id, (item_channel.clone(), element.clone(), now));
+            }
+            if options.signature_dedup {
+                if let Some(key) = signature_key(element) {
+                    signatures
+                        .entry(key)
+                        .or_insert_with(|| (item_channel, signature_entry(element), now));
+                }
             }
         }
     }
 
     element.children.retain(|child| {
         if let Some(child_element) = child.as_element() {
-            if child_element.name == "item" {
-                
-                if let Some(link) = child_element.get_child("link") {
+            if is_item(child_element) && is_deduplicated(child_element) {
+
+                if let Some(link) = item_link(child_element, &options.link_sources) {
+                    let id = item_key(child_element, &link, channel, options);
                     // remove old items first
-                    if let Some(pubdate) = child_element.get_child("pubDate"){
-                        if !check_pub_date_not_older_than(&pubdate.get_text().unwrap_or_default(), max_age, &nowutc){
-                            info!("Removing old item {} with pubDate {}", link.get_text().unwrap_or_default(), pubdate.get_text().unwrap_or_default());
-                            return false;
-                        }
+                    let first_seen = existing_items.get(&id).map(|existing| existing.2);
+                    if let Some(skew) = future_date_skew(child_element, &nowutc) {
+                        warn!("Item {} is dated {} minutes in the future", link, skew.num_minutes());
+                        observer.on_future_date(&link);
+                    }
+                    if !check_item_not_older_than(child_element, first_seen, options.max_age, &nowutc, options.clamp_future_dates){
+                        info!("Removing old item {} with date {:?}", link, item_date(child_element));
+                        observer.on_aged_out(&link);
+                        return false;
                     }
-                    let id = ids::extract_unique_id_and_host_from_url_string(
-                        &link.get_text().unwrap_or_default(),
-                    ).unwrap_or_default();
+                    let item_channel = item_channel(child_element, channel, options.use_source);
                     if let Some(existing) = existing_items.get(&id) {
-                        if existing.0 == *channel {
+                        if existing.0 == item_channel {
                             debug!(
                                 "Keping duplicate item {} from same channel {} to be replaced later",
-                                link.get_text().unwrap_or_default(),
-                                channel
+                                link,
+                                item_channel
                             );
                             return true;
                         } else {
                             info!(
                                 "Removing duplicate item {}, previous channel {}, current channel {}",
-                                link.get_text().unwrap_or_default(),
+                                link,
                                 existing.0,
-                                channel
+                                item_channel
                             );
+                            observer.on_removed_cross_channel(&link, &item_channel, &existing.0);
                             return false;
                         }
+                    } else if let Some(existing) = options
+                        .signature_dedup
+                        .then(|| signature_key(child_element))
+                        .flatten()
+                        .and_then(|key| signatures.get(&key))
+                        .filter(|existing| existing.0 != item_channel)
+                    {
+                        info!(
+                            "Removing item {} with the content of an item of channel {}",
+                            link,
+                            existing.0
+                        );
+                        observer.on_removed_cross_channel(&link, &item_channel, &existing.0);
+                        return false;
                     } else {
                         debug!(
                             "Keeping new item {} from channel {}",
-                            link.get_text().unwrap_or_default(),
+                            link,
                             channel
                         );
+                        observer.on_kept(&link, &item_channel);
                         return true;
                     }
                 }
@@ -133,215 +1484,4275 @@ fn traverse_and_modify(
     // Recursively modify child elements
     for child in element.children.iter_mut() {
         if let Some(child_element) = child.as_mut_element() {
-            traverse_and_modify(child_element, existing_items, channel, max_age)?;
+            traverse_and_modify(
+                child_element,
+                existing_items,
+                signatures,
+                channel,
+                channel_identity,
+                options,
+                observer,
+            )?;
         }
     }
     Ok(())
 }
 
-impl Feed {
-    pub fn new(url: &str, filename: &str) -> Self {
-        Self {
-            url: url.to_string(),
-            filename: filename.to_string(),
-            content: String::new(),
-            last_build_date: String::new(),
-        }
+// remove the existing items or signatures whose own date is older than max_age hours, they are
+// filtered by age anyway and would otherwise be kept until the cache history runs out; items
+// without a date are left to the cache history pruning because their first seen time is all
+// that tells their age
+pub fn evict_outdated_items<K>(
+    existing_items: &mut HashMap<K, (String, Element, SystemTime)>,
+    max_age: u64,
+    clamp_future_dates: bool,
+) {
+    if max_age == 0 {
+        return;
     }
-
-    // read the content of the stream into an internal String and return if the feed has been updated
-    // from the last time it was read
-    pub fn read(&mut self) -> Result<bool, String> {
-        let response = reqwest::blocking::get(&self.url)
-            .map_err(|e| format!("Feed {} cannot be read: {}", self.url, e))?;
-        self.content = response
-            .text()
-            .map_err(|e| format!("Feed {} cannot be read: {}", self.url, e))?;
-        // use simple parsing for lastbuildDate to avoid full xml parsing if content hasn't changed
-        for line in self.content.lines() {
-            if line.trim_start().starts_with("<lastBuildDate>") {
-                let modified = self.last_build_date != line.trim();
-                self.last_build_date = line.trim().to_string();
-                info!(
-                    "Feed has {}been updated: {}",
-                    if modified { "" } else { "not " },
-                    self.url,
-                );
-                return Ok(modified);
-            }
-        }
-        // if we don't have lastBuildDate we assume it has been updated and refresh in each iteration
-        Ok(true)
+    let nowutc = Local::now().with_timezone(&Utc);
+    let before = existing_items.len();
+    existing_items.retain(|_, (_, item, first_seen)| {
+        item_date(item).is_none()
+            || check_item_not_older_than(
+                item,
+                Some(*first_seen),
+                max_age,
+                &nowutc,
+                clamp_future_dates,
+            )
+    });
+    if existing_items.len() < before {
+        debug!(
+            "Evicted {} items older than {} hours",
+            before - existing_items.len(),
+            max_age
+        );
     }
+}
 
-    /*
-    - for each item in the feed
-    - create the ID for the item
+// number of entries and an estimate of the bytes they retain: the serialized size of the items
+// plus their keys and channels, to size the process
+pub fn existing_items_usage(existing_items: &ExistingItemsMap) -> (usize, usize) {
+    let bytes = existing_items
+        .iter()
+        .map(|((id, host), (channel, item, _))| {
+            let mut xml = Vec::new();
+            let _ = item.write(&mut xml);
+            id.len() + host.len() + channel.len() + xml.len()
+        })
+        .sum();
+    (existing_items.len(), bytes)
+}
+
+// one entry of the existing items in the state file
+#[derive(Serialize, Deserialize)]
+struct StateEntry {
+    id: String,
+    host: String,
+    channel: String,
+    /// the item element as XML
+    item: String,
+    /// seconds since the epoch when the item was first seen
+    first_seen: u64,
+    /// the entry maps the content signature in id to the item, it has no host
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    signature: bool,
+}
+
+// load the existing items and signatures saved by save_existing_items, so that a restart does
+// not forget which channel published an item first
+pub fn load_existing_items(filename: &str) -> Result<(ExistingItemsMap, SignatureMap), String> {
+    let content = fs::read_to_string(filename)
+        .map_err(|e| format!("State file {} cannot be read: {}", filename, e))?;
+    let entries: Vec<StateEntry> = serde_json::from_str(&content)
+        .map_err(|e| format!("State file {} cannot be parsed: {}", filename, e))?;
+    let mut existing_items = ExistingItemsMap::new();
+    let mut signatures = SignatureMap::new();
+    for entry in entries {
+        let item = Element::parse(entry.item.as_bytes())
+            .map_err(|e| format!("State file {} item parse error: {}", filename, e))?;
+        let first_seen = UNIX_EPOCH + std::time::Duration::from_secs(entry.first_seen);
+        if entry.signature {
+            signatures.insert(entry.id, (entry.channel, item, first_seen));
+        } else {
+            existing_items.insert((entry.id, entry.host), (entry.channel, item, first_seen));
+        }
+    }
+    Ok((existing_items, signatures))
+}
+
+pub fn save_existing_items(
+    filename: &str,
+    existing_items: &ExistingItemsMap,
+    signatures: &SignatureMap,
+) -> Result<(), String> {
+    let mut entries = Vec::with_capacity(existing_items.len() + signatures.len());
+    let all_entries = existing_items
+        .iter()
+        .map(|((id, host), value)| (id, host.as_str(), value, false))
+        .chain(
+            signatures
+                .iter()
+                .map(|(signature, value)| (signature, "", value, true)),
+        );
+    for (id, host, (channel, item, first_seen), signature) in all_entries {
+        let mut xml = Vec::new();
+        item.write(&mut xml)
+            .map_err(|e| format!("Item {} cannot be serialized: {}", id, e))?;
+        entries.push(StateEntry {
+            id: id.clone(),
+            host: host.to_string(),
+            channel: channel.clone(),
+            item: String::from_utf8_lossy(&xml).to_string(),
+            first_seen: first_seen
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            signature,
+        });
+    }
+    let json = serde_json::to_string(&entries)
+        .map_err(|e| format!("State cannot be serialized: {}", e))?;
+    let tmp_filename = write_temporary_file(filename, json.as_bytes())?;
+    fs::rename(&tmp_filename, filename)
+        .map_err(|e| format!("File {} cannot be renamed: {}", tmp_filename, e))
+}
+
+// read the feeds, up to threads at the same time, and parse the updated ones; returns the result
+// of read for each feed in the order of feeds; only fetching and parsing run in parallel, the
+// deduplication of the updated feeds is left to the caller in a fixed order so that its result
+// does not depend on which feed was fetched first
+pub fn read_all(
+    feeds: &mut [&mut Feed],
+    fetcher: &Fetcher,
+    normalization: ContentNormalization,
+    options: &DedupOptions,
+    threads: usize,
+) -> Vec<Result<bool, String>> {
+    // the reading threads do not inherit the span of the caller
+    let parent = Span::current();
+    let read = |feed: &mut Feed| {
+        let _span = info_span!(parent: &parent, "read_feed", url = feed.url()).entered();
+        let result = feed.read(fetcher, normalization);
+        if result == Ok(true) {
+            feed.parse(options);
+        }
+        result
+    };
+    if threads <= 1 {
+        return feeds.iter_mut().map(|feed| read(feed)).collect();
+    }
+    let mut results: Vec<Option<Result<bool, String>>> = vec![None; feeds.len()];
+    let queue = Mutex::new(feeds.iter_mut().enumerate());
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.min(results.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut read_feeds = Vec::new();
+                    loop {
+                        let next = queue.lock().unwrap().next();
+                        let Some((index, feed)) = next else {
+                            return read_feeds;
+                        };
+                        read_feeds.push((index, read(feed)));
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            for (index, result) in worker.join().unwrap() {
+                results[index] = Some(result);
+            }
+        }
+    });
+    results.into_iter().map(Option::unwrap).collect()
+}
+
+// deduplicate the in-memory content of feeds, (channel url, content) pairs, against each other
+// in their order with fresh maps of existing items and signatures, without any network or file
+// I/O; returns the deduplicated content of each feed
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) fn dedup_batch(
+    feeds: &[(String, String)],
+    options: &DedupOptions,
+) -> Result<Vec<(String, String)>, String> {
+    let mut existing_items: ExistingItemsMap = HashMap::new();
+    let mut signatures: SignatureMap = HashMap::new();
+    feeds
+        .iter()
+        .map(|(url, content)| {
+            let mut feed = Feed::new(url, "");
+            feed.content = content.clone();
+            feed.remove_duplicates(&mut existing_items, &mut signatures, options)?;
+            Ok((feed.url, feed.content))
+        })
+        .collect()
+}
+
+// the channel identity configured for the feed at url, else its url with ChannelKey::FeedUrl;
+// None if the channel is taken from the channel link
+fn channel_identity(url: &str, options: &DedupOptions) -> Option<String> {
+    let url = ids::normalize_url(url);
+    options
+        .channel_identities
+        .iter()
+        .find(|(feed_url, _)| ids::normalize_url(feed_url) == url)
+        .map(|(_, identity)| identity.clone())
+        .or_else(|| (options.channel_key == ChannelKey::FeedUrl).then_some(url))
+}
+
+// the channel of a feed with channel link: the link, or only its host with ChannelKey::Host
+fn link_channel(link: &str, options: &DedupOptions) -> String {
+    match options.channel_key {
+        ChannelKey::Host => channel_host(link),
+        ChannelKey::Link | ChannelKey::FeedUrl => link.to_string(),
+    }
+}
+
+// pre-populate existing_items with the items of the served files of feeds, (url, filename)
+// pairs of the feeds file, so that duplicates are removed from the first iteration after a
+// restart without state; items already known or older than max_age are skipped and a feed
+// whose channel link was rewritten by base_url is skipped because its channel is unknown;
+// returns the number of items added
+pub fn warm_existing_items(
+    existing_items: &mut ExistingItemsMap,
+    feeds: &[(String, String)],
+    target_directory: &str,
+    options: &DedupOptions,
+) -> usize {
+    let now = SystemTime::now();
+    let nowutc = Local::now().with_timezone(&Utc);
+    let mut added = 0;
+    for (url, filename) in feeds {
+        let path = Path::new(target_directory).join(filename);
+        let Ok(content) = fs::read_to_string(&path) else {
+            debug!("No served file {} to warm from", path.display());
+            continue;
+        };
+        let rssroot = match Element::parse(strip_doctype(&content).as_bytes()) {
+            Ok(rssroot) => rssroot,
+            Err(e) => {
+                warn!("Served file {} cannot be parsed: {}", path.display(), e);
+                continue;
+            }
+        };
+        let Some(channel_element) = item_container(&rssroot) else {
+            continue;
+        };
+        let basename = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let served_url = options
+            .base_url
+            .as_ref()
+            .map(|base_url| format!("{}{}", base_url, basename));
+        // the links of Atom feeds are not rewritten to the served url
+        let channel = if let Some(identity) = channel_identity(url, options) {
+            Some(identity)
+        } else if is_atom(channel_element, "feed") {
+            atom_channel(channel_element, options)
+        } else {
+            channel_element
+                .get_child("link")
+                .and_then(|link| {
+                    link.get_text()
+                        .map(|text| text.to_string())
+                        .or_else(|| link.attributes.get("href").cloned())
+                })
+                .filter(|link| served_url.as_ref() != Some(link))
+                .map(|link| link_channel(&link, options))
+        };
+        let Some(channel) = channel else {
+            warn!(
+                "Served file {} has no original channel link, its items are not warmed",
+                path.display()
+            );
+            continue;
+        };
+        for item in channel_element
+            .children
+            .iter()
+            .filter_map(XMLNode::as_element)
+            .filter(|item| is_item(item))
+        {
+            let Some(link) = item_link(item, &options.link_sources) else {
+                continue;
+            };
+            let id = item_key(item, &link, &channel, options);
+            if existing_items.contains_key(&id)
+                || !check_item_not_older_than(
+                    item,
+                    None,
+                    options.max_age,
+                    &nowutc,
+                    options.clamp_future_dates,
+                )
+            {
+                continue;
+            }
+            let item_channel = item_channel(item, &channel, options.use_source);
+            existing_items.insert(id, (item_channel, item.clone(), now));
+            added += 1;
+        }
+    }
+    added
+}
+
+// reads between two checks of the parse deadline, the XML parser reads one byte at a time
+const PARSE_DEADLINE_CHECK_READS: usize = 4096;
+
+// the content of a feed for Element::parse that fails once deadline has passed, so a parse
+// that takes too long is abandoned while it runs instead of being detected when it is done
+struct DeadlineReader<'a> {
+    content: &'a [u8],
+    deadline: Instant,
+    reads: usize,
+    timed_out: bool,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reads += 1;
+        if self.reads.is_multiple_of(PARSE_DEADLINE_CHECK_READS) && Instant::now() > self.deadline {
+            self.timed_out = true;
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "parse deadline passed",
+            ));
+        }
+        self.content.read(buf)
+    }
+}
+
+// bring the map from feed url to Feed in line with the (url, filename) pairs of a (re)loaded
+// feeds file: feeds whose mapping is unchanged keep their Feed and thus their change detection
+// state, feeds with a new filename get a fresh Feed and removed feeds are dropped
+pub fn update_feed_map(
+    feed_map: &mut HashMap<String, Feed>,
+    feeds: &[(String, String)],
+    target_directory: &str,
+) {
+    feed_map.retain(|url, _| feeds.iter().any(|(feed_url, _)| feed_url == url));
+    for (url, filename) in feeds {
+        let fully_qualified_filename = Path::new(target_directory).join(filename);
+        // if the target directory or filename is not valid we want to panic!
+        let fully_qualified_filename = fully_qualified_filename.to_str().unwrap();
+        match feed_map.get(url) {
+            Some(feed) if feed.filename == fully_qualified_filename => {}
+            Some(_) => {
+                info!("Feed {} moved to file {}", url, fully_qualified_filename);
+                feed_map.insert(url.clone(), Feed::new(url, fully_qualified_filename));
+            }
+            None => {
+                feed_map.insert(url.clone(), Feed::new(url, fully_qualified_filename));
+            }
+        }
+    }
+}
+
+impl Feed {
+    pub fn new(url: &str, filename: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            filename: filename.to_string(),
+            content: String::new(),
+            body: None,
+            last_build_date: String::new(),
+            content_hash: String::new(),
+            etag: None,
+            validators: None,
+            change_detection: ChangeDetection::default(),
+            request_headers: RequestHeaders::default(),
+            priority: 0,
+            max_age: None,
+            failures: 0,
+            disabled: None,
+            gone: false,
+            fresh_until: None,
+            parsed: None,
+            parse_time: std::time::Duration::ZERO,
+        }
+    }
+
+    // false while the feed is disabled after too many consecutive failures
+    pub fn is_enabled(&self, now: Instant) -> bool {
+        match self.disabled {
+            None => true,
+            Some(until) => until.is_some_and(|until| now >= until),
+        }
+    }
+
+    // count a failed iteration and disable the feed for backoff (None means until restart) once
+    // it failed max_failures times in a row, 0 never disables it; after the backoff a single
+    // further failure disables the feed again; returns true if the feed was disabled
+    pub fn record_failure(
+        &mut self,
+        max_failures: u32,
+        backoff: Option<std::time::Duration>,
+        now: Instant,
+    ) -> bool {
+        self.failures += 1;
+        if max_failures == 0 || self.failures < max_failures {
+            return false;
+        }
+        self.disabled = Some(backoff.and_then(|backoff| now.checked_add(backoff)));
+        true
+    }
+
+    pub fn record_success(&mut self) {
+        self.failures = 0;
+        self.disabled = None;
+    }
+
+    pub fn set_change_detection(&mut self, change_detection: ChangeDetection) {
+        self.change_detection = change_detection;
+    }
+
+    pub fn set_request_headers(&mut self, request_headers: RequestHeaders) {
+        self.request_headers = request_headers;
+    }
+
+    pub fn set_priority(&mut self, priority: i32) {
+        self.priority = priority;
+    }
+
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    pub fn set_max_age(&mut self, max_age: Option<u64>) {
+        self.max_age = max_age;
+    }
+
+    pub fn is_gone(&self) -> bool {
+        self.gone
+    }
+
+    // the feed has been permanently removed by its server and is not read anymore
+    pub fn mark_gone(&mut self) {
+        self.gone = true;
+    }
+
+    // true while the last response is within the cache lifetime its server declared
+    pub fn is_fresh(&self, now: Instant) -> bool {
+        self.fresh_until.is_some_and(|until| now < until)
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    // the number of items of the content, 0 if it cannot be parsed
+    pub fn item_count(&self) -> usize {
+        Element::parse(strip_doctype(&self.content).as_bytes())
+            .map(|rssroot| count_items(&rssroot))
+            .unwrap_or_default()
+    }
+
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    // the body of the last response as it was received, before its content was decoded
+    pub fn body(&self) -> &[u8] {
+        self.body.as_deref().unwrap_or(self.content.as_bytes())
+    }
+
+    // a feed with only the url, filename and content of this one, to be written by another
+    // thread while this one is read again
+    pub fn output(&self) -> Feed {
+        Feed {
+            content: self.content.clone(),
+            ..Feed::new(&self.url, &self.filename)
+        }
+    }
+
+    // true if the feed parses and has no items
+    fn is_empty(&self) -> bool {
+        Element::parse(self.content.as_bytes()).is_ok_and(|rssroot| count_items(&rssroot) == 0)
+    }
+
+    // read the content of the stream into an internal String and return if the feed has been updated
+    // from the last time it was read, as detected by the feed's change detection
+    pub fn read(
+        &mut self,
+        fetcher: &Fetcher,
+        normalization: ContentNormalization,
+    ) -> Result<bool, String> {
+        if fetcher.head_first()
+            && self.change_detection != ChangeDetection::Always
+            && self.unchanged_by_head(fetcher)
+        {
+            return Ok(false);
+        }
+        let use_etag = matches!(
+            self.change_detection,
+            ChangeDetection::Auto | ChangeDetection::Etag
+        );
+        let etag = if use_etag { self.etag.as_deref() } else { None };
+        let response = fetcher.get(&self.url, etag, &self.request_headers)?;
+        let validators = Validators::from_headers(response.headers());
+        self.fresh_until = cache_lifetime(response.headers(), Utc::now())
+            .and_then(|lifetime| Instant::now().checked_add(lifetime));
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            info!("Feed has not been modified: {}", self.url);
+            return Ok(false);
+        }
+        if response.status() == reqwest::StatusCode::GONE {
+            self.mark_gone();
+            return Err(format!(
+                "Feed {} is gone (410), it will not be read anymore",
+                self.url
+            ));
+        }
+        if !fetcher.accepts(response.status()) {
+            return Err(format!(
+                "Feed {} answered with status {}",
+                self.url,
+                response.status()
+            ));
+        }
+        let etag = response
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+        // servers that ignore If-None-Match may still answer with an equivalent ETag, possibly
+        // with only the weakness marker changed
+        if let (true, Some(previous), Some(etag)) = (use_etag, &self.etag, &etag) {
+            if etags_match_weakly(previous, etag) {
+                info!("Feed has the same ETag {} as before: {}", etag, self.url);
+                self.etag = Some(etag.clone());
+                return Ok(false);
+            }
+        }
+        // a connection closed before the end of the body (Content-Length not reached or chunked
+        // without its final chunk) is a body error; a partial body could still parse, so it is
+        // a failure that keeps the previous content instead of an update
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .map(str::to_string);
+        let body = response.bytes().map_err(|e| {
+            if e.is_body() || e.is_decode() {
+                format!(
+                    "Feed {} is incomplete, its body was cut off: {}",
+                    self.url, e
+                )
+            } else {
+                format!("Feed {} cannot be read: {}", self.url, e)
+            }
+        })?;
+        let content = decode_body(&body, content_type.as_deref());
+        // an empty answer is no update, keep the previous content instead of failing to parse it
+        if content.trim_start_matches('\u{feff}').trim().is_empty() {
+            warn!("Feed {} returned an empty body", self.url);
+            return Ok(false);
+        }
+        self.etag = etag;
+        self.validators = Some(validators);
+        let updated = self.update_content(content, normalization);
+        self.body = (self.content.as_bytes() != &body[..]).then(|| body.to_vec());
+        Ok(updated)
+    }
+
+    // true if a HEAD request answers with the validators of the last GET, so the feed need not
+    // be downloaded; servers that do not support HEAD (405 or 501) and failed HEAD requests
+    // fall back to the GET
+    fn unchanged_by_head(&mut self, fetcher: &Fetcher) -> bool {
+        let Some(previous) = &self.validators else {
+            return false;
+        };
+        let response = match fetcher.head(&self.url, &self.request_headers) {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("HEAD request failed, falling back to GET: {}", e);
+                return false;
+            }
+        };
+        let status = response.status();
+        if status == reqwest::StatusCode::METHOD_NOT_ALLOWED
+            || status == reqwest::StatusCode::NOT_IMPLEMENTED
+        {
+            info!(
+                "Feed does not support HEAD ({}), falling back to GET: {}",
+                status, self.url
+            );
+            return false;
+        }
+        if !fetcher.accepts(status)
+            || !previous.unchanged(&Validators::from_headers(response.headers()))
+        {
+            return false;
+        }
+        info!("Feed has the same validators as before: {}", self.url);
+        self.fresh_until = cache_lifetime(response.headers(), Utc::now())
+            .and_then(|lifetime| Instant::now().checked_add(lifetime));
+        true
+    }
+
+    // parse the content ahead of remove_duplicates, which then uses the parsed content; errors are
+    // left to remove_duplicates to report
+    pub fn parse(&mut self, options: &DedupOptions) {
+        let started = Instant::now();
+        let deadline = options.parse_timeout.map(|timeout| started + timeout);
+        self.parsed = self.parse_content(options, deadline).ok();
+        self.parse_time = started.elapsed();
+    }
+
+    // parse the content, giving up once deadline has passed
+    fn parse_content(
+        &self,
+        options: &DedupOptions,
+        deadline: Option<Instant>,
+    ) -> Result<Element, String> {
+        let content = if options.allow_dtd {
+            Cow::Borrowed(self.content.as_str())
+        } else {
+            strip_doctype(&self.content)
+        };
+        if let Cow::Owned(_) = content {
+            warn!("Removed DOCTYPE declaration from RSS feed {}", self.url);
+        }
+        let content = match options.parse_strictness {
+            ParseStrictness::Strict => content,
+            ParseStrictness::Lenient => match sanitize_xml(&content) {
+                Cow::Borrowed(_) => content,
+                Cow::Owned(sanitized) => {
+                    warn!("Repaired XML defects of RSS feed {}", self.url);
+                    Cow::Owned(sanitized)
+                }
+            },
+        };
+        self.check_document_limits(&content, options)?;
+        let Some(deadline) = deadline else {
+            return Element::parse(content.as_bytes()).map_err(|e| {
+                format!(
+                    "RSS feed {} XML parse error at {}",
+                    self.url,
+                    describe_parse_error(&content, &e)
+                )
+            });
+        };
+        let mut reader = DeadlineReader {
+            content: content.as_bytes(),
+            deadline,
+            reads: 0,
+            timed_out: false,
+        };
+        Element::parse(&mut reader).map_err(|e| {
+            if reader.timed_out {
+                format!("the parse stage of RSS feed {} timed out", self.url)
+            } else {
+                format!(
+                    "RSS feed {} XML parse error at {}",
+                    self.url,
+                    describe_parse_error(&content, &e)
+                )
+            }
+        })
+    }
+
+    // refuse content above the configured byte or element count before its DOM is built; the
+    // elements are counted by a streaming parser that stops at the limit, syntax errors are left
+    // to Element::parse to report
+    fn check_document_limits(&self, content: &str, options: &DedupOptions) -> Result<(), String> {
+        if options.max_document_bytes > 0 && content.len() > options.max_document_bytes {
+            return Err(format!(
+                "RSS feed {} has {} bytes, more than the limit of {}",
+                self.url,
+                content.len(),
+                options.max_document_bytes
+            ));
+        }
+        if options.max_document_elements == 0 {
+            return Ok(());
+        }
+        let mut elements = 0;
+        for event in EventReader::new(content.as_bytes()) {
+            match event {
+                Ok(XmlEvent::StartElement { .. }) => {
+                    elements += 1;
+                    if elements > options.max_document_elements {
+                        return Err(format!(
+                            "RSS feed {} has more than the limit of {} elements",
+                            self.url, options.max_document_elements
+                        ));
+                    }
+                }
+                Ok(XmlEvent::EndDocument) | Err(_) => break,
+                Ok(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    // store fetched content and return if the feed has been updated from the last time
+    fn update_content(&mut self, content: String, normalization: ContentNormalization) -> bool {
+        self.parsed = None;
+        // a leading byte order mark or whitespace before the XML declaration breaks parsing
+        let trimmed = content.trim_start_matches('\u{feff}').trim_start();
+        self.content = if trimmed.len() == content.len() {
+            content
+        } else {
+            trimmed.to_string()
+        };
+        match self.change_detection {
+            // the ETag differed or is missing
+            ChangeDetection::Etag | ChangeDetection::Always => {
+                info!(
+                    "Feed is processed without comparing its content: {}",
+                    self.url
+                );
+                return true;
+            }
+            ChangeDetection::Hash => return self.update_content_hash(normalization),
+            ChangeDetection::Auto | ChangeDetection::BuildDate => {}
+        }
+        // use simple parsing for lastbuildDate to avoid full xml parsing if content hasn't changed
+        for line in self.content.lines() {
+            if line.trim_start().starts_with("<lastBuildDate>") {
+                let modified = self.last_build_date != line.trim();
+                self.last_build_date = line.trim().to_string();
+                info!(
+                    "Feed has {}been updated: {}",
+                    if modified { "" } else { "not " },
+                    self.url,
+                );
+                return modified;
+            }
+        }
+        if self.change_detection == ChangeDetection::BuildDate {
+            info!("Feed has no lastBuildDate and is processed: {}", self.url);
+            return true;
+        }
+        self.update_content_hash(normalization)
+    }
+
+    // without lastBuildDate the feed has been updated if its normalized content changed
+    fn update_content_hash(&mut self, normalization: ContentNormalization) -> bool {
+        let content_hash = content_hash(&self.content, normalization);
+        let modified = self.content_hash != content_hash;
+        self.content_hash = content_hash;
+        info!(
+            "Feed content has {}changed: {}",
+            if modified { "" } else { "not " },
+            self.url,
+        );
+        modified
+    }
+
+    /*
+    - for each item in the feed
+    - create the ID for the item
     - if the ID is not in the HashMap keys add it to the HashMap and publish the item to the feed
     - if the ID is in the HashMap keys and the feed is the same feed as the one in the HashMap value publish the original item (not the new one) to the feed
     - if the ID is in the HashMap keys and the feed is different from the one in the HashMap value do not publish the item
      existing_items: - HashMap<ID, (channellink, content)>  a map from the item ID (generated from the item link) to a tuple containing the channel link URL and the item XML elements
+     signatures: - HashMap<signature, (channellink, dates)>  with signature_dedup a map from the content signature of an item to the channel that published it first
+
+     */
+    pub fn remove_duplicates(
+        &mut self,
+        existing_items: &mut ExistingItemsMap,
+        signatures: &mut SignatureMap,
+        options: &DedupOptions,
+    ) -> Result<DedupStats, String> {
+        self.remove_duplicates_with_observer(existing_items, signatures, options, &mut NoopObserver)
+    }
+
+    // remove_duplicates reporting each decision to observer
+    pub(crate) fn remove_duplicates_with_observer(
+        &mut self,
+        existing_items: &mut ExistingItemsMap,
+        signatures: &mut SignatureMap,
+        options: &DedupOptions,
+        observer: &mut dyn DedupObserver,
+    ) -> Result<DedupStats, String> {
+        let feed_options;
+        let options = match self.max_age {
+            Some(max_age) if max_age != options.max_age => {
+                feed_options = DedupOptions {
+                    max_age,
+                    ..options.clone()
+                };
+                &feed_options
+            }
+            _ => options,
+        };
+        let started = Instant::now();
+        let mut rssroot = match self.parsed.take() {
+            Some(rssroot) => rssroot,
+            None => {
+                // the time spent parsing ahead counts against the timeout
+                let deadline = options
+                    .parse_timeout
+                    .map(|timeout| started + timeout.saturating_sub(self.parse_time));
+                self.parse_content(options, deadline)?
+            }
+        };
+        // checked before the items are recorded as known, so an abandoned feed leaves no trace
+        let parse_time = std::mem::take(&mut self.parse_time) + started.elapsed();
+        if let Some(timeout) = options.parse_timeout {
+            if parse_time > timeout {
+                return Err(format!(
+                    "the parse stage timed out after {} ms, the limit is {} ms",
+                    parse_time.as_millis(),
+                    timeout.as_millis()
+                ));
+            }
+        }
+        let items = count_items(&rssroot);
+        let mut channel = String::new();
+        let channel_identity = channel_identity(&self.url, options);
+        traverse_and_modify(
+            &mut rssroot,
+            existing_items,
+            signatures,
+            &mut channel,
+            channel_identity.as_deref(),
+            options,
+            observer,
+        )?;
+        let removed = items - count_items(&rssroot);
+        if options.retain_dropped_items {
+            self.retain_dropped_items(&mut rssroot, existing_items, &channel, options);
+        }
+        if options.dedup_provenance {
+            annotate_items(&mut rssroot, existing_items, &channel, options);
+        }
+        sort_items(&mut rssroot, &channel, options);
+        truncate_titles(&mut rssroot, options.max_title_length);
+        set_last_build_date(&mut rssroot, options.last_build_date);
+        if let Some(base_url) = &options.base_url {
+            let basename = Path::new(&self.filename)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default();
+            rewrite_channel_links(&mut rssroot, &format!("{}{}", base_url, basename));
+        }
+        let metadata = options.dedup_metadata.then(|| (Utc::now(), removed));
+        annotate_channel(&mut rssroot, options.generator, metadata);
+        if options.strip_namespaces && is_atom(&rssroot, "feed") {
+            // without its namespace the document is no longer an Atom feed
+            warn!(
+                "Not stripping the namespaces of feed {}, it is an Atom feed",
+                self.url
+            );
+        } else if options.strip_namespaces {
+            strip_namespaces(&mut rssroot);
+        }
+        self.set_content(&rssroot, options.compact)?;
+        Ok(DedupStats { items, removed })
+    }
+
+    // append the items of the previously served file that the deduplicated rssroot no longer
+    // lists and that are still attributed to channel and not older than max_age; items unknown
+    // to existing_items (after a restart) are only retained with a max_age and are added to
+    // them, so they age out and are not served by another channel
+    fn retain_dropped_items(
+        &self,
+        rssroot: &mut Element,
+        existing_items: &mut ExistingItemsMap,
+        channel: &str,
+        options: &DedupOptions,
+    ) {
+        let Ok(served) = fs::read_to_string(&self.filename) else {
+            return;
+        };
+        let served = match Element::parse(strip_doctype(&served).as_bytes()) {
+            Ok(served) => served,
+            Err(e) => {
+                warn!(
+                    "Served file {} of feed {} cannot be parsed: {}",
+                    self.filename, self.url, e
+                );
+                return;
+            }
+        };
+        let Some(target) = item_container_mut(rssroot) else {
+            return;
+        };
+        let item_keys = |channel_element: &Element| -> Vec<((String, String), Element)> {
+            channel_element
+                .children
+                .iter()
+                .filter_map(XMLNode::as_element)
+                .filter(|item| is_item(item))
+                .filter_map(|item| {
+                    item_link(item, &options.link_sources)
+                        .map(|link| (item_key(item, &link, channel, options), item.clone()))
+                })
+                .collect()
+        };
+        let listed: HashSet<(String, String)> =
+            item_keys(target).into_iter().map(|(id, _)| id).collect();
+        let now = SystemTime::now();
+        let nowutc = Local::now().with_timezone(&Utc);
+        let mut retained = 0;
+        for (id, item) in item_container(&served).map(item_keys).unwrap_or_default() {
+            if listed.contains(&id) {
+                continue;
+            }
+            let item_channel = item_channel(&item, channel, options.use_source);
+            let first_seen = match existing_items.get(&id) {
+                Some(existing) if existing.0 == item_channel => existing.2,
+                Some(_) => continue,
+                None if options.max_age > 0 => {
+                    existing_items.insert(id.clone(), (item_channel, item.clone(), now));
+                    now
+                }
+                None => continue,
+            };
+            if check_item_not_older_than(
+                &item,
+                Some(first_seen),
+                options.max_age,
+                &nowutc,
+                options.clamp_future_dates,
+            ) {
+                target.children.push(XMLNode::Element(item));
+                retained += 1;
+            }
+        }
+        if retained > 0 {
+            info!(
+                "Retained {} items of feed {} that are no longer listed upstream",
+                retained, self.url
+            );
+        }
+    }
+
+    // build the merged "firehose" feed of all unique items in existing_items, newest first,
+    // limited to items not older than the maximum age of options and to max_items items (0 means
+    // unlimited)
+    pub fn firehose(
+        url: &str,
+        filename: &str,
+        existing_items: &ExistingItemsMap,
+        max_items: usize,
+        options: &DedupOptions,
+    ) -> Result<Self, String> {
+        let nowutc = Local::now().with_timezone(&Utc);
+        let mut items: Vec<_> = existing_items
+            .iter()
+            .filter(|(_, (_, item, first_seen))| {
+                check_item_not_older_than(
+                    item,
+                    Some(*first_seen),
+                    options.max_age,
+                    &nowutc,
+                    options.clamp_future_dates,
+                )
+            })
+            .map(|(key, (_, item, _))| (item_date(item), key, item))
+            .collect();
+        // newest first, items without date last, ties in key order to keep the output stable
+        items.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+        if max_items > 0 {
+            items.truncate(max_items);
+        }
+
+        let rssroot = generated_rss(
+            url,
+            "rssdeduper firehose",
+            "All deduplicated items",
+            items.into_iter().map(|(_, _, item)| {
+                // the merged feed is RSS, Atom entries are converted to items
+                if is_atom(item, "entry") {
+                    entry_to_item(item)
+                } else {
+                    item.clone()
+                }
+            }),
+        );
+        let mut feed = Feed::new(url, filename);
+        feed.set_content(&rssroot, options.compact)?;
+        Ok(feed)
+    }
+
+    // feed served under url with an item per feed that failed in this iteration, failures are
+    // (feed url, error) pairs; without failures the feed is valid but has no items
+    pub fn error_feed(
+        url: &str,
+        filename: &str,
+        failures: &[(&str, &str)],
+        compact: bool,
+    ) -> Result<Self, String> {
+        let now = Local::now().with_timezone(&Utc);
+        let pub_date = now.to_rfc2822();
+        let items = failures.iter().map(|(feed_url, error)| {
+            let mut item = Element::new("item");
+            for (name, text) in [
+                ("title", feed_url.to_string()),
+                ("description", error.to_string()),
+                ("pubDate", pub_date.clone()),
+                ("guid", format!("{}#{}", feed_url, now.timestamp())),
+            ] {
+                item.children
+                    .push(XMLNode::Element(text_element(name, &text)));
+            }
+            if let Some(guid) = item.get_mut_child("guid") {
+                guid.attributes
+                    .insert("isPermaLink".to_string(), "false".to_string());
+            }
+            item
+        });
+        let rssroot = generated_rss(
+            url,
+            "rssdeduper errors",
+            "Feeds that failed in the last iteration",
+            items,
+        );
+        let mut feed = Feed::new(url, filename);
+        feed.set_content(&rssroot, compact)?;
+        Ok(feed)
+    }
+
+    // serialize the XML document into the content of the feed
+    fn set_content(&mut self, rssroot: &Element, compact: bool) -> Result<(), String> {
+        let mut new_content = Vec::with_capacity(self.content.len());
+        rssroot
+            .write_with_config(&mut new_content, emitter_config(compact))
+            .map_err(|e| format!("RSS feed {} XML write error: {}", self.url, e))?;
+        self.content = String::from_utf8(new_content).unwrap();
+        Ok(())
+    }
+
+    // write the feed to its file and to the same filename in each mirror directory, a mirror
+    // that cannot be written is logged and does not stop the others; returns whether the feed
+    // file changed
+    pub fn write(&self, options: &WriteOptions) -> Result<bool, String> {
+        if let Some(timeout) = options.timeout {
+            return self.write_with_timeout(options, timeout);
+        }
+        let empty = options.on_empty != OnEmpty::Write && self.is_empty();
+        let result = self.write_file(&self.filename, empty, options);
+        let basename = Path::new(&self.filename).file_name().unwrap_or_default();
+        for directory in &options.mirror_directories {
+            let filename = Path::new(directory).join(basename);
+            let filename = filename.to_string_lossy();
+            if let Err(e) = self.write_file(&filename, empty, options) {
+                error!(
+                    "Could not write mirror {} of feed {}: {}",
+                    filename, self.url, e
+                );
+            }
+        }
+        result
+    }
+
+    // write a copy of the feed in another thread and stop waiting for it after timeout; the
+    // abandoned write still replaces the files atomically when it finishes, until then further
+    // writes of the same file are skipped so they do not share its temporary file
+    fn write_with_timeout(
+        &self,
+        options: &WriteOptions,
+        timeout: std::time::Duration,
+    ) -> Result<bool, String> {
+        if !WRITES_IN_FLIGHT
+            .lock()
+            .unwrap()
+            .insert(self.filename.clone())
+        {
+            return Err(format!(
+                "the previous write of {} timed out and is still running",
+                self.filename
+            ));
+        }
+        let feed = self.output();
+        let options = WriteOptions {
+            timeout: None,
+            ..options.clone()
+        };
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let result = feed.write(&options);
+            WRITES_IN_FLIGHT.lock().unwrap().remove(&feed.filename);
+            let _ = sender.send(result);
+        });
+        receiver
+            .recv_timeout(timeout)
+            .map_err(|_| format!("the write stage timed out after {} ms", timeout.as_millis()))?
+    }
+
+    fn write_file(
+        &self,
+        filename: &str,
+        empty: bool,
+        options: &WriteOptions,
+    ) -> Result<bool, String> {
+        let filename = &resolve_symlink(filename, options.no_follow_symlinks)?;
+        let skip_empty = match options.on_empty {
+            OnEmpty::Write => false,
+            OnEmpty::Skip => true,
+            OnEmpty::KeepPrevious => Path::new(filename).exists(),
+        };
+        if skip_empty && empty {
+            info!(
+                "Not writing feed {} without items to {}",
+                self.url, filename
+            );
+            return Ok(false);
+        }
+        let checksum_filename = format!("{}.sha256", filename);
+        if fs::read(filename).is_ok_and(|previous| previous == self.content.as_bytes())
+            && (!options.checksum || Path::new(&checksum_filename).exists())
+        {
+            debug!("File {} is unchanged", filename);
+            return Ok(false);
+        }
+        let tmp_filename = write_temporary_file(filename, self.content.as_bytes())?;
+        let checksum_tmp_filename = format!("{}.tmp", checksum_filename);
+        let result = self.replace_file(
+            filename,
+            &tmp_filename,
+            &checksum_filename,
+            &checksum_tmp_filename,
+            options,
+        );
+        // temporary files that were not renamed would accumulate, e.g. when the disk is full
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_filename);
+            let _ = fs::remove_file(&checksum_tmp_filename);
+        }
+        result.map(|_| true)
+    }
+
+    // write the checksum of the feed (with options.checksum), back up filename and rename the
+    // temporary files written for them into place
+    fn replace_file(
+        &self,
+        filename: &str,
+        tmp_filename: &str,
+        checksum_filename: &str,
+        checksum_tmp_filename: &str,
+        options: &WriteOptions,
+    ) -> Result<(), String> {
+        // sha256sum compatible line, so the feed can be verified with sha256sum -c
+        if options.checksum {
+            let basename = Path::new(filename)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default();
+            let line = format!(
+                "{:x}  {}\n",
+                Sha256::digest(self.content.as_bytes()),
+                basename
+            );
+            write_temporary_file(checksum_filename, line.as_bytes())?;
+        }
+        if options.backups > 0 && Path::new(filename).exists() {
+            rotate_backups(filename, options.backups)?;
+        }
+        fs::rename(tmp_filename, filename)
+            .map_err(|e| format!("File {} cannot be renamed: {}", tmp_filename, e))?;
+        if options.checksum {
+            fs::rename(checksum_tmp_filename, checksum_filename)
+                .map_err(|e| format!("File {} cannot be renamed: {}", checksum_tmp_filename, e))?;
+        }
+        Ok(())
+    }
+}
+
+// the file a symlinked feed file points to, so that replacing it keeps the symlink; with
+// no_follow a symlink is an error; other files are returned unchanged
+fn resolve_symlink(filename: &str, no_follow: bool) -> Result<String, String> {
+    let is_symlink =
+        fs::symlink_metadata(filename).is_ok_and(|metadata| metadata.file_type().is_symlink());
+    if !is_symlink {
+        return Ok(filename.to_string());
+    }
+    if no_follow {
+        return Err(format!(
+            "File {} is a symlink, not replacing it because symlinks are not followed",
+            filename
+        ));
+    }
+    let target = fs::canonicalize(filename)
+        .map_err(|e| format!("Symlink {} cannot be resolved: {}", filename, e))?;
+    debug!(
+        "Writing symlinked file {} to {}",
+        filename,
+        target.display()
+    );
+    Ok(target.to_string_lossy().to_string())
+}
+
+// shift the backups of filename by one, dropping the oldest, and copy filename to
+// <filename>.bak; the file is copied and not moved so it never disappears for readers
+fn rotate_backups(filename: &str, backups: usize) -> Result<(), String> {
+    let backup_filename = |index: usize| match index {
+        0 => format!("{}.bak", filename),
+        _ => format!("{}.bak.{}", filename, index),
+    };
+    for index in (1..backups).rev() {
+        let older = backup_filename(index - 1);
+        if Path::new(&older).exists() {
+            fs::rename(&older, backup_filename(index))
+                .map_err(|e| format!("Backup {} cannot be renamed: {}", older, e))?;
+        }
+    }
+    fs::copy(filename, backup_filename(0))
+        .map_err(|e| format!("Backup of {} cannot be written: {}", filename, e))?;
+    Ok(())
+}
+
+// write content to <filename>.tmp and return the temporary filename, which is renamed to
+// filename afterwards so readers never see a partially written file; a partially written
+// temporary file is removed
+pub fn write_temporary_file(filename: &str, content: &[u8]) -> Result<String, String> {
+    let tmp_filename = format!("{}.tmp", filename);
+    let mut file = fs::File::create(&tmp_filename)
+        .map_err(|e| format!("Temporary file {} cannot be created: {}", tmp_filename, e))?;
+    if let Err(e) = file.write_all(content).and_then(|_| file.flush()) {
+        drop(file);
+        let _ = fs::remove_file(&tmp_filename);
+        return Err(if e.kind() == std::io::ErrorKind::StorageFull {
+            format!(
+                "Temporary file {} cannot be written, the disk is full: {}",
+                tmp_filename, e
+            )
+        } else {
+            format!("Temporary file {} cannot be written: {}", tmp_filename, e)
+        });
+    }
+    Ok(tmp_filename)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fetch::FetchOptions;
+    use super::super::hosts::HostMatcher;
+    use super::super::testserver::{response, TestServer};
+    use super::super::utilities::setup_test_logger;
+    use super::*;
+
+    #[test]
+    fn test_rss_read() {
+        setup_test_logger();
+        let fetcher = Fetcher::new(&FetchOptions::default()).unwrap();
+        let mut feed = Feed::new(
+            "http://www.faz.net/aktuell/politik/ausland/?rssview=1",
+            "testdata/testfazausland.rss",
+        );
+        let result = feed.read(&fetcher, ContentNormalization::default());
+        assert!(result.is_ok());
+        assert!(result.unwrap()); // updated == true
+        assert!(!feed.content.is_empty());
+        let result = feed.read(&fetcher, ContentNormalization::default());
+        assert!(result.is_ok());
+        assert!(!result.unwrap()); // updated == false
+        assert!(!feed.content.is_empty());
+    }
+
+    #[test]
+    fn test_rss_read_from_server() {
+        const FEED1: &str = include_str!("../testdata/channelwithbom.rss");
+        setup_test_logger();
+        let server = TestServer::start(vec![response("200 OK", &[], FEED1); 2]);
+        let fetcher = Fetcher::new(&FetchOptions::default()).unwrap();
+        let mut feed = Feed::new(&server.url, "testdata/channelwithbom_read.rss");
+        assert_eq!(
+            feed.read(&fetcher, ContentNormalization::default()),
+            Ok(true)
+        );
+        assert!(feed.content.starts_with("<?xml"));
+        // same lastBuildDate
+        assert_eq!(
+            feed.read(&fetcher, ContentNormalization::default()),
+            Ok(false)
+        );
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].starts_with("GET /feed.rss HTTP/1.1"));
+    }
+
+    #[test]
+    fn test_rss_read_with_weak_etag() {
+        const FEED1: &str = include_str!("../testdata/channel1.rss");
+        setup_test_logger();
+        let etag = "W/\"5f3c-1700032270\"";
+        let server = TestServer::start(vec![
+            response("200 OK", &[("ETag", etag)], FEED1),
+            response("304 Not Modified", &[("ETag", etag)], ""),
+            response("200 OK", &[("ETag", "\"5f3c-1700032270\"")], FEED1),
+        ]);
+        let fetcher = Fetcher::new(&FetchOptions::default()).unwrap();
+        let mut feed = Feed::new(&server.url, "testdata/channel1_etag.rss");
+        let read = |feed: &mut Feed| feed.read(&fetcher, ContentNormalization::default());
+        assert_eq!(read(&mut feed), Ok(true));
+        assert_eq!(read(&mut feed), Ok(false));
+        // the strong ETag only differs in the weakness marker
+        assert_eq!(read(&mut feed), Ok(false));
+        assert_eq!(feed.content, FEED1);
+        let requests = server.requests();
+        assert!(!requests[0].to_lowercase().contains("if-none-match"));
+        for request in &requests[1..] {
+            assert!(request
+                .lines()
+                .any(|line| line.eq_ignore_ascii_case(&format!("if-none-match: {}", etag))));
+        }
+    }
+
+    #[test]
+    fn test_rss_read_head_first() {
+        const FEED1: &str = include_str!("../testdata/channel1.rss");
+        setup_test_logger();
+        let headers = [
+            ("ETag", "\"5f3c-1700032270\""),
+            ("Last-Modified", "Wed, 15 Nov 2023 07:11:10 GMT"),
+        ];
+        // a HEAD response announces the length of the body it does not send
+        let head = |status: &str| {
+            format!(
+                "HTTP/1.1 {}\r\nETag: {}\r\nLast-Modified: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                headers[0].1,
+                headers[1].1,
+                FEED1.len()
+            )
+        };
+        let server = TestServer::start(vec![
+            response("200 OK", &headers, FEED1),
+            head("200 OK"),
+            head("405 Method Not Allowed"),
+            response("200 OK", &headers, FEED1),
+        ]);
+        let fetcher = Fetcher::new(&FetchOptions {
+            head_first: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut feed = Feed::new(&server.url, "testdata/channel1_head.rss");
+        feed.set_change_detection(ChangeDetection::Hash);
+        let read = |feed: &mut Feed| feed.read(&fetcher, ContentNormalization::default());
+        // without validators of a previous GET there is nothing to compare
+        assert_eq!(read(&mut feed), Ok(true));
+        assert_eq!(read(&mut feed), Ok(false));
+        // HEAD is not supported, the feed is read with a GET
+        assert_eq!(read(&mut feed), Ok(false));
+        let methods: Vec<String> = server
+            .requests()
+            .iter()
+            .map(|request| request.split_whitespace().next().unwrap().to_string())
+            .collect();
+        assert_eq!(methods, vec!["GET", "HEAD", "HEAD", "GET"]);
+    }
+
+    #[test]
+    fn test_rss_read_digest_auth() {
+        const FEED1: &str = include_str!("../testdata/channel1.rss");
+        setup_test_logger();
+        let challenge = "Digest realm=\"feeds\", qop=\"auth\", nonce=\"7ypf\", opaque=\"c0ffee\"";
+        let stale = challenge.replace("7ypf", "8zqg") + ", stale=true";
+        let server = TestServer::start(vec![
+            response("401 Unauthorized", &[("WWW-Authenticate", challenge)], ""),
+            response("200 OK", &[], FEED1),
+            response("200 OK", &[], FEED1),
+            response("401 Unauthorized", &[("WWW-Authenticate", &stale)], ""),
+            response("200 OK", &[], FEED1),
+        ]);
+        let fetcher = Fetcher::new(&FetchOptions::default()).unwrap();
+        let url = server.url.replace("http://", "http://alice:s3cret@");
+        let mut feed = Feed::new(&url, "testdata/channel1_digest.rss");
+        assert_eq!(
+            feed.read(&fetcher, ContentNormalization::default()),
+            Ok(true)
+        );
+        assert_eq!(feed.content, FEED1);
+        // the next requests answer the remembered challenge right away, until its nonce is stale
+        for _ in 0..2 {
+            assert_eq!(
+                feed.read(&fetcher, ContentNormalization::default()),
+                Ok(false)
+            );
+        }
+        let requests: Vec<String> = server
+            .requests()
+            .iter()
+            .map(|request| request.to_lowercase())
+            .collect();
+        assert_eq!(requests.len(), 5);
+        assert!(requests[0].contains("authorization: basic "));
+        assert!(requests[1].contains("authorization: digest username=\"alice\", realm=\"feeds\""));
+        assert!(requests[1].contains("uri=\"/feed.rss\""));
+        assert!(requests[1].contains("opaque=\"c0ffee\""));
+        assert!(requests[1].contains("nc=00000001"));
+        assert!(!requests[1].contains("authorization: basic "));
+        assert!(requests[2].contains("authorization: digest username=\"alice\""));
+        assert!(requests[2].contains("nc=00000002"));
+        assert!(!requests[2].contains("authorization: basic "));
+        assert!(requests[3].contains("nonce=\"7ypf\""));
+        assert!(requests[3].contains("nc=00000003"));
+        assert!(requests[4].contains("nonce=\"8zqg\""));
+        assert!(requests[4].contains("nc=00000001"));
+    }
+
+    #[test]
+    fn test_rss_read_with_change_detection() {
+        const FEED1: &str = include_str!("../testdata/channel1.rss");
+        setup_test_logger();
+        let etag = "\"5f3c-1700032270\"";
+        let server = TestServer::start(vec![response("200 OK", &[("ETag", etag)], FEED1); 4]);
+        let fetcher = Fetcher::new(&FetchOptions::default()).unwrap();
+        let mut always = Feed::new(&server.url, "testdata/channel1_always.rss");
+        always.set_change_detection(ChangeDetection::Always);
+        let mut hash = Feed::new(&server.url, "testdata/channel1_hash.rss");
+        hash.set_change_detection(ChangeDetection::Hash);
+        let read = |feed: &mut Feed| feed.read(&fetcher, ContentNormalization::default());
+        assert_eq!(read(&mut always), Ok(true));
+        assert_eq!(read(&mut always), Ok(true));
+        assert_eq!(read(&mut hash), Ok(true));
+        assert_eq!(read(&mut hash), Ok(false));
+        // neither sends the ETag it got
+        for request in server.requests() {
+            assert!(!request.to_lowercase().contains("if-none-match"));
+        }
+    }
+
+    #[test]
+    fn test_change_detection_parse() {
+        assert_eq!(ChangeDetection::parse("etag"), Some(ChangeDetection::Etag));
+        assert_eq!(
+            ChangeDetection::parse("buildDate"),
+            Some(ChangeDetection::BuildDate)
+        );
+        assert_eq!(ChangeDetection::parse(" hash"), Some(ChangeDetection::Hash));
+        assert_eq!(ChangeDetection::parse("never"), None);
+    }
+
+    #[test]
+    fn test_rss_read_gone() {
+        setup_test_logger();
+        let server = TestServer::start(vec![response("410 Gone", &[], "")]);
+        let fetcher = Fetcher::new(&FetchOptions::default()).unwrap();
+        let mut feed = Feed::new(&server.url, "testdata/channel1_gone.rss");
+        assert!(!feed.is_gone());
+        let error = feed
+            .read(&fetcher, ContentNormalization::default())
+            .unwrap_err();
+        assert!(error.contains("is gone (410)"));
+        assert!(feed.is_gone());
+    }
+
+    #[test]
+    fn test_rss_read_status_codes() {
+        setup_test_logger();
+        const FEED1: &str = include_str!("../testdata/channel1.rss");
+        let server = TestServer::start(vec![
+            response("203 Non-Authoritative Information", &[], FEED1),
+            response("500 Internal Server Error", &[], FEED1),
+            response("203 Non-Authoritative Information", &[], FEED1),
+        ]);
+        let mut feed = Feed::new(&server.url, "testdata/channel1_status.rss");
+        let fetcher = Fetcher::new(&FetchOptions::default()).unwrap();
+        let error = feed
+            .read(&fetcher, ContentNormalization::default())
+            .unwrap_err();
+        assert!(error.contains("answered with status 203"));
+
+        let fetcher = Fetcher::new(&FetchOptions {
+            accepted_status_codes: vec![203],
+            ..Default::default()
+        })
+        .unwrap();
+        let error = feed
+            .read(&fetcher, ContentNormalization::default())
+            .unwrap_err();
+        assert!(error.contains("answered with status 500"));
+        assert_eq!(
+            feed.read(&fetcher, ContentNormalization::default()),
+            Ok(true)
+        );
+        assert_eq!(feed.content(), FEED1);
+    }
+
+    #[test]
+    fn test_rss_read_truncated() {
+        setup_test_logger();
+        const FEED1: &str = include_str!("../testdata/channel1.rss");
+        // the feed has umlauts, so the body is cut at char boundaries
+        let boundary = |index: usize| {
+            FEED1
+                .char_indices()
+                .map(|(boundary, _)| boundary)
+                .find(|boundary| *boundary >= index)
+                .unwrap()
+        };
+        let cut = boundary(FEED1.len() / 2);
+        let end = boundary(FEED1.len() - 10);
+        // the connection is closed after half of the body, without the final 0-length chunk
+        let chunked = format!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n{:x}\r\n{}\r\n{:x}\r\n{}",
+            cut,
+            &FEED1[..cut],
+            FEED1.len() - cut,
+            &FEED1[cut..end]
+        );
+        let content_length = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            FEED1.len(),
+            &FEED1[..cut]
+        );
+        let server = TestServer::start(vec![
+            response("200 OK", &[], FEED1),
+            chunked,
+            content_length,
+        ]);
+        let mut feed = Feed::new(&server.url, "testdata/channel1_truncated.rss");
+        let fetcher = Fetcher::new(&FetchOptions::default()).unwrap();
+        assert_eq!(
+            feed.read(&fetcher, ContentNormalization::default()),
+            Ok(true)
+        );
+        for _ in 0..2 {
+            let error = feed
+                .read(&fetcher, ContentNormalization::default())
+                .unwrap_err();
+            assert!(error.contains("is incomplete"), "{}", error);
+            assert_eq!(feed.content(), FEED1);
+        }
+    }
+
+    // /dev/full fails every write with ENOSPC like a full disk
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_rss_write_disk_full() {
+        setup_test_logger();
+        let directory = std::env::temp_dir().join("rssdeduper-disk-full");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let filename = directory.join("full.rss");
+        let tmp_filename = directory.join("full.rss.tmp");
+        std::os::unix::fs::symlink("/dev/full", &tmp_filename).unwrap();
+        let mut feed = Feed::new(
+            "https://www.faz.net/rss/aktuell/",
+            filename.to_str().unwrap(),
+        );
+        feed.content = include_str!("../testdata/channel1.rss").to_string();
+        let error = feed.write(&WriteOptions::default()).unwrap_err();
+        assert!(error.contains("the disk is full"), "{}", error);
+        assert!(fs::symlink_metadata(&tmp_filename).is_err());
+        assert!(!filename.exists());
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 0);
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_rss_read_cache_lifetime() {
+        setup_test_logger();
+        let server = TestServer::start(vec![response(
+            "200 OK",
+            &[("Cache-Control", "max-age=3600")],
+            include_str!("../testdata/channel1.rss"),
+        )]);
+        let fetcher = Fetcher::new(&FetchOptions::default()).unwrap();
+        let mut feed = Feed::new(&server.url, "");
+        assert!(!feed.is_fresh(Instant::now()));
+        assert_eq!(
+            feed.read(&fetcher, ContentNormalization::default()),
+            Ok(true)
+        );
+        let now = Instant::now();
+        let minutes = |minutes: u64| now + std::time::Duration::from_secs(minutes * 60);
+        assert!(feed.is_fresh(now));
+        assert!(feed.is_fresh(minutes(59)));
+        assert!(!feed.is_fresh(minutes(61)));
+    }
+
+    #[test]
+    fn test_rss_read_empty_body() {
+        const FEED1: &str = include_str!("../testdata/channel1.rss");
+        setup_test_logger();
+        let server = TestServer::start(vec![
+            response("200 OK", &[], FEED1),
+            response("200 OK", &[], ""),
+            response("200 OK", &[], " \r\n\t"),
+        ]);
+        let fetcher = Fetcher::new(&FetchOptions::default()).unwrap();
+        let mut feed = Feed::new(&server.url, "testdata/channel1_empty_body.rss");
+        assert_eq!(
+            feed.read(&fetcher, ContentNormalization::default()),
+            Ok(true)
+        );
+        assert_eq!(
+            feed.read(&fetcher, ContentNormalization::default()),
+            Ok(false)
+        );
+        assert_eq!(
+            feed.read(&fetcher, ContentNormalization::default()),
+            Ok(false)
+        );
+        assert_eq!(feed.content, FEED1);
+    }
+
+    #[test]
+    fn test_decode_body() {
+        let latin1 = b"<title>Gr\xfc\xdfe</title>";
+        assert_eq!(
+            decode_body(latin1, Some("application/rss+xml; charset=\"ISO-8859-1\"")),
+            "<title>Grüße</title>"
+        );
+        assert_eq!(
+            decode_body("<title>Grüße</title>".as_bytes(), Some("text/xml")),
+            "<title>Grüße</title>"
+        );
+        assert_eq!(decode_body(b"\xef\xbb\xbf<rss/>", None), "<rss/>");
+    }
+
+    #[test]
+    fn test_content_hash_normalization() {
+        let body1 = "<item>\n  <description>&lt;img src=\"a.jpg\" border=\"0\"&gt;Eine  Hiobsbotschaft</description>\n</item>";
+        let body2 = "<item><description>&lt;img   src=\"a.jpg\"  border=\"0\"&gt;Eine\n Hiobsbotschaft</description></item>";
+        let body3 = "<item> <description>&lt;img border=\"0\" src=\"a.jpg\"&gt;Eine Hiobsbotschaft</description> </item>";
+        assert_ne!(
+            content_hash(body1, ContentNormalization::Off),
+            content_hash(body2, ContentNormalization::Off)
+        );
+        assert_eq!(
+            content_hash(body1, ContentNormalization::Whitespace),
+            content_hash(body2, ContentNormalization::Whitespace)
+        );
+        assert_ne!(
+            content_hash(body1, ContentNormalization::Whitespace),
+            content_hash(body3, ContentNormalization::Whitespace)
+        );
+        // whitespace next to a > in text is content, not whitespace between tags
+        assert_ne!(
+            content_hash(
+                "<item><title>a > </title></item>",
+                ContentNormalization::Whitespace
+            ),
+            content_hash(
+                "<item><title>a ></title></item>",
+                ContentNormalization::Whitespace
+            )
+        );
+        assert_ne!(
+            content_hash(
+                "<item><description><![CDATA[<b> <i>]]></description></item>",
+                ContentNormalization::Whitespace
+            ),
+            content_hash(
+                "<item><description><![CDATA[<b><i>]]></description></item>",
+                ContentNormalization::Whitespace
+            )
+        );
+        assert_eq!(
+            content_hash(body1, ContentNormalization::Attributes),
+            content_hash(body3, ContentNormalization::Attributes)
+        );
+        assert_ne!(
+            content_hash(body1, ContentNormalization::Attributes),
+            content_hash(
+                "<item><description>Eine Hiobsbotschaft</description></item>",
+                ContentNormalization::Attributes
+            )
+        );
+    }
+
+    #[test]
+    fn test_rss_read_without_last_build_date() {
+        const FEED1: &str = include_str!("../testdata/channel1.rss");
+        setup_test_logger();
+        let server = TestServer::start(vec![
+            response("200 OK", &[], FEED1),
+            response("200 OK", &[], &FEED1.replace("\n", "\r\n")),
+            response("200 OK", &[], &FEED1.replace("Religion", "Kirche")),
+        ]);
+        let fetcher = Fetcher::new(&FetchOptions::default()).unwrap();
+        let mut feed = Feed::new(&server.url, "testdata/channel1_hash.rss");
+        assert_eq!(
+            feed.read(&fetcher, ContentNormalization::default()),
+            Ok(true)
+        );
+        assert_eq!(
+            feed.read(&fetcher, ContentNormalization::default()),
+            Ok(false)
+        );
+        assert_eq!(
+            feed.read(&fetcher, ContentNormalization::default()),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn test_rss_write() {
+        setup_test_logger();
+        let fetcher = Fetcher::new(&FetchOptions::default()).unwrap();
+        let mut feed = Feed::new(
+            "http://www.faz.net/aktuell/politik/ausland/?rssview=1",
+            "testdata/testfazausland.rss",
+        );
+        // feed.content = String::from("Test");
+        let result = feed.read(&fetcher, ContentNormalization::default());
+        assert!(result.is_ok());
+        let result = feed.write(&WriteOptions::default());
+        assert!(result.is_ok());
+        assert!(Path::new(&feed.filename).exists());
+        let _ = fs::remove_file(&feed.filename);
+    }
+
+    #[test]
+    fn test_rss_write_reports_changes() {
+        setup_test_logger();
+        let mut feed = Feed::new(
+            "https://www.stuttgarter-zeitung.de/news",
+            "testdata/channel1_changes.rss",
+        );
+        let _ = fs::remove_file(&feed.filename);
+        feed.content = include_str!("../testdata/channel1.rss").to_string();
+        assert_eq!(feed.write(&WriteOptions::default()), Ok(true));
+        assert_eq!(feed.write(&WriteOptions::default()), Ok(false));
+        feed.content = include_str!("../testdata/channel2.rss").to_string();
+        assert_eq!(feed.write(&WriteOptions::default()), Ok(true));
+        let _ = fs::remove_file(&feed.filename);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rss_write_symlink() {
+        setup_test_logger();
+        let directory = "testdata/symlink";
+        let _ = fs::remove_dir_all(directory);
+        fs::create_dir(directory).unwrap();
+        let target = format!("{}/canonical.rss", directory);
+        let link = format!("{}/served.rss", directory);
+        fs::write(&target, "previous").unwrap();
+        std::os::unix::fs::symlink("canonical.rss", &link).unwrap();
+        let mut feed = Feed::new("https://www.stuttgarter-zeitung.de/news", &link);
+        feed.content = include_str!("../testdata/channel1.rss").to_string();
+
+        let no_follow = WriteOptions {
+            no_follow_symlinks: true,
+            ..Default::default()
+        };
+        let error = feed.write(&no_follow).unwrap_err();
+        assert!(error.contains("is a symlink"));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "previous");
+
+        assert_eq!(feed.write(&WriteOptions::default()), Ok(true));
+        let is_symlink = fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink();
+        let written = fs::read_to_string(&target).unwrap();
+        let _ = fs::remove_dir_all(directory);
+        assert!(is_symlink);
+        assert_eq!(written, feed.content);
+    }
+
+    #[test]
+    fn test_rss_remove_duplicates() {
+        const FEED1: &str = include_str!("../testdata/channel1.rss");
+        const FEED2: &str = include_str!("../testdata/channel2.rss");
+        setup_test_logger();
+        let mut feed1 = Feed::new(
+            "https://www.stuttgarter-zeitung.de/news",
+            "testdata/channel1_dedup.rss",
+        );
+        feed1.content = FEED1.to_string();
+        let mut feed2 = Feed::new(
+            "https://www.stuttgarter-zeitung.de/schlagzeilen",
+            "testdata/channel2_dedup.rss",
+        );
+        feed2.content = FEED2.to_string();
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+
+        let options = DedupOptions::default();
+        assert!(feed1
+            .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .is_ok());
+        assert_eq!(
+            feed2.remove_duplicates(&mut existing_items, &mut HashMap::new(), &options),
+            Ok(DedupStats {
+                items: 2,
+                removed: 1
+            })
+        );
+        assert!(feed1.write(&WriteOptions::default()).is_ok());
+        assert!(feed2.write(&WriteOptions::default()).is_ok());
+        assert_eq!(4, feed1.content.matches("<item>").count());
+        assert_eq!(1, feed2.content.matches("<item>").count());
+        assert_eq!(0, feed1.content.matches("chifa2").count());
+        assert!(feed2.content != FEED2);
+
+        let _ = fs::remove_file(&feed1.filename);
+        let _ = fs::remove_file(&feed2.filename);
+    }
+
+    #[test]
+    fn test_rss_update_modified_items() {
+        setup_test_logger();
+        let content = |title: &str, modified: &str| {
+            format!(
+                "<rss version=\"2.0\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\"><channel>\
+                 <link>https://www.faz.net/aktuell/</link>\
+                 <item><title>Vorher</title><link>https://www.faz.net/aktuell/vorher-1000001.html</link></item>\
+                 <item><title>{}</title><link>https://www.faz.net/aktuell/live-1000002.html</link>\
+                 <dc:modified>{}</dc:modified></item>\
+                 <item><title>Nachher</title><link>https://www.faz.net/aktuell/nachher-1000003.html</link></item>\
+                 </channel></rss>",
+                title, modified
+            )
+        };
+        let options = DedupOptions {
+            update_modified_items: true,
+            ..Default::default()
+        };
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        let mut feed = Feed::new("https://www.faz.net/rss/aktuell/", "");
+        let mut dedup = |title: &str, modified: &str, options: &DedupOptions| {
+            feed.content = content(title, modified);
+            feed.remove_duplicates(&mut existing_items, &mut HashMap::new(), options)
+                .unwrap();
+            feed.content.clone()
+        };
+        dedup("Liveblog", "2023-11-15T07:00:00Z", &options);
+
+        // a newer version is served at the position of the item
+        let served = dedup("Liveblog (aktualisiert)", "2023-11-15T08:00:00Z", &options);
+        assert!(served.contains("<title>Liveblog (aktualisiert)</title>"));
+        let position = |title: &str| served.find(title).unwrap();
+        assert!(position("Vorher") < position("Liveblog"));
+        assert!(position("Liveblog") < position("Nachher"));
+
+        // an older version is replaced by the stored newer one
+        let served = dedup("Liveblog", "2023-11-15T07:00:00Z", &options);
+        assert!(served.contains("<title>Liveblog (aktualisiert)</title>"));
+
+        // without the option the stored version is served
+        let served = dedup(
+            "Liveblog (neu)",
+            "2023-11-15T09:00:00Z",
+            &DedupOptions::default(),
+        );
+        assert!(served.contains("<title>Liveblog (aktualisiert)</title>"));
+    }
+
+    #[test]
+    fn test_rss_item_dedup_scope() {
+        setup_test_logger();
+        // the same wire service article, published by two newspapers under its uuid
+        let content = |host: &str, path: &str, own: u32| {
+            format!(
+                "<rss version=\"2.0\"><channel><link>https://{}/</link>\
+                 <item><title>Syndicated</title>\
+                 <link>https://{}/{}/dpa-5c1f3f9e-8a4b-4c2d-9e1f-2b3c4d5e6f70.html</link></item>\
+                 <item><title>Own</title><link>https://{}/{}/lokales-{}.html</link></item>\
+                 </channel></rss>",
+                host, host, path, host, path, own
+            )
+        };
+        let dedup = |scope: DedupScope| {
+            let options = DedupOptions {
+                item_dedup_scope: scope,
+                ..Default::default()
+            };
+            let mut existing_items: ExistingItemsMap = HashMap::new();
+            let mut feed1 = Feed::new("https://www.stuttgarter-zeitung.de/rss", "");
+            feed1.content = content("www.stuttgarter-zeitung.de", "inhalt", 1000001);
+            feed1
+                .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+                .unwrap();
+            let mut feed2 = Feed::new("https://www.faz.net/rss", "");
+            feed2.content = content("www.faz.net", "agenturmeldungen", 1000002);
+            feed2
+                .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+                .unwrap()
+        };
+        assert_eq!(
+            dedup(DedupScope::Host),
+            DedupStats {
+                items: 2,
+                removed: 0
+            }
+        );
+        // only the first newspaper keeps the syndicated article
+        assert_eq!(
+            dedup(DedupScope::Global),
+            DedupStats {
+                items: 2,
+                removed: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_rss_remove_duplicates_guid_only() {
+        setup_test_logger();
+        let content = |channel: &str, items: &[(&str, Option<&str>)]| {
+            let items: String = items
+                .iter()
+                .map(|(link, guid)| {
+                    let guid = guid
+                        .map(|guid| format!("<guid isPermaLink=\"false\">{}</guid>", guid))
+                        .unwrap_or_default();
+                    format!("<item><link>{}</link>{}</item>", link, guid)
+                })
+                .collect();
+            format!(
+                "<rss version=\"2.0\"><channel><link>https://www.example.com/{}/</link>{}</channel></rss>",
+                channel, items
+            )
+        };
+        let feed1 = content(
+            "news",
+            &[
+                (
+                    "https://www.example.com/news/flood-20231115.html",
+                    Some("post-1"),
+                ),
+                ("https://www.example.com/2023/11/storm.html", None),
+            ],
+        );
+        // a duplicate by guid with another link and an unrelated item whose link has the same number
+        let feed2 = content(
+            "local",
+            &[
+                ("https://www.example.com/local/flood.html", Some("post-1")),
+                (
+                    "https://www.example.com/events/fair-20231115.html",
+                    Some("post-2"),
+                ),
+                ("https://www.example.com/2023/11/storm.html", None),
+            ],
+        );
+        let dedup = |options: &DedupOptions| {
+            let mut existing_items: ExistingItemsMap = HashMap::new();
+            let mut feed = Feed::new("https://www.example.com/news/", "");
+            feed.content = feed1.clone();
+            feed.remove_duplicates(&mut existing_items, &mut HashMap::new(), options)
+                .unwrap();
+            let mut feed = Feed::new("https://www.example.com/local/", "");
+            feed.content = feed2.clone();
+            feed.remove_duplicates(&mut existing_items, &mut HashMap::new(), options)
+                .unwrap();
+            feed.content
+        };
+
+        // the url heuristic keys both links by the date number 20231115
+        let content = dedup(&DedupOptions::default());
+        assert!(!content.contains("fair-20231115.html"));
+
+        let content = dedup(&DedupOptions {
+            replace_on_guid_only: true,
+            ..Default::default()
+        });
+        assert!(!content.contains("local/flood.html"));
+        assert!(content.contains("fair-20231115.html"));
+        assert!(content.contains("storm.html"));
+        assert_eq!(2, content.matches("<item>").count());
+    }
+
+    #[test]
+    fn test_rss_remove_duplicates_title_id() {
+        setup_test_logger();
+        let content = |channel: &str, items: &[(&str, &str)]| {
+            let items: String = items
+                .iter()
+                .map(|(link, title)| {
+                    format!("<item><title>{}</title><link>{}</link></item>", title, link)
+                })
+                .collect();
+            format!(
+                "<rss version=\"2.0\"><channel><link>https://tracker.example.com/{}/</link>{}</channel></rss>",
+                channel, items
+            )
+        };
+        // the links carry a session token instead of the ticket number
+        let feed1 = content(
+            "open",
+            &[(
+                "https://tracker.example.com/view?s=a81f3c",
+                "Login fails [#12345]",
+            )],
+        );
+        let feed2 = content(
+            "mine",
+            &[
+                (
+                    "https://tracker.example.com/view?s=3f9a1c",
+                    "Login fails after update [#12345]",
+                ),
+                (
+                    "https://tracker.example.com/view?s=7d4e2b",
+                    "Crash on start [#12346]",
+                ),
+            ],
+        );
+        let dedup = |options: &DedupOptions| {
+            let mut existing_items: ExistingItemsMap = HashMap::new();
+            let mut feed = Feed::new("https://tracker.example.com/open/", "");
+            feed.content = feed1.clone();
+            feed.remove_duplicates(&mut existing_items, &mut HashMap::new(), options)
+                .unwrap();
+            let mut feed = Feed::new("https://tracker.example.com/mine/", "");
+            feed.content = feed2.clone();
+            feed.remove_duplicates(&mut existing_items, &mut HashMap::new(), options)
+                .unwrap();
+            (feed.content, existing_items)
+        };
+
+        let (content, _) = dedup(&DedupOptions::default());
+        assert_eq!(2, content.matches("<item>").count());
+
+        let options = DedupOptions {
+            id_options: ids::IdOptions {
+                title_patterns: vec![(
+                    HostMatcher::new("tracker.example.com").unwrap(),
+                    Regex::new(r"\[#(\d+)\]").unwrap(),
+                )],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (content, existing_items) = dedup(&options);
+        assert!(!content.contains("Login fails after update"));
+        assert!(content.contains("Crash on start"));
+        let mut keys: Vec<_> = existing_items.keys().cloned().collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![
+                ("12345".to_string(), "tracker.example.com".to_string()),
+                ("12346".to_string(), "tracker.example.com".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dedup_batch() {
+        setup_test_logger();
+        let feeds = vec![
+            (
+                "https://www.stuttgarter-zeitung.de/news".to_string(),
+                include_str!("../testdata/channel1.rss").to_string(),
+            ),
+            (
+                "https://www.stuttgarter-zeitung.de/schlagzeilen".to_string(),
+                include_str!("../testdata/channel2.rss").to_string(),
+            ),
+        ];
+        let deduped = dedup_batch(&feeds, &DedupOptions::default()).unwrap();
+        assert_eq!(2, deduped.len());
+        assert_eq!(feeds[0].0, deduped[0].0);
+        assert_eq!(feeds[1].0, deduped[1].0);
+        assert_eq!(4, deduped[0].1.matches("<item>").count());
+        assert_eq!(1, deduped[1].1.matches("<item>").count());
+        // the same input gives the same output
+        assert_eq!(
+            deduped,
+            dedup_batch(&feeds, &DedupOptions::default()).unwrap()
+        );
+        // in reversed order the first channel keeps the shared item instead
+        let reversed: Vec<(String, String)> = feeds.into_iter().rev().collect();
+        let deduped = dedup_batch(&reversed, &DedupOptions::default()).unwrap();
+        assert_eq!(2, deduped[0].1.matches("<item>").count());
+        assert_eq!(3, deduped[1].1.matches("<item>").count());
+
+        let malformed = vec![("https://www.faz.net/rss".to_string(), "<rss".to_string())];
+        assert!(dedup_batch(&malformed, &DedupOptions::default()).is_err());
+    }
+
+    // records the decisions as strings
+    #[derive(Default)]
+    struct RecordingObserver {
+        decisions: Vec<String>,
+    }
+
+    impl DedupObserver for RecordingObserver {
+        fn on_kept(&mut self, link: &str, channel: &str) {
+            self.decisions.push(format!("kept {} {}", link, channel));
+        }
+        fn on_removed_cross_channel(&mut self, link: &str, channel: &str, previous_channel: &str) {
+            self.decisions
+                .push(format!("removed {} {} {}", link, channel, previous_channel));
+        }
+        fn on_aged_out(&mut self, link: &str) {
+            self.decisions.push(format!("aged out {}", link));
+        }
+        fn on_replaced(&mut self, link: &str, channel: &str) {
+            self.decisions
+                .push(format!("replaced {} {}", link, channel));
+        }
+        fn on_future_date(&mut self, link: &str) {
+            self.decisions.push(format!("future date {}", link));
+        }
+    }
+
+    #[test]
+    fn test_rss_dedup_observer() {
+        setup_test_logger();
+        let mut feed1 = Feed::new("https://www.stuttgarter-zeitung.de/news", "");
+        feed1.content = include_str!("../testdata/channel1.rss").to_string();
+        let mut feed2 = Feed::new("https://www.stuttgarter-zeitung.de/schlagzeilen", "");
+        feed2.content = include_str!("../testdata/channel2.rss").to_string();
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        let mut observer = RecordingObserver::default();
+        let options = DedupOptions::default();
+        for feed in [&mut feed1, &mut feed2] {
+            feed.remove_duplicates_with_observer(
+                &mut existing_items,
+                &mut HashMap::new(),
+                &options,
+                &mut observer,
+            )
+            .unwrap();
+        }
+        let news = "https://www.stuttgarter-zeitung.de/news";
+        let schlagzeilen = "https://www.stuttgarter-zeitung.de/schlagzeilen";
+        let kirchen = "https://www.stuttgarter-zeitung.de/inhalt.deutschlands-kirchen-am-kipppunkt-religion-ist-den-meisten-voellig-egal.c8990bf7-8c6a-435c-9612-1ad7ee9a60ed.html";
+        let klima = "https://www.stuttgarter-zeitung.de/inhalt.folgen-des-klimawandels-grosse-gesundheitsrisiken-durch-klimakrise.d46eec6a-8d67-457f-a8fb-b71a21481ae0.html";
+        let gaza = "https://www.faz.net/aktuell/politik/ausland/gaza-stadt-israelische-armee-fuehrt-razzia-in-schifa-klinik-durch-19314690.html";
+        let gaza2 = "https://www.faz.net/aktuell/politik/ausland/gaza-stadt-israelische-armee-fuehrt-razzia-in-schifa2-klinik-durch-19314690.html";
+        let nahost = "https://www.stuttgarter-zeitung.de/inhalt.krieg-in-nahost-israelische-streitkraefte-dringen-in-schifa-krankenhaus-ein.775c66f7-2820-4817-a8b6-786d445be7ca.html";
+        assert_eq!(
+            observer.decisions,
+            vec![
+                format!("kept {} {}", kirchen, news),
+                format!("kept {} {}", klima, news),
+                format!("kept {} {}", gaza, news),
+                // same id as gaza, first kept and then replaced by the version of gaza
+                format!("kept {} {}", gaza2, news),
+                format!("replaced {} {}", gaza2, news),
+                format!("kept {} {}", nahost, schlagzeilen),
+                format!("removed {} {} {}", kirchen, schlagzeilen, news),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rss_with_atom_link() {
+        const FEED1: &str = include_str!("../testdata/feedwithatomlink.rss");
+        setup_test_logger();
+        let mut feed1 = Feed::new(
+            "http://arduino-praxis.ch/feed/",
+            "testdata/atomlink_dedup.rss",
+        );
+        feed1.content = FEED1.to_string();
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        let result = feed1.remove_duplicates(
+            &mut existing_items,
+            &mut HashMap::new(),
+            &DedupOptions::default(),
+        );
+        // info!("Result: {:?}", result);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_rss_remove_duplicates_with_small_maxage() {
+        const FEED1: &str = include_str!("../testdata/channel1.rss");
+        setup_test_logger();
+        let mut feed1 = Feed::new(
+            "https://www.stuttgarter-zeitung.de/news",
+            "testdata/channel1_dedup_with_age.rss",
+        );
+        feed1.content = FEED1.to_string();
+
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+
+        let options = DedupOptions {
+            max_age: 1,
+            ..Default::default()
+        };
+        assert!(feed1
+            .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .is_ok());
+        assert!(feed1.write(&WriteOptions::default()).is_ok());
+        assert_eq!(0, feed1.content.matches("<item>").count());
+
+        let _ = fs::remove_file(&feed1.filename);
+    }
+
+    #[test]
+    fn test_rss_write_on_empty() {
+        const FEED1: &str = include_str!("../testdata/channel1.rss");
+        setup_test_logger();
+        let filename = "testdata/channel1_on_empty.rss";
+        let mut feed = Feed::new("https://www.stuttgarter-zeitung.de/news", filename);
+        feed.content = FEED1.to_string();
+        let options = DedupOptions {
+            max_age: 1,
+            ..Default::default()
+        };
+        assert!(feed
+            .remove_duplicates(&mut HashMap::new(), &mut HashMap::new(), &options)
+            .is_ok());
+        assert!(feed.is_empty());
+        let write_options = |on_empty| WriteOptions {
+            on_empty,
+            ..Default::default()
+        };
+
+        let _ = fs::remove_file(filename);
+        assert_eq!(feed.write(&write_options(OnEmpty::Skip)), Ok(false));
+        assert!(!Path::new(filename).exists());
+
+        // without a previous file keep-previous writes the empty feed
+        assert_eq!(feed.write(&write_options(OnEmpty::KeepPrevious)), Ok(true));
+        assert_eq!(fs::read_to_string(filename).unwrap(), feed.content);
+
+        fs::write(filename, FEED1).unwrap();
+        assert_eq!(feed.write(&write_options(OnEmpty::KeepPrevious)), Ok(false));
+        assert_eq!(fs::read_to_string(filename).unwrap(), FEED1);
+        assert_eq!(feed.write(&write_options(OnEmpty::Skip)), Ok(false));
+        assert_eq!(fs::read_to_string(filename).unwrap(), FEED1);
+
+        assert_eq!(feed.write(&write_options(OnEmpty::Write)), Ok(true));
+        assert_eq!(fs::read_to_string(filename).unwrap(), feed.content);
+
+        let _ = fs::remove_file(filename);
+    }
+
+    #[test]
+    fn test_rss_write_mirror_directories() {
+        const FEED1: &str = include_str!("../testdata/channel1.rss");
+        setup_test_logger();
+        let mirror = "testdata/mirror";
+        let _ = fs::create_dir(mirror);
+        let mut feed = Feed::new(
+            "https://www.stuttgarter-zeitung.de/news",
+            "testdata/channel1_mirrored.rss",
+        );
+        feed.content = FEED1.to_string();
+        let options = WriteOptions {
+            checksum: true,
+            mirror_directories: vec!["testdata/missing_mirror".to_string(), mirror.to_string()],
+            ..Default::default()
+        };
+        // the missing mirror directory fails but does not stop the others
+        assert_eq!(feed.write(&options), Ok(true));
+        let primary = fs::read(&feed.filename).unwrap();
+        let mirrored = fs::read("testdata/mirror/channel1_mirrored.rss").unwrap();
+        let mirrored_checksum =
+            fs::read_to_string("testdata/mirror/channel1_mirrored.rss.sha256").unwrap();
+        let primary_checksum = fs::read_to_string(format!("{}.sha256", feed.filename)).unwrap();
+
+        let _ = fs::remove_file(&feed.filename);
+        let _ = fs::remove_file(format!("{}.sha256", feed.filename));
+        let _ = fs::remove_dir_all(mirror);
+        assert_eq!(primary, FEED1.as_bytes());
+        assert_eq!(mirrored, primary);
+        assert_eq!(mirrored_checksum, primary_checksum);
+    }
+
+    #[test]
+    fn test_rss_write_backups() {
+        setup_test_logger();
+        let filename = "testdata/channel1_backup.rss";
+        let mut feed = Feed::new("https://www.stuttgarter-zeitung.de/news", filename);
+        let options = WriteOptions {
+            backups: 2,
+            ..Default::default()
+        };
+        for content in [
+            "<rss>1</rss>",
+            "<rss>2</rss>",
+            "<rss>3</rss>",
+            "<rss>4</rss>",
+        ] {
+            feed.content = content.to_string();
+            assert_eq!(feed.write(&options), Ok(true));
+        }
+        let current = fs::read_to_string(filename).unwrap();
+        let backup = fs::read_to_string(format!("{}.bak", filename)).unwrap();
+        let older_backup = fs::read_to_string(format!("{}.bak.1", filename)).unwrap();
+        let oldest_backup_exists = Path::new(&format!("{}.bak.2", filename)).exists();
+        for suffix in ["", ".bak", ".bak.1", ".bak.2"] {
+            let _ = fs::remove_file(format!("{}{}", filename, suffix));
+        }
+        assert_eq!(current, "<rss>4</rss>");
+        assert_eq!(backup, "<rss>3</rss>");
+        assert_eq!(older_backup, "<rss>2</rss>");
+        assert!(!oldest_backup_exists);
+    }
+
+    #[test]
+    fn test_rss_remove_duplicates_with_large_maxage() {
+        const FEED1: &str = include_str!("../testdata/channel1.rss");
+        setup_test_logger();
+        let mut feed1 = Feed::new(
+            "https://www.stuttgarter-zeitung.de/news",
+            "testdata/channel1_dedup_with_large_age.rss",
+        );
+        feed1.content = FEED1.to_string();
+
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+
+        let options = DedupOptions {
+            max_age: u32::MAX as u64,
+            ..Default::default()
+        };
+        assert!(feed1
+            .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .is_ok());
+        assert!(feed1.write(&WriteOptions::default()).is_ok());
+        assert_eq!(4, feed1.content.matches("<item>").count());
+
+        let _ = fs::remove_file(&feed1.filename);
+    }
+
+    #[test]
+    fn test_rss_remove_duplicates_with_dc_date_and_maxage() {
+        const FEED: &str = include_str!("../testdata/channeldcdate.rss");
+        setup_test_logger();
+        let mut feed = Feed::new(
+            "https://www.stuttgarter-zeitung.de/news",
+            "testdata/channeldcdate_dedup_with_age.rss",
+        );
+        // one item is recent, the others are from 2023
+        let recent = Utc::now().to_rfc3339();
+        feed.content = FEED.replace("2023-11-15T06:50:57Z", &recent);
+
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+
+        let options = DedupOptions {
+            max_age: 1,
+            ..Default::default()
+        };
+        assert!(feed
+            .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .is_ok());
+        assert_eq!(1, feed.content.matches("<item>").count());
+        assert!(feed.content.contains(&recent));
+    }
+
+    #[test]
+    fn test_item_date_fallbacks() {
+        let item = Element::parse(
+            "<item><dc:date xmlns:dc=\"http://purl.org/dc/elements/1.1/\">2023-11-15T06:51:06+01:00</dc:date></item>"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            item_date(&item).unwrap().to_rfc3339(),
+            "2023-11-15T05:51:06+00:00"
+        );
+        let item = Element::parse(
+            "<item><updated>2023-11-15T06:51:06Z</updated><pubDate>Wed, 15 Nov 2023 08:00:00 GMT</pubDate></item>"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            item_date(&item).unwrap().to_rfc3339(),
+            "2023-11-15T08:00:00+00:00"
+        );
+        let item =
+            Element::parse("<item><published>gestern</published></item>".as_bytes()).unwrap();
+        assert!(item_date(&item).is_none());
+        assert!(!check_item_not_older_than(
+            &item,
+            None,
+            1,
+            &Utc::now(),
+            false
+        ));
+        let item = Element::parse("<item><title>no date</title></item>".as_bytes()).unwrap();
+        assert!(check_item_not_older_than(
+            &item,
+            None,
+            1,
+            &Utc::now(),
+            false
+        ));
+        let first_seen = SystemTime::now() - std::time::Duration::from_secs(2 * 60 * 60);
+        assert!(!check_item_not_older_than(
+            &item,
+            Some(first_seen),
+            1,
+            &Utc::now(),
+            false
+        ));
+    }
+
+    #[test]
+    fn test_future_dates() {
+        setup_test_logger();
+        let now = Utc::now();
+        let dated = |date: DateTime<Utc>| {
+            let mut pub_date = Element::new("pubDate");
+            pub_date.children.push(XMLNode::Text(date.to_rfc2822()));
+            let mut item = Element::new("item");
+            item.children.push(XMLNode::Element(pub_date));
+            item
+        };
+        let future = dated(now + Duration::days(2));
+        assert!(future_date_skew(&future, &now).is_some_and(|skew| skew > Duration::days(1)));
+        assert!(future_date_skew(&dated(now + Duration::minutes(5)), &now).is_none());
+        assert!(future_date_skew(&dated(now - Duration::days(2)), &now).is_none());
+        // first seen two hours ago, the item never ages without clamping
+        let first_seen = SystemTime::now() - std::time::Duration::from_secs(2 * 60 * 60);
+        assert!(check_item_not_older_than(
+            &future,
+            Some(first_seen),
+            1,
+            &now,
+            false
+        ));
+        assert!(!check_item_not_older_than(
+            &future,
+            Some(first_seen),
+            1,
+            &now,
+            true
+        ));
+        assert!(check_item_not_older_than(
+            &future,
+            Some(first_seen),
+            3,
+            &now,
+            true
+        ));
+        assert!(check_item_not_older_than(&future, None, 1, &now, true));
+
+        // the observer is told about the item when it is deduplicated
+        let content = include_str!("../testdata/channel1.rss").replace(
+            "Wed, 15 Nov 2023 06:51:06 GMT",
+            &(now + Duration::days(2)).to_rfc2822(),
+        );
+        let mut feed = Feed::new("https://www.stuttgarter-zeitung.de/news", "");
+        feed.content = content;
+        let mut observer = RecordingObserver::default();
+        let options = DedupOptions {
+            max_age: 24,
+            ..Default::default()
+        };
+        feed.remove_duplicates_with_observer(
+            &mut HashMap::new(),
+            &mut HashMap::new(),
+            &options,
+            &mut observer,
+        )
+        .unwrap();
+        let future_dated: Vec<&String> = observer
+            .decisions
+            .iter()
+            .filter(|decision| decision.starts_with("future date "))
+            .collect();
+        assert_eq!(
+            future_dated,
+            vec!["future date https://www.stuttgarter-zeitung.de/inhalt.deutschlands-kirchen-am-kipppunkt-religion-ist-den-meisten-voellig-egal.c8990bf7-8c6a-435c-9612-1ad7ee9a60ed.html"]
+        );
+    }
+
+    #[test]
+    fn test_rss_remove_duplicates_item_order() {
+        const FEED1: &str = include_str!("../testdata/channel1.rss");
+        setup_test_logger();
+        let dates = |order: ItemOrder| {
+            let mut feed = Feed::new(
+                "https://www.stuttgarter-zeitung.de/news",
+                "testdata/channel1_order.rss",
+            );
+            feed.content = FEED1.to_string();
+            let options = DedupOptions {
+                item_order: order,
+                ..Default::default()
+            };
+            feed.remove_duplicates(&mut HashMap::new(), &mut HashMap::new(), &options)
+                .unwrap();
+            let rssroot = Element::parse(feed.content.as_bytes()).unwrap();
+            let channel = rssroot.get_child("channel").unwrap();
+            channel
+                .children
+                .iter()
+                .filter_map(XMLNode::as_element)
+                .filter(|item| item.name == "item")
+                .map(|item| item_date(item).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let oldest = dates(ItemOrder::Oldest);
+        assert_eq!(oldest.len(), 4);
+        assert!(oldest.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(oldest[0].to_rfc3339(), "2023-11-15T01:41:09+00:00");
+        let newest = dates(ItemOrder::Newest);
+        assert!(newest.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert_eq!(dates(ItemOrder::Source), newest);
+    }
+
+    #[test]
+    fn test_rss_stable_item_order() {
+        setup_test_logger();
+        let item = |id: &str, date: &str| {
+            format!(
+                "<item><link>https://www.example.com/news/story-{}.html</link>{}</item>",
+                id, date
+            )
+        };
+        let same_date = "<pubDate>Wed, 15 Nov 2023 09:00:00 +0000</pubDate>";
+        let items = [
+            item("1000001", same_date),
+            item("1000002", same_date),
+            item(
+                "1000003",
+                "<pubDate>Wed, 15 Nov 2023 07:00:00 +0000</pubDate>",
+            ),
+            item("1000004", ""),
+            item("1000005", ""),
+        ];
+        // two fetches of the feed, the second one with the items of the first one known
+        let serve = |order: ItemOrder, first: &[&String], second: &[&String]| {
+            let mut existing_items: ExistingItemsMap = HashMap::new();
+            let mut served = Vec::new();
+            for items in [first, second] {
+                let mut feed = Feed::new(
+                    "https://www.example.com/news.rss",
+                    "testdata/stable_order.rss",
+                );
+                feed.content = format!(
+                    "<rss version=\"2.0\"><channel><link>https://www.example.com/</link>{}</channel></rss>",
+                    items.iter().map(|item| item.as_str()).collect::<String>()
+                );
+                let options = DedupOptions {
+                    item_order: order,
+                    ..Default::default()
+                };
+                feed.remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+                    .unwrap();
+                served.push(feed.content);
+            }
+            served
+        };
+        let fetched: Vec<&String> = items.iter().collect();
+        let reordered: Vec<&String> = [4, 2, 1, 3, 0].iter().map(|index| &items[*index]).collect();
+        let served = serve(ItemOrder::Newest, &fetched, &reordered);
+        assert_ne!(served[0], served[1]);
+        let served = serve(ItemOrder::Stable, &fetched, &reordered);
+        assert_eq!(served[0], served[1]);
+        let served = &served[0];
+        let position = |id: &str| served.find(&format!("story-{}", id)).unwrap();
+        assert!(position("1000001") < position("1000002"));
+        assert!(position("1000002") < position("1000003"));
+        assert!(position("1000003") < position("1000004"));
+        assert!(position("1000004") < position("1000005"));
+    }
+
+    #[test]
+    fn test_rss_last_build_date() {
+        setup_test_logger();
+        let item = |id: &str, date: &str| {
+            format!(
+                "<item><link>https://www.example.com/news/story-{}.html</link><pubDate>{}</pubDate></item>",
+                id, date
+            )
+        };
+        let newest = item("1000001", "Wed, 15 Nov 2023 09:00:00 +0000");
+        let content = |channel: &str, items: &[&str]| {
+            format!(
+                "<rss version=\"2.0\"><channel><link>https://www.example.com/{}/</link><lastBuildDate>Wed, 15 Nov 2023 09:30:00 +0000</lastBuildDate>{}</channel></rss>",
+                channel,
+                items.concat()
+            )
+        };
+        let older = item("1000002", "Wed, 15 Nov 2023 07:00:00 +0000");
+        let middle = item("1000003", "Wed, 15 Nov 2023 08:00:00 +0000");
+        let last_build_date = |mode: LastBuildDate| {
+            let mut existing_items: ExistingItemsMap = HashMap::new();
+            let options = DedupOptions {
+                last_build_date: mode,
+                ..Default::default()
+            };
+            // the newest item is already served by another channel
+            let mut feed = Feed::new("https://www.example.com/top/", "");
+            feed.content = content("top", &[&newest]);
+            feed.remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+                .unwrap();
+            let mut feed = Feed::new("https://www.example.com/news/", "");
+            feed.content = content("news", &[&older, &newest, &middle]);
+            feed.remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+                .unwrap();
+            assert!(!feed.content.contains("story-1000001"));
+            let rssroot = Element::parse(feed.content.as_bytes()).unwrap();
+            let channel = rssroot.get_child("channel").unwrap();
+            let date = channel.get_child("lastBuildDate").unwrap().get_text();
+            parse_date(&date.unwrap()).unwrap()
+        };
+        assert_eq!(
+            last_build_date(LastBuildDate::Keep).to_rfc3339(),
+            "2023-11-15T09:30:00+00:00"
+        );
+        assert_eq!(
+            last_build_date(LastBuildDate::Newest).to_rfc3339(),
+            "2023-11-15T08:00:00+00:00"
+        );
+        let now = last_build_date(LastBuildDate::Now);
+        assert!((Utc::now() - now).num_seconds().abs() < 60);
+
+        // a channel without <lastBuildDate> gets one before its items
+        let mut feed = Feed::new("https://www.example.com/news/", "");
+        feed.content = format!(
+            "<rss version=\"2.0\"><channel><link>https://www.example.com/news/</link>{}</channel></rss>",
+            older
+        );
+        let options = DedupOptions {
+            last_build_date: LastBuildDate::Newest,
+            ..Default::default()
+        };
+        feed.remove_duplicates(&mut HashMap::new(), &mut HashMap::new(), &options)
+            .unwrap();
+        let position = |text: &str| feed.content.find(text).unwrap();
+        assert!(
+            position("<lastBuildDate>Wed, 15 Nov 2023 07:00:00 +0000</lastBuildDate>")
+                < position("<item>")
+        );
+    }
+
+    #[test]
+    fn test_rss_remove_duplicates_media() {
+        const FEED1: &str = include_str!("../testdata/videochannel1.rss");
+        const FEED2: &str = include_str!("../testdata/videochannel2.rss");
+        setup_test_logger();
+        for (dedup_media, kept) in [(false, 2), (true, 1)] {
+            let mut feed1 = Feed::new(
+                "https://www.youtube.com/feeds/videos.xml?channel_id=UC1",
+                "testdata/videochannel1_dedup.rss",
+            );
+            feed1.content = FEED1.to_string();
+            let mut feed2 = Feed::new(
+                "https://www.youtube.com/feeds/videos.xml?playlist_id=PL2",
+                "testdata/videochannel2_dedup.rss",
+            );
+            feed2.content = FEED2.to_string();
+            let mut existing_items: ExistingItemsMap = HashMap::new();
+            let options = DedupOptions {
+                dedup_media,
+                ..Default::default()
+            };
+            feed1
+                .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+                .unwrap();
+            feed2
+                .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+                .unwrap();
+            assert_eq!(2, feed1.content.matches("<item>").count());
+            // feed 2 links the shared video with a playlist parameter and a different host
+            assert_eq!(kept, feed2.content.matches("<item>").count());
+            assert!(feed2.content.contains("Git für Einsteiger"));
+        }
+    }
+
+    #[test]
+    fn test_rss_remove_duplicates_link_sources() {
+        const FEED1: &str = include_str!("../testdata/guidonly.rss");
+        setup_test_logger();
+        let url1 = "https://www.faz.net/rss/aktuell/politik/ausland/";
+        let url2 = "https://www.faz.net/rss/aktuell/politik/";
+        for (link_sources, kept) in [(vec![], 2), (vec!["link", "guid"], 0)] {
+            let options = DedupOptions {
+                link_sources: link_sources.into_iter().map(str::to_string).collect(),
+                channel_identities: HashMap::from([(url2.to_string(), "faz-politik".to_string())]),
+                ..Default::default()
+            };
+            let mut existing_items: ExistingItemsMap = HashMap::new();
+            let mut feed1 = Feed::new(url1, "testdata/guidonly1_dedup.rss");
+            feed1.content = FEED1.to_string();
+            let mut feed2 = Feed::new(url2, "testdata/guidonly2_dedup.rss");
+            feed2.content = FEED1.to_string();
+            feed1
+                .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+                .unwrap();
+            feed2
+                .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+                .unwrap();
+            assert_eq!(2, feed1.content.matches("<item>").count());
+            assert_eq!(kept, feed2.content.matches("<item>").count());
+            if kept == 0 {
+                assert!(existing_items
+                    .contains_key(&("19314690".to_string(), "www.faz.net".to_string())));
+            }
+        }
+    }
+
+    #[test]
+    fn test_item_link() {
+        let item = Element::parse(
+            "<item xmlns:atom=\"http://www.w3.org/2005/Atom\"><guid isPermaLink=\"false\">faz-19314690</guid>\
+             <atom:link href=\"https://www.faz.net/19314690.html\"/></item>"
+                .as_bytes(),
+        )
+        .unwrap();
+        let sources = ["guid".to_string(), "link".to_string()];
+        assert_eq!(
+            item_link(&item, &sources).as_deref(),
+            Some("https://www.faz.net/19314690.html")
+        );
+        assert_eq!(
+            item_link(&item, &sources[..1]).as_deref(),
+            Some("faz-19314690")
+        );
+        assert_eq!(item_link(&item, &["comments".to_string()]), None);
+        let item =
+            Element::parse("<item><title>Eilmeldung</title><link/></item>".as_bytes()).unwrap();
+        assert_eq!(item_link(&item, &[]).as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_item_key_guid_permalink() {
+        let options = DedupOptions {
+            link_sources: vec!["guid".to_string()],
+            ..Default::default()
+        };
+        let channel = "https://www.faz.net/aktuell/";
+        let key = |item: &str, channel: &str| {
+            let item = Element::parse(item.as_bytes()).unwrap();
+            let link = item_link(&item, &options.link_sources).unwrap();
+            item_key(&item, &link, channel, &options)
+        };
+        let url = "https://www.faz.net/aktuell/gaza-stadt-19314690.html";
+        // a permalink guid is a url, with or without the attribute
+        let permalink = key(&format!("<item><guid>{}</guid></item>", url), channel);
+        assert_eq!(
+            permalink,
+            ("19314690".to_string(), "www.faz.net".to_string())
+        );
+        assert_eq!(
+            key(
+                &format!("<item><guid isPermaLink=\"true\">{}</guid></item>", url),
+                channel
+            ),
+            permalink
+        );
+        // an opaque guid is used as it is, scoped by the host of the channel
+        let opaque = format!("<item><guid isPermaLink=\"false\">{}</guid></item>", url);
+        assert_eq!(
+            key(&opaque, channel),
+            (url.to_string(), "www.faz.net".to_string())
+        );
+        assert_ne!(key(&opaque, channel), permalink);
+        let opaque = "<item><guid isPermaLink=\"false\">faz-19314690</guid></item>";
+        assert_eq!(
+            key(opaque, channel),
+            ("faz-19314690".to_string(), "www.faz.net".to_string())
+        );
+        assert_eq!(
+            key(opaque, "https://www.spiegel.de/"),
+            ("faz-19314690".to_string(), "www.spiegel.de".to_string())
+        );
+        assert_eq!(
+            key(opaque, "faz-politik"),
+            ("faz-19314690".to_string(), "faz-politik".to_string())
+        );
+    }
+
+    #[test]
+    fn test_rss_remove_duplicates_generator() {
+        const FEED1: &str = include_str!("../testdata/channel1.rss");
+        setup_test_logger();
+        let mut feed = Feed::new(
+            "https://www.stuttgarter-zeitung.de/news",
+            "testdata/channel1_generator.rss",
+        );
+        feed.content = FEED1.to_string();
+        let options = DedupOptions {
+            generator: true,
+            dedup_metadata: true,
+            ..Default::default()
+        };
+        feed.remove_duplicates(&mut HashMap::new(), &mut HashMap::new(), &options)
+            .unwrap();
+        // a second run replaces the elements of the first
+        let stats = feed
+            .remove_duplicates(&mut HashMap::new(), &mut HashMap::new(), &options)
+            .unwrap();
+        let rssroot = Element::parse(feed.content.as_bytes()).unwrap();
+        let channel = rssroot.get_child("channel").unwrap();
+        let generators: Vec<_> = channel
+            .children
+            .iter()
+            .filter_map(XMLNode::as_element)
+            .filter(|child| child.name == "generator")
+            .collect();
+        assert_eq!(generators.len(), 1);
+        assert_eq!(
+            generators[0].get_text().unwrap(),
+            format!("rssdeduper/{}", env!("CARGO_PKG_VERSION"))
+        );
+        let dedup = channel.get_child(("dedup", RSSDEDUPER_NAMESPACE)).unwrap();
+        assert_eq!(dedup.attributes["removed"], stats.removed.to_string());
+        assert!(parse_date(&dedup.attributes["timestamp"]).is_some());
+    }
+
+    #[test]
+    fn test_rss_signature_dedup() {
+        setup_test_logger();
+        let channel = |link: &str, title: &str, item_link: &str| {
+            format!(
+                r#"<rss version="2.0"><channel><title>{0}</title><link>{0}</link>
+<item><title>{1}</title><link>{2}</link><guid>{2}</guid>
+<description>Der Gemeinderat hat am Dienstag den Haushalt beschlossen.</description>
+<enclosure url="https://www.example.com/haushalt.jpg" type="image/jpeg" length="1"/></item>
+</channel></rss>"#,
+                link, title, item_link
+            )
+        };
+        let original = channel(
+            "https://www.example.com/news",
+            "Haushalt beschlossen",
+            "https://www.example.com/news/haushalt-123456.html",
+        );
+        // a different url and guid and slightly different wording of the title
+        let repost = channel(
+            "https://www.example.com/schlagzeilen",
+            "Haushalt: beschlossen!",
+            "https://www.example.com/schlagzeilen/haushalt-654321.html",
+        );
+        // the number of items of the last feed and of the firehose
+        let items = |feeds: &[(&str, &String)], options: &DedupOptions| {
+            let mut existing_items: ExistingItemsMap = HashMap::new();
+            let mut signatures: SignatureMap = HashMap::new();
+            let mut count = 0;
+            for (url, content) in feeds {
+                let mut feed = Feed::new(url, "");
+                feed.content = content.to_string();
+                feed.remove_duplicates(&mut existing_items, &mut signatures, options)
+                    .unwrap();
+                count = feed.content.matches("<item>").count();
+            }
+            let firehose =
+                Feed::firehose("", "", &existing_items, 0, &DedupOptions::default()).unwrap();
+            (count, firehose.content.matches("<item>").count())
+        };
+        let feeds = [
+            ("https://www.example.com/news", &original),
+            ("https://www.example.com/schlagzeilen", &repost),
+        ];
+        assert_eq!(items(&feeds, &DedupOptions::default()), (1, 2));
+        let options = DedupOptions {
+            signature_dedup: true,
+            ..Default::default()
+        };
+        // the repost is removed and the firehose only lists items
+        assert_eq!(items(&feeds, &options), (0, 1));
+        // the channel that published the content first keeps it under a new url
+        let moved = channel(
+            "https://www.example.com/news",
+            "Haushalt: beschlossen!",
+            "https://www.example.com/news/haushalt-654321.html",
+        );
+        let feeds = [
+            ("https://www.example.com/news", &original),
+            ("https://www.example.com/news", &moved),
+        ];
+        assert_eq!(items(&feeds, &options), (1, 2));
+    }
+
+    #[test]
+    fn test_rss_dedup_provenance() {
+        const FEED1: &str = include_str!("../testdata/channel1.rss");
+        const FEED2: &str = include_str!("../testdata/channel2.rss");
+        setup_test_logger();
+        let mut feed1 = Feed::new(
+            "https://www.stuttgarter-zeitung.de/news",
+            "testdata/channel1_provenance.rss",
+        );
+        let mut feed2 = Feed::new(
+            "https://www.stuttgarter-zeitung.de/schlagzeilen",
+            "testdata/channel2_provenance.rss",
+        );
+        let options = DedupOptions {
+            dedup_provenance: true,
+            ..Default::default()
+        };
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        // the item both channels publish is first seen in the first one, which keeps serving it
+        let mut dedup = |feed: &mut Feed, content: &str| {
+            feed.content = content.to_string();
+            feed.remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+                .unwrap();
+        };
+        dedup(&mut feed1, FEED1);
+        dedup(&mut feed2, FEED2);
+        dedup(&mut feed1, FEED1);
+        let (_, (_, _, first_seen)) = existing_items
+            .iter()
+            .find(|((id, _), _)| id.contains("c8990bf7"))
+            .unwrap();
+        let first_seen = DateTime::<Utc>::from(*first_seen).to_rfc3339();
+        let provenance = |feed: &Feed, link: &str| {
+            let rssroot = Element::parse(feed.content.as_bytes()).unwrap();
+            let channel = rssroot.get_child("channel").unwrap();
+            let item = channel
+                .children
+                .iter()
+                .filter_map(XMLNode::as_element)
+                .find(|item| {
+                    item.get_child("link")
+                        .and_then(|link| link.get_text())
+                        .is_some_and(|text| text.contains(link))
+                })
+                .map(|item| item.get_child(("dedup", RSSDEDUPER_NAMESPACE)).cloned());
+            item.map(|dedup| {
+                let dedup = dedup.unwrap();
+                (
+                    dedup.attributes["firstSeen"].clone(),
+                    dedup.attributes["channel"].clone(),
+                )
+            })
+        };
+        assert_eq!(
+            provenance(&feed1, "kirchen"),
+            Some((
+                first_seen,
+                "https://www.stuttgarter-zeitung.de/news".to_string()
+            ))
+        );
+        assert_eq!(provenance(&feed2, "kirchen"), None);
+        assert_eq!(
+            provenance(&feed2, "schifa").map(|(_, channel)| channel),
+            Some("https://www.stuttgarter-zeitung.de/schlagzeilen".to_string())
+        );
+        // the annotation is replaced and not repeated
+        assert_eq!(feed1.content.matches("rssdeduper:dedup ").count(), 4);
+    }
+
+    #[test]
+    fn test_rss_retain_dropped_items() {
+        const FEED1: &str = include_str!("../testdata/channel1.rss");
+        setup_test_logger();
+        let filename = "testdata/channel1_retained.rss";
+        let _ = fs::remove_file(filename);
+        let start = FEED1.find("<item>\n      <title>Folgen").unwrap();
+        let end = start + FEED1[start..].find("</item>").unwrap() + "</item>".len();
+        let dropped = format!("{}{}", &FEED1[..start], &FEED1[end..]);
+        let options = DedupOptions {
+            max_age: u32::MAX as u64,
+            retain_dropped_items: true,
+            ..Default::default()
+        };
+        let dedup = |existing_items: &mut ExistingItemsMap, options: &DedupOptions| {
+            let mut feed = Feed::new("https://www.stuttgarter-zeitung.de/news", filename);
+            feed.content = dropped.clone();
+            let stats = feed
+                .remove_duplicates(existing_items, &mut HashMap::new(), options)
+                .unwrap();
+            (feed, stats)
+        };
+
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        let mut feed = Feed::new("https://www.stuttgarter-zeitung.de/news", filename);
+        feed.content = FEED1.to_string();
+        feed.remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .unwrap();
+        feed.write(&WriteOptions::default()).unwrap();
+
+        // the second fetch no longer lists the item, it is retained from the served file
+        let (feed, stats) = dedup(&mut existing_items, &options);
+        assert_eq!(stats.items, 3);
+        assert_eq!(4, feed.content.matches("<item>").count());
+        assert_eq!(
+            1,
+            feed.content
+                .matches("<title>Folgen des Klimawandels")
+                .count()
+        );
+
+        // after a restart the item is known again from the served file
+        let mut restarted: ExistingItemsMap = HashMap::new();
+        let (feed, _) = dedup(&mut restarted, &options);
+        assert_eq!(4, feed.content.matches("<item>").count());
+        assert_eq!(restarted.len(), 3);
+
+        // items older than max_age are not retained, nor without the option
+        let (feed, _) = dedup(
+            &mut existing_items.clone(),
+            &DedupOptions {
+                max_age: 1,
+                ..options.clone()
+            },
+        );
+        assert_eq!(0, feed.content.matches("<item>").count());
+        let (feed, _) = dedup(&mut existing_items, &DedupOptions::default());
+        assert_eq!(3, feed.content.matches("<item>").count());
+
+        let _ = fs::remove_file(filename);
+    }
+
+    #[test]
+    fn test_warm_existing_items() {
+        setup_test_logger();
+        // the entries of a served Atom feed are attributed to its web page
+        let feeds = vec![(
+            "https://blog.rust-lang.org/feed.xml".to_string(),
+            "atomchannel1.xml".to_string(),
+        )];
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        assert_eq!(
+            warm_existing_items(
+                &mut existing_items,
+                &feeds,
+                "testdata",
+                &DedupOptions::default()
+            ),
+            2
+        );
+        assert!(existing_items
+            .values()
+            .all(|(channel, _, _)| channel == "https://blog.rust-lang.org/"));
+
+        let feeds = vec![(
+            "https://www.stuttgarter-zeitung.de/news".to_string(),
+            "channel1.rss".to_string(),
+        )];
+        let options = DedupOptions::default();
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        assert_eq!(
+            warm_existing_items(&mut existing_items, &feeds, "testdata", &options),
+            3
+        );
+        assert_eq!(
+            warm_existing_items(&mut existing_items, &feeds, "testdata", &options),
+            0
+        );
+
+        // the first iteration removes the item already served by the other channel
+        let mut feed2 = Feed::new("https://www.stuttgarter-zeitung.de/schlagzeilen", "");
+        feed2.content = include_str!("../testdata/channel2.rss").to_string();
+        assert_eq!(
+            feed2.remove_duplicates(&mut existing_items, &mut HashMap::new(), &options),
+            Ok(DedupStats {
+                items: 2,
+                removed: 1
+            })
+        );
+
+        // the channel of a served file with rewritten links is unknown
+        let options = DedupOptions {
+            base_url: Some("https://www.stuttgarter-zeitung.de/".to_string()),
+            ..Default::default()
+        };
+        let feeds = vec![(
+            "https://www.stuttgarter-zeitung.de/news".to_string(),
+            "news".to_string(),
+        )];
+        let _ = fs::create_dir("testdata/warm");
+        fs::write(
+            "testdata/warm/news",
+            include_str!("../testdata/channel1.rss"),
+        )
+        .unwrap();
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        let added = warm_existing_items(&mut existing_items, &feeds, "testdata/warm", &options);
+        let _ = fs::remove_dir_all("testdata/warm");
+        assert_eq!(added, 0);
+    }
+
+    #[test]
+    fn test_media_key() {
+        let item = Element::parse(
+            "<item xmlns:media=\"http://search.yahoo.com/mrss/\"><link>https://www.faz.net/video/</link>\
+             <media:content url=\"https://media0.faz.net/ppmedia/2039518452/teaser.jpg\" medium=\"image\"/>\
+             <media:content url=\"https://video.faz.net/clips/19314690.mp4\" type=\"video/mp4\"/></item>"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            media_key(&item, &DedupOptions::default()),
+            Some(("19314690".to_string(), "video.faz.net".to_string()))
+        );
+        let options = DedupOptions {
+            dedup_media: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            item_key(&item, "https://www.faz.net/video/", "", &options).0,
+            "19314690"
+        );
+        let item =
+            Element::parse("<item><link>https://www.faz.net/video/</link></item>".as_bytes())
+                .unwrap();
+        assert_eq!(media_key(&item, &options), None);
+    }
+
+    #[test]
+    fn test_media_key_youtube_atom() {
+        const FEED: &str = include_str!("../testdata/youtubechannel.xml");
+        let feed = Element::parse(FEED.as_bytes()).unwrap();
+        let entries: Vec<&Element> = feed
+            .children
+            .iter()
+            .filter_map(XMLNode::as_element)
+            .filter(|entry| is_item(entry))
+            .collect();
+        assert_eq!(entries.len(), 2);
+        let options = DedupOptions::default();
+        assert_eq!(
+            media_key(entries[0], &options),
+            Some(("6B5pBRFc2k0".to_string(), "www.youtube.com".to_string()))
+        );
+        // without yt:videoId the video is the media:content in the media:group, not its
+        // thumbnail
+        assert_eq!(
+            media_key(entries[1], &options),
+            Some(("19314690".to_string(), "video.faz.net".to_string()))
+        );
+        let options = DedupOptions {
+            dedup_media: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            item_key(
+                entries[0],
+                "https://www.youtube.com/watch?v=6B5pBRFc2k0",
+                "https://www.youtube.com/channel/UCaYhcUwRBNscFNUKTjgPFiA",
+                &options
+            ),
+            ("6B5pBRFc2k0".to_string(), "www.youtube.com".to_string())
+        );
+    }
+
+    #[test]
+    fn test_rss_remove_duplicates_with_source() {
+        const FEED1: &str = include_str!("../testdata/sourcechannel1.rss");
+        const FEED2: &str = include_str!("../testdata/sourcechannel2.rss");
+        setup_test_logger();
+        let mut feed1 = Feed::new(
+            "https://aggregator-a.example.com/politik",
+            "testdata/sourcechannel1_dedup.rss",
+        );
+        feed1.content = FEED1.to_string();
+        let mut feed2 = Feed::new(
+            "https://aggregator-b.example.com/schlagzeilen",
+            "testdata/sourcechannel2_dedup.rss",
+        );
+        feed2.content = FEED2.to_string();
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        let options = DedupOptions {
+            use_source: true,
+            ..Default::default()
+        };
+
+        assert!(feed1
+            .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .is_ok());
+        assert!(feed2
+            .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .is_ok());
+        // both reposts are attributed to the wire feed, so the second one is kept as a
+        // same-channel duplicate and replaced by the original item
+        assert_eq!(2, feed1.content.matches("<item>").count());
+        assert_eq!(1, feed2.content.matches("<item>").count());
+        assert_eq!(0, feed2.content.matches("aktualisiert").count());
+        let (_, (channel, _, _)) = existing_items
+            .iter()
+            .find(|((id, _), _)| id == "19314690")
+            .unwrap();
+        assert_eq!(channel, "https://www.wire.example.com/rss");
+    }
 
-     */
-    pub fn remove_duplicates(
-        &mut self,
-        existing_items: &mut ExistingItemsMap,
-        max_age: u64
-    ) -> Result<(), String> {
-        let mut rssroot = Element::parse(self.content.as_bytes())
-            .map_err(|e| format!("RSS feed {} XML parse error: {}", self.url, e))?;
-        let mut channel = String::new();
-        traverse_and_modify(&mut rssroot, existing_items, &mut channel, max_age)?;
+    #[test]
+    fn test_rss_remove_duplicates_without_source() {
+        const FEED1: &str = include_str!("../testdata/sourcechannel1.rss");
+        const FEED2: &str = include_str!("../testdata/sourcechannel2.rss");
+        setup_test_logger();
+        let mut feed1 = Feed::new(
+            "https://aggregator-a.example.com/politik",
+            "testdata/sourcechannel1_dedup.rss",
+        );
+        feed1.content = FEED1.to_string();
+        let mut feed2 = Feed::new(
+            "https://aggregator-b.example.com/schlagzeilen",
+            "testdata/sourcechannel2_dedup.rss",
+        );
+        feed2.content = FEED2.to_string();
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        let options = DedupOptions::default();
 
-        let config = EmitterConfig::new()
-            .indent_string("    ")
-            .line_separator("\n")
-            .perform_indent(true)
-            .normalize_empty_elements(true);
-        let mut new_content = Vec::with_capacity(self.content.len());
-        rssroot
-            .write_with_config(&mut new_content, config)
-            .map_err(|e| format!("RSS feed {} XML write error: {}", self.url, e))?;
-        self.content = String::from_utf8(new_content).unwrap();
-        Ok(())
+        assert!(feed1
+            .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .is_ok());
+        assert!(feed2
+            .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .is_ok());
+        // without source attribution the repost in the second channel is a cross-channel duplicate
+        assert_eq!(2, feed1.content.matches("<item>").count());
+        assert_eq!(0, feed2.content.matches("<item>").count());
     }
 
-    // write the content of the feed to its file
-    pub fn write(&self) -> Result<(), String> {
-        let tmp_filename = format!("{}.tmp", &self.filename);
-        let mut file = fs::File::create(&tmp_filename)
-            .map_err(|e| format!("Temporary file {} cannot be created: {}", tmp_filename, e))?;
-        file.write_all(self.content.as_bytes())
-            .map_err(|e| format!("Temporary file {} cannot be written: {}", tmp_filename, e))?;
-        fs::rename(&tmp_filename, &self.filename)
-            .map_err(|e| format!("File {} cannot be renamed: {}", tmp_filename, e))?;
-        Ok(())
+    #[test]
+    fn test_existing_items_usage() {
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        assert_eq!(existing_items_usage(&existing_items), (0, 0));
+        for id in ["8990", "8991", "8992"] {
+            let mut item = Element::new("item");
+            let mut link = Element::new("link");
+            link.children.push(XMLNode::Text(format!(
+                "https://www.faz.net/aktuell/{}.html",
+                id
+            )));
+            item.children.push(XMLNode::Element(link));
+            existing_items.insert(
+                (id.to_string(), "www.faz.net".to_string()),
+                ("https://www.faz.net".to_string(), item, SystemTime::now()),
+            );
+        }
+        let (entries, bytes) = existing_items_usage(&existing_items);
+        assert_eq!(entries, 3);
+        assert!(
+            bytes > 3 * "<item><link>https://www.faz.net/aktuell/8990.html</link></item>".len()
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::path::Path;
+    #[test]
+    fn test_evict_outdated_items_after_reload() {
+        setup_test_logger();
+        let recent = Utc::now().to_rfc2822();
+        let two_days_ago = SystemTime::now() - std::time::Duration::from_secs(48 * 60 * 60);
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        let mut signatures: SignatureMap = HashMap::new();
+        for (id, pub_date) in [
+            ("8990", Some("Wed, 15 Nov 2023 06:51:06 GMT")),
+            ("8991", Some(recent.as_str())),
+            ("8992", None),
+        ] {
+            let mut item = Element::new("item");
+            if let Some(pub_date) = pub_date {
+                let mut date = Element::new("pubDate");
+                date.children.push(XMLNode::Text(pub_date.to_string()));
+                item.children.push(XMLNode::Element(date));
+            }
+            signatures.insert(
+                format!("signature{}", id),
+                (
+                    "https://www.faz.net".to_string(),
+                    item.clone(),
+                    two_days_ago,
+                ),
+            );
+            existing_items.insert(
+                (id.to_string(), "www.faz.net".to_string()),
+                ("https://www.faz.net".to_string(), item, two_days_ago),
+            );
+        }
+        let filename = "testdata/state_evict.json";
+        save_existing_items(filename, &existing_items, &signatures).unwrap();
+        let (mut reloaded, mut reloaded_signatures) = load_existing_items(filename).unwrap();
+        let _ = fs::remove_file(filename);
+        assert_eq!(reloaded.len(), 3);
+        assert_eq!(reloaded_signatures.len(), 3);
+        let key = ("8992".to_string(), "www.faz.net".to_string());
+        assert_eq!(
+            reloaded[&key]
+                .2
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            two_days_ago.duration_since(UNIX_EPOCH).unwrap().as_secs()
+        );
 
-    use super::super::utilities::setup_test_logger;
-    use super::*;
+        evict_outdated_items(&mut reloaded, 24, false);
+        let mut ids: Vec<_> = reloaded.keys().map(|(id, _)| id.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["8991", "8992"]);
+        evict_outdated_items(&mut reloaded_signatures, 24, false);
+        let mut signatures: Vec<_> = reloaded_signatures.keys().map(String::as_str).collect();
+        signatures.sort();
+        assert_eq!(signatures, vec!["signature8991", "signature8992"]);
+    }
 
     #[test]
-    fn test_rss_read() {
+    fn test_rss_document_limits() {
+        setup_test_logger();
+        let items: String = (0..100_000)
+            .map(|index| {
+                format!(
+                    "<item><title>Item {}</title><link>https://www.faz.net/aktuell/{}.html</link></item>",
+                    index,
+                    1_000_000 + index
+                )
+            })
+            .collect();
+        // the document is cut off after its items, so the limit error shows that the check
+        // stops counting at the limit instead of reading, or parsing, the whole document
+        let content = format!(
+            "<rss version=\"2.0\"><channel><title>Big</title><link>https://www.faz.net/</link>{}<item><title>Cut off",
+            items
+        );
+        let mut feed = Feed::new("https://www.faz.net/rss/big/", "");
+        feed.content = content.clone();
+        let options = DedupOptions {
+            max_document_elements: 1000,
+            ..Default::default()
+        };
+        let error = feed
+            .remove_duplicates(&mut HashMap::new(), &mut HashMap::new(), &options)
+            .unwrap_err();
+        assert!(error.contains("more than the limit of 1000 elements"));
+        assert_eq!(feed.content, content);
+
+        let options = DedupOptions {
+            max_document_bytes: 1_000_000,
+            ..Default::default()
+        };
+        let error = feed
+            .remove_duplicates(&mut HashMap::new(), &mut HashMap::new(), &options)
+            .unwrap_err();
+        assert!(error.contains("more than the limit of 1000000"));
+
+        // feeds within the limits are deduplicated as before
+        feed.content = include_str!("../testdata/channel1.rss").to_string();
+        let options = DedupOptions {
+            max_document_bytes: 1_000_000,
+            max_document_elements: 1000,
+            ..Default::default()
+        };
+        assert!(feed
+            .remove_duplicates(&mut HashMap::new(), &mut HashMap::new(), &options)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_rss_max_title_length() {
+        setup_test_logger();
+        let title = "Folgen des Klimawandels: ".repeat(20);
+        let content = |channel: &str, title: &str| {
+            format!(
+                "<rss version=\"2.0\"><channel><title>{}</title><link>https://www.faz.net/{}/</link>\
+                 <item><title>{}</title><link>https://www.faz.net/aktuell/klima-1000001.html</link></item>\
+                 <item><title>Kurz</title><link>https://www.faz.net/aktuell/kurz-1000002.html</link></item>\
+                 </channel></rss>",
+                channel, channel, title
+            )
+        };
+        let options = DedupOptions {
+            max_title_length: 40,
+            ..Default::default()
+        };
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        let mut feed1 = Feed::new("https://www.faz.net/rss/wissen/", "");
+        feed1.content = content("wissen", &title);
+        assert!(feed1
+            .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .is_ok());
+        assert!(feed1
+            .content
+            .contains("<title>Folgen des Klimawandels: Folgen des…</title>"));
+        assert!(feed1.content.contains("<title>Kurz</title>"));
+        // the known item keeps its full title
+        let stored = existing_items
+            .values()
+            .find_map(|(_, item, _)| item.get_child("title")?.get_text().filter(|t| t.len() > 40))
+            .unwrap();
+        assert_eq!(stored, title.as_str());
+
+        // the item is still a duplicate in another channel
+        let mut feed2 = Feed::new("https://www.faz.net/rss/politik/", "");
+        feed2.content = content("politik", &title);
+        assert_eq!(
+            feed2.remove_duplicates(&mut existing_items, &mut HashMap::new(), &options),
+            Ok(DedupStats {
+                items: 2,
+                removed: 2
+            })
+        );
+
+        assert_eq!(truncate_title("kurz", 4), None);
+        assert_eq!(truncate_title("Klimawandel", 6).unwrap(), "Klima…");
+    }
+
+    #[test]
+    fn test_rss_parse_error_position() {
         setup_test_logger();
         let mut feed = Feed::new(
-            "http://www.faz.net/aktuell/politik/ausland/?rssview=1",
-            "testdata/testfazausland.rss",
+            "https://www.stuttgarter-zeitung.de/schlagzeilen",
+            "testdata/malformed_dedup.rss",
         );
-        let result = feed.read();
-        assert!(result.is_ok());
-        assert!(result.unwrap()); // updated == true
-        assert!(!feed.content.is_empty());
-        let result = feed.read();
-        assert!(result.is_ok());
-        assert!(!result.unwrap()); // updated == false
-        assert!(!feed.content.is_empty());
+        feed.content = include_str!("../testdata/malformed.rss").to_string();
+        let error = feed
+            .remove_duplicates(
+                &mut HashMap::new(),
+                &mut HashMap::new(),
+                &DedupOptions::default(),
+            )
+            .unwrap_err();
+        assert!(error.contains("XML parse error at line 14, column"));
+        assert!(error.contains("Schifa-Krankenhaus ein</titel>"));
     }
 
     #[test]
-    fn test_rss_write() {
+    fn test_update_feed_map_preserves_unchanged_feeds() {
+        setup_test_logger();
+        const BUILD_DATE: &str = "<lastBuildDate>Wed, 15 Nov 2023 07:11:10 GMT</lastBuildDate>";
+        let mut feed_map: HashMap<String, Feed> = HashMap::new();
+        let feeds = vec![
+            ("https://www.faz.net/rss".to_string(), "faz.rss".to_string()),
+            ("https://www.stz.de/rss".to_string(), "stz.rss".to_string()),
+            ("https://www.bbc.com/rss".to_string(), "bbc.rss".to_string()),
+        ];
+        update_feed_map(&mut feed_map, &feeds, "/var/www/html/rss/");
+        assert_eq!(feed_map.len(), 3);
+        for feed in feed_map.values_mut() {
+            feed.last_build_date = BUILD_DATE.to_string();
+        }
+
+        // OPML reload: faz is unchanged, stz got a new file, bbc was removed and nzz added
+        let feeds = vec![
+            ("https://www.faz.net/rss".to_string(), "faz.rss".to_string()),
+            (
+                "https://www.stz.de/rss".to_string(),
+                "stz-new.rss".to_string(),
+            ),
+            ("https://www.nzz.ch/rss".to_string(), "nzz.rss".to_string()),
+        ];
+        update_feed_map(&mut feed_map, &feeds, "/var/www/html/rss/");
+        assert_eq!(feed_map.len(), 3);
+        assert_eq!(
+            feed_map["https://www.faz.net/rss"].last_build_date,
+            BUILD_DATE
+        );
+        assert!(feed_map["https://www.stz.de/rss"]
+            .last_build_date
+            .is_empty());
+        assert_eq!(
+            feed_map["https://www.stz.de/rss"].filename,
+            "/var/www/html/rss/stz-new.rss"
+        );
+        assert!(!feed_map.contains_key("https://www.bbc.com/rss"));
+        assert!(feed_map["https://www.nzz.ch/rss"]
+            .last_build_date
+            .is_empty());
+    }
+
+    #[test]
+    fn test_strip_doctype() {
+        const NETSCAPE: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE rss PUBLIC "-//Netscape Communications//DTD RSS 0.91//EN" "http://my.netscape.com/publish/formats/rss-0.91.dtd">
+<rss version="0.91"></rss>"#;
+        assert_eq!(
+            strip_doctype(NETSCAPE),
+            "<?xml version=\"1.0\"?>\n\n<rss version=\"0.91\"></rss>"
+        );
+        const SUBSET: &str = r#"<!DOCTYPE rss [ <!ENTITY gt2 ">>"> ]><rss/>"#;
+        assert_eq!(strip_doctype(SUBSET), "<rss/>");
+        assert!(matches!(strip_doctype("<rss/>"), Cow::Borrowed("<rss/>")));
+        const QUOTED: &str =
+            "<rss><!-- <!DOCTYPE x> --><description><![CDATA[<!DOCTYPE html>]]></description></rss>";
+        assert!(matches!(strip_doctype(QUOTED), Cow::Borrowed(QUOTED)));
+        assert_eq!(
+            strip_doctype("<!-- feed --><!DOCTYPE rss><rss/>"),
+            "<!-- feed --><rss/>"
+        );
+    }
+
+    #[test]
+    fn test_sanitize_xml() {
+        assert_eq!(
+            sanitize_xml("<title>Tom & Jerry &amp; &#38; &#x26; &auml;</title>"),
+            "<title>Tom &amp; Jerry &amp; &#38; &#x26; &auml;</title>"
+        );
+        assert_eq!(
+            sanitize_xml("<a>R&D</a><![CDATA[a & b]]><!-- c & d --><b>\u{1}x\ty</b>"),
+            "<a>R&amp;D</a><![CDATA[a & b]]><!-- c & d --><b>x\ty</b>"
+        );
+        assert!(matches!(sanitize_xml("<rss/>"), Cow::Borrowed("<rss/>")));
+    }
+
+    #[test]
+    fn test_rss_parse_strictness() {
         setup_test_logger();
+        let content = include_str!("../testdata/channel1.rss").replace(
+            "<title>Folgen des Klimawandels",
+            "<title>Klima & Gesundheit: Folgen des Klimawandels",
+        );
         let mut feed = Feed::new(
-            "http://www.faz.net/aktuell/politik/ausland/?rssview=1",
-            "testdata/testfazausland.rss",
+            "https://www.stuttgarter-zeitung.de/news",
+            "testdata/channel1_lenient.rss",
         );
-        // feed.content = String::from("Test");
-        let result = feed.read();
-        assert!(result.is_ok());
-        let result = feed.write();
-        assert!(result.is_ok());
-        assert!(Path::new(&feed.filename).exists());
-        let _ = fs::remove_file(&feed.filename);
+        feed.content = content.clone();
+        let error = feed
+            .remove_duplicates(
+                &mut HashMap::new(),
+                &mut HashMap::new(),
+                &DedupOptions::default(),
+            )
+            .unwrap_err();
+        assert!(error.contains("XML parse error"));
+        feed.content = content;
+        let options = DedupOptions {
+            parse_strictness: ParseStrictness::Lenient,
+            ..Default::default()
+        };
+        feed.remove_duplicates(&mut HashMap::new(), &mut HashMap::new(), &options)
+            .unwrap();
+        assert!(feed
+            .content
+            .contains("<title>Klima &amp; Gesundheit: Folgen des Klimawandels"));
     }
 
     #[test]
-    fn test_rss_remove_duplicates() {
-        const FEED1: &str = include_str!("../testdata/channel1.rss");
-        const FEED2: &str = include_str!("../testdata/channel2.rss");
+    fn test_rss_parse_timeout() {
         setup_test_logger();
-        let mut feed1 = Feed::new(
+        let content = include_str!("../testdata/channel1.rss");
+        let start = content.find("<item>").unwrap();
+        let end = content.find("</item>").unwrap() + "</item>".len();
+        let items: String = (0..20000)
+            .map(|index| content[start..end].replace("</link>", &format!("?copy={}</link>", index)))
+            .collect();
+        let large = format!("{}{}{}", &content[..start], items, &content[start..]);
+        let options = DedupOptions {
+            parse_timeout: Some(std::time::Duration::from_millis(1)),
+            ..Default::default()
+        };
+        let mut feed = Feed::new(
             "https://www.stuttgarter-zeitung.de/news",
-            "testdata/channel1_dedup.rss",
+            "testdata/channel1_timeout.rss",
+        );
+        feed.content = large;
+        // parsing stops at the deadline instead of building the whole document first
+        let error = feed
+            .parse_content(&options, Some(Instant::now()))
+            .unwrap_err();
+        assert!(error.contains("timed out"));
+        assert!(feed.parse_content(&options, None).is_ok());
+        feed.parse(&options);
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        let error = feed
+            .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .unwrap_err();
+        assert!(error.contains("timed out"));
+        assert!(existing_items.is_empty());
+        assert!(!Path::new("testdata/channel1_timeout.rss").exists());
+
+        feed.content = content.to_string();
+        let options = DedupOptions {
+            parse_timeout: Some(std::time::Duration::from_secs(60)),
+            ..Default::default()
+        };
+        feed.remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .unwrap();
+        assert!(!existing_items.is_empty());
+    }
+
+    // the temporary file is a named pipe, so writing blocks until the test reads it
+    #[cfg(unix)]
+    #[test]
+    fn test_rss_write_timeout() {
+        setup_test_logger();
+        let filename = "testdata/channel1_write_timeout.rss";
+        let tmp_filename = format!("{}.tmp", filename);
+        let _ = fs::remove_file(filename);
+        let _ = fs::remove_file(&tmp_filename);
+        let status = std::process::Command::new("mkfifo")
+            .arg(&tmp_filename)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let mut feed = Feed::new("https://www.stuttgarter-zeitung.de/news", filename);
+        feed.content = include_str!("../testdata/channel1.rss").to_string();
+        let options = WriteOptions {
+            timeout: Some(std::time::Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let error = feed.write(&options).unwrap_err();
+        assert!(error.contains("the write stage timed out"));
+        // the next write does not share the temporary file with the abandoned one
+        let error = feed.write(&options).unwrap_err();
+        assert!(error.contains("is still running"));
+
+        // reading the pipe lets the abandoned write finish
+        assert_eq!(fs::read_to_string(&tmp_filename).unwrap(), feed.content);
+        let started = Instant::now();
+        while WRITES_IN_FLIGHT.lock().unwrap().contains(filename) {
+            assert!(started.elapsed() < std::time::Duration::from_secs(10));
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        // the abandoned write renamed the pipe into place
+        fs::remove_file(filename).unwrap();
+        assert_eq!(feed.write(&options), Ok(true));
+        assert_eq!(fs::read_to_string(filename).unwrap(), feed.content);
+        let _ = fs::remove_file(filename);
+    }
+
+    #[test]
+    fn test_rss_retain_dropped_atom_entries() {
+        const FEED1: &str = include_str!("../testdata/atomchannel1.xml");
+        setup_test_logger();
+        let filename = "testdata/atom1_retained.xml";
+        let _ = fs::remove_file(filename);
+        let start = FEED1.find("<entry>").unwrap();
+        let end = start + FEED1[start..].find("</entry>").unwrap() + "</entry>".len();
+        let dropped = format!("{}{}", &FEED1[..start], &FEED1[end..]);
+        let options = DedupOptions {
+            max_age: u32::MAX as u64,
+            retain_dropped_items: true,
+            ..Default::default()
+        };
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        let mut feed = Feed::new("https://blog.rust-lang.org/feed.xml", filename);
+        for content in [FEED1, &dropped] {
+            feed.content = content.to_string();
+            feed.remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+                .unwrap();
+            feed.write(&WriteOptions::default()).unwrap();
+        }
+        // the second fetch no longer lists the first entry, it is retained from the served file
+        assert_eq!(2, feed.content.matches("<entry>").count());
+        assert_eq!(
+            1,
+            feed.content
+                .matches("<title>Announcing Rust 1.74.0</title>")
+                .count()
+        );
+        let _ = fs::remove_file(filename);
+    }
+
+    #[test]
+    fn test_rss_atom_dedup() {
+        setup_test_logger();
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        let mut feed1 = Feed::new(
+            "https://blog.rust-lang.org/feed.xml",
+            "testdata/atom1_dedup.xml",
+        );
+        feed1.content = include_str!("../testdata/atomchannel1.xml").to_string();
+        let stats = feed1
+            .remove_duplicates(
+                &mut existing_items,
+                &mut HashMap::new(),
+                &DedupOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(
+            stats,
+            DedupStats {
+                items: 2,
+                removed: 0
+            }
+        );
+        let (channel, entry, _) = existing_items
+            .values()
+            .find(|(_, entry, _)| item_guid(entry).unwrap().ends_with("Rust-1.74.0.html"))
+            .unwrap();
+        // the web page of the feed, not its rel="self" link
+        assert_eq!(channel, "https://blog.rust-lang.org/");
+        assert!(is_atom(entry, "entry"));
+        // and stays an Atom entry when it is saved in the state file
+        let mut xml = Vec::new();
+        entry.write(&mut xml).unwrap();
+        assert!(is_atom(&Element::parse(xml.as_slice()).unwrap(), "entry"));
+
+        let mut feed2 = Feed::new(
+            "https://planet.example.org/rust/atom.xml",
+            "testdata/atom2_dedup.xml",
+        );
+        feed2.content = include_str!("../testdata/atomchannel2.xml").to_string();
+        let mut observer = RecordingObserver::default();
+        let stats = feed2
+            .remove_duplicates_with_observer(
+                &mut existing_items,
+                &mut HashMap::new(),
+                &DedupOptions::default(),
+                &mut observer,
+            )
+            .unwrap();
+        assert_eq!(
+            stats,
+            DedupStats {
+                items: 2,
+                removed: 1
+            }
+        );
+        assert!(!feed2.content.contains("Announcing Rust 1.74.0"));
+        assert!(feed2.content.contains("This Week in Rust 521"));
+        assert!(observer.decisions.contains(&format!(
+            "removed {} {} {}",
+            "https://blog.rust-lang.org/2023/11/16/Rust-1.74.0.html",
+            "https://planet.example.org/rust/",
+            "https://blog.rust-lang.org/"
+        )));
+        let served = Element::parse(feed2.content.as_bytes()).unwrap();
+        assert!(is_atom(&served, "feed"));
+        assert_eq!(count_items(&served), 1);
+
+        // entries are aged by their <updated>, not by when they were first published
+        let now = Local::now().with_timezone(&Utc);
+        let entry = |published: i64, updated: i64| {
+            format!(
+                "<feed xmlns=\"http://www.w3.org/2005/Atom\"><link href=\"https://www.example.com/\"/>\
+                 <entry><link href=\"https://www.example.com/post-{}.html\"/><published>{}</published><updated>{}</updated></entry></feed>",
+                published,
+                (now - Duration::hours(published)).to_rfc3339(),
+                (now - Duration::hours(updated)).to_rfc3339()
+            )
+        };
+        let options = DedupOptions {
+            max_age: 24,
+            ..Default::default()
+        };
+        let mut feed = Feed::new("https://www.example.com/atom.xml", "testdata/atom_age.xml");
+        feed.content = entry(100, 1);
+        let stats = feed
+            .remove_duplicates(&mut HashMap::new(), &mut HashMap::new(), &options)
+            .unwrap();
+        assert_eq!(stats.removed, 0);
+        feed.content = entry(1, 100);
+        let stats = feed
+            .remove_duplicates(&mut HashMap::new(), &mut HashMap::new(), &options)
+            .unwrap();
+        assert_eq!(stats.removed, 1);
+    }
+
+    #[test]
+    fn test_rss_atom_output_options() {
+        const FEED1: &str = include_str!("../testdata/atomchannel1.xml");
+        setup_test_logger();
+        // a later version of the first entry with the same id
+        let start = FEED1.find("<entry>").unwrap();
+        let end = start + FEED1[start..].find("</entry>").unwrap() + "</entry>".len();
+        let repost = FEED1[start..end].replace("2023-11-16T00:00:00", "2023-11-20T00:00:00");
+        let content = format!("{}{}{}", &FEED1[..end], repost, &FEED1[end..]);
+        let options = DedupOptions {
+            duplicate_guids: KeepDuplicate::Newest,
+            item_order: ItemOrder::Oldest,
+            last_build_date: LastBuildDate::Newest,
+            max_title_length: 16,
+            dedup_provenance: true,
+            strip_namespaces: true,
+            base_url: Some("https://www.bodobolero.com/rss/".to_string()),
+            ..Default::default()
+        };
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        let mut feed = Feed::new("https://blog.rust-lang.org/feed.xml", "atom1.xml");
+        feed.content = content;
+        let stats = feed
+            .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .unwrap();
+        assert_eq!(stats.items, 3);
+        let served = Element::parse(feed.content.as_bytes()).unwrap();
+        // the namespace is kept, so the feed is still an Atom feed
+        assert!(is_atom(&served, "feed"));
+        let entries = feed_items(&served);
+        assert_eq!(entries.len(), 2);
+        // the newest of the entries with the same id is kept, the oldest entry comes first
+        let dates: Vec<_> = entries
+            .iter()
+            .filter_map(|entry| item_date(entry))
+            .collect();
+        assert_eq!(
+            dates,
+            vec![
+                parse_date("2023-10-05T00:00:00+00:00").unwrap(),
+                parse_date("2023-11-20T00:00:00+00:00").unwrap(),
+            ]
+        );
+        for entry in &entries {
+            let title = entry.get_child("title").unwrap().get_text().unwrap();
+            assert_eq!(title, "Announcing…");
+            assert!(entry.get_child("dedup").is_some());
+        }
+        // the feed is dated by its newest entry and points to where it is served
+        let updated = served.get_child("updated").unwrap().get_text().unwrap();
+        assert_eq!(updated, "2023-11-20T00:00:00+00:00");
+        assert!(feed
+            .content
+            .contains("href=\"https://www.bodobolero.com/rss/atom1.xml\" rel=\"self\""));
+
+        // the merged feed lists the entries as RSS items
+        let firehose = Feed::firehose("", "", &existing_items, 0, &options).unwrap();
+        assert_eq!(2, firehose.content.matches("<item>").count());
+        assert!(firehose
+            .content
+            .contains("<link>https://blog.rust-lang.org/2023/10/05/Rust-1.73.0.html</link>"));
+        assert!(firehose.content.contains(
+            "<guid isPermaLink=\"false\">https://blog.rust-lang.org/2023/10/05/Rust-1.73.0.html</guid>"
+        ));
+        assert!(firehose
+            .content
+            .contains("<pubDate>Thu, 5 Oct 2023 00:00:00 +0000</pubDate>"));
+    }
+
+    #[test]
+    fn test_rss_link_without_host() {
+        setup_test_logger();
+        let link = "tag:blogger.com,1999:blog-6021296093126498395.post-4531235463154337925";
+        let content = format!(
+            "<rss version=\"2.0\"><channel><link>https://www.example.com/</link>\
+             <item><link>{}</link></item></channel></rss>",
+            link
+        );
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        let mut feed = Feed::new("https://www.example.com/rss", "testdata/tag_link.rss");
+        feed.content = content;
+        let stats = feed
+            .remove_duplicates(
+                &mut existing_items,
+                &mut HashMap::new(),
+                &DedupOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(
+            stats,
+            DedupStats {
+                items: 1,
+                removed: 0
+            }
+        );
+        assert!(feed.content.contains(link));
+    }
+
+    #[test]
+    fn test_rss_rejects_entity_expansion() {
+        const FEED1: &str = include_str!("../testdata/xmlbomb.rss");
+        setup_test_logger();
+        let mut feed1 = Feed::new("https://www.example.com/", "testdata/xmlbomb_dedup.rss");
+        feed1.content = FEED1.to_string();
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        let result = feed1.remove_duplicates(
+            &mut existing_items,
+            &mut HashMap::new(),
+            &DedupOptions::default(),
+        );
+        assert!(result.unwrap_err().contains("XML parse error"));
+        assert!(existing_items.is_empty());
+    }
+
+    #[test]
+    fn test_rss_channel_link_change() {
+        setup_test_logger();
+        let content = |channel_link: &str, ids: &[&str]| {
+            let items: String = ids
+                .iter()
+                .map(|id| {
+                    format!(
+                        "<item><link>https://www.example.com/news/story-{}.html</link></item>",
+                        id
+                    )
+                })
+                .collect();
+            format!(
+                "<rss version=\"2.0\"><channel><link>{}</link>{}</channel></rss>",
+                channel_link, items
+            )
+        };
+        // removed items of the second iteration, after the channel link changed to channel_link
+        let removed = |channel_key: ChannelKey, channel_link: &str| {
+            let options = DedupOptions {
+                channel_key,
+                ..Default::default()
+            };
+            let mut existing_items: ExistingItemsMap = HashMap::new();
+            let mut feed = Feed::new(
+                "https://www.example.com/news.rss",
+                "testdata/channel_link_change.rss",
+            );
+            feed.content = content("https://www.example.com/", &["1000001", "1000002"]);
+            feed.remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+                .unwrap();
+            feed.content = content(channel_link, &["1000001", "1000002", "1000003"]);
+            let stats = feed
+                .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+                .unwrap();
+            assert_eq!(stats.items, 3);
+            stats.removed
+        };
+        // the items of the feed are taken for duplicates of those under its old channel link
+        assert_eq!(
+            removed(ChannelKey::Link, "https://www.example.com/news/"),
+            2
+        );
+        assert_eq!(
+            removed(ChannelKey::Host, "https://www.example.com/news/"),
+            0
+        );
+        assert_eq!(removed(ChannelKey::Host, "https://news.example.org/"), 2);
+        assert_eq!(removed(ChannelKey::FeedUrl, "https://news.example.org/"), 0);
+    }
+
+    #[test]
+    fn test_rss_remove_duplicates_with_channel_identities() {
+        const FEED1: &str = include_str!("../testdata/sectionpolitik.rss");
+        const FEED2: &str = include_str!("../testdata/sectionsport.rss");
+        setup_test_logger();
+        let mut options = DedupOptions::default();
+        options.channel_identities.insert(
+            "https://www.example-news.com/politik/rss".to_string(),
+            "example-news-politik".to_string(),
+        );
+        options.channel_identities.insert(
+            "https://www.example-news.com/sport/rss".to_string(),
+            "example-news-sport".to_string(),
+        );
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        let mut feed1 = Feed::new(
+            "https://www.example-news.com/politik/rss",
+            "testdata/sectionpolitik_dedup.rss",
         );
         feed1.content = FEED1.to_string();
         let mut feed2 = Feed::new(
-            "https://www.stuttgarter-zeitung.de/schlagzeilen",
-            "testdata/channel2_dedup.rss",
+            "https://www.example-news.com/sport/rss",
+            "testdata/sectionsport_dedup.rss",
         );
         feed2.content = FEED2.to_string();
-        let mut existing_items: ExistingItemsMap = HashMap::new();
 
-        assert!(feed1.remove_duplicates(&mut existing_items, 0).is_ok());
-        assert!(feed2.remove_duplicates(&mut existing_items, 0).is_ok());
-        assert!(feed1.write().is_ok());
-        assert!(feed2.write().is_ok());
-        assert_eq!(4, feed1.content.matches("<item>").count());
+        assert!(feed1
+            .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .is_ok());
+        assert!(feed2
+            .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .is_ok());
+        // the shared article is a cross-channel duplicate and only served in politik
+        assert_eq!(2, feed1.content.matches("<item>").count());
         assert_eq!(1, feed2.content.matches("<item>").count());
-        assert_eq!(0, feed1.content.matches("chifa2").count());
-        assert!(feed2.content != FEED2);
+        assert_eq!(0, feed2.content.matches("Olympia").count());
+        let channel = |id: &str| {
+            existing_items
+                .get(&(id.to_string(), "www.example-news.com".to_string()))
+                .map(|(channel, _, _)| channel.clone())
+        };
+        assert_eq!(channel("100001").unwrap(), "example-news-politik");
+        assert_eq!(channel("100003").unwrap(), "example-news-sport");
 
-        let _ = fs::remove_file(&feed1.filename);
-        let _ = fs::remove_file(&feed2.filename);
+        // without identities both sections are the same channel and the sport item is
+        // replaced by the politik article
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        feed1.content = FEED1.to_string();
+        feed2.content = FEED2.to_string();
+        let options = DedupOptions::default();
+        assert!(feed1
+            .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .is_ok());
+        assert!(feed2
+            .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .is_ok());
+        assert_eq!(2, feed2.content.matches("<item>").count());
+        assert_eq!(0, feed2.content.matches("Streit um Olympia").count());
     }
 
     #[test]
-    fn test_rss_with_atom_link() {
-        const FEED1: &str = include_str!("../testdata/feedwithatomlink.rss");
+    fn test_firehose_max_items() {
+        const FEED1: &str = include_str!("../testdata/channel1.rss");
+        const FEED2: &str = include_str!("../testdata/channel2.rss");
+        setup_test_logger();
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        for (url, content) in [
+            ("https://www.stuttgarter-zeitung.de/news", FEED1),
+            ("https://www.stuttgarter-zeitung.de/schlagzeilen", FEED2),
+        ] {
+            let mut feed = Feed::new(url, "testdata/firehose_channel.rss");
+            feed.content = content.to_string();
+            assert!(feed
+                .remove_duplicates(
+                    &mut existing_items,
+                    &mut HashMap::new(),
+                    &DedupOptions::default()
+                )
+                .is_ok());
+        }
+        assert_eq!(existing_items.len(), 4);
+
+        let firehose = Feed::firehose(
+            "https://www.bodobolero.com/rss/firehose.rss",
+            "testdata/firehose.rss",
+            &existing_items,
+            0,
+            &DedupOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(4, firehose.content.matches("</item>").count());
+        // items keep the namespace declarations of their source feed
+        let root = Element::parse(firehose.content.as_bytes()).unwrap();
+        let channel = root.get_child("channel").unwrap();
+        let media = channel
+            .children
+            .iter()
+            .filter_map(|node| node.as_element())
+            .filter(|child| child.name == "item")
+            .flat_map(|item| item.children.iter().filter_map(|node| node.as_element()))
+            .filter(|child| child.name == "content" && child.prefix.as_deref() == Some("media"))
+            .collect::<Vec<_>>();
+        assert!(!media.is_empty());
+        assert!(media
+            .iter()
+            .all(|content| content.namespace.as_deref() == Some(MEDIA_NAMESPACE)));
+
+        let firehose = Feed::firehose(
+            "https://www.bodobolero.com/rss/firehose.rss",
+            "testdata/firehose.rss",
+            &existing_items,
+            2,
+            &DedupOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(2, firehose.content.matches("</item>").count());
+        let newest = firehose.content.find("Krieg in Nahost").unwrap();
+        let second = firehose.content.find("Deutschlands Kirchen").unwrap();
+        assert!(newest < second);
+        assert!(Element::parse(firehose.content.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_rss_write_checksum() {
+        setup_test_logger();
+        let mut feed = Feed::new(
+            "https://www.stuttgarter-zeitung.de/news",
+            "testdata/channel1_checksum.rss",
+        );
+        let checksum_filename = "testdata/channel1_checksum.rss.sha256";
+        let _ = fs::remove_file(checksum_filename);
+        feed.content = include_str!("../testdata/channel1.rss").to_string();
+        let options = WriteOptions {
+            checksum: true,
+            ..Default::default()
+        };
+        assert_eq!(feed.write(&options), Ok(true));
+
+        let written = fs::read(&feed.filename).unwrap();
+        let checksum = fs::read_to_string(checksum_filename).unwrap();
+        assert_eq!(
+            checksum,
+            format!("{:x}  channel1_checksum.rss\n", Sha256::digest(&written))
+        );
+        assert!(!Path::new("testdata/channel1_checksum.rss.sha256.tmp").exists());
+
+        let _ = fs::remove_file(&feed.filename);
+        let _ = fs::remove_file(checksum_filename);
+    }
+
+    #[test]
+    fn test_rss_remove_duplicate_guids_in_same_channel() {
+        const FEED1: &str = include_str!("../testdata/dupguidsamechannel.rss");
         setup_test_logger();
         let mut feed1 = Feed::new(
-            "http://arduino-praxis.ch/feed/",
-            "testdata/atomlink_dedup.rss",
+            "https://blog.example.com/feed",
+            "testdata/dupguidsamechannel_dedup.rss",
         );
         feed1.content = FEED1.to_string();
         let mut existing_items: ExistingItemsMap = HashMap::new();
-        let result = feed1.remove_duplicates(&mut existing_items, 0);
-        // info!("Result: {:?}", result);
-        assert!(result.is_ok());
+        assert!(feed1
+            .remove_duplicates(
+                &mut existing_items,
+                &mut HashMap::new(),
+                &DedupOptions::default()
+            )
+            .is_ok());
+        assert_eq!(2, feed1.content.matches("<item>").count());
+        assert_eq!(1, feed1.content.matches("Release notes (page 1)").count());
+        assert_eq!(0, feed1.content.matches("Release notes (page 2)").count());
+
+        feed1.content = FEED1.to_string();
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        let options = DedupOptions {
+            duplicate_guids: KeepDuplicate::Newest,
+            ..Default::default()
+        };
+        assert!(feed1
+            .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .is_ok());
+        assert_eq!(2, feed1.content.matches("<item>").count());
+        assert_eq!(0, feed1.content.matches("Release notes (page 1)").count());
+        assert_eq!(1, feed1.content.matches("Release notes (page 2)").count());
+        // the newest item is kept at its own position
+        assert!(feed1.content.find("Welcome").unwrap() < feed1.content.find("page 2").unwrap());
+        assert_eq!(1, feed1.content.matches("07:30:00 GMT").count());
     }
 
     #[test]
-    fn test_rss_remove_duplicates_with_small_maxage() {
-        const FEED1: &str = include_str!("../testdata/channel1.rss");
+    fn test_rss_freeze_pubdate() {
+        const FEED1: &str = include_str!("../testdata/dupguidsamechannel.rss");
         setup_test_logger();
         let mut feed1 = Feed::new(
-            "https://www.stuttgarter-zeitung.de/news",
-            "testdata/channel1_dedup_with_age.rss",
+            "https://blog.example.com/feed",
+            "testdata/dupguidsamechannel_frozen.rss",
         );
         feed1.content = FEED1.to_string();
-        
         let mut existing_items: ExistingItemsMap = HashMap::new();
+        let options = DedupOptions {
+            duplicate_guids: KeepDuplicate::Newest,
+            freeze_pubdate: true,
+            ..Default::default()
+        };
+        assert!(feed1
+            .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .is_ok());
+        // the content of the newest item with the date of the first appearance
+        assert_eq!(2, feed1.content.matches("<item>").count());
+        assert_eq!(1, feed1.content.matches("Release notes (page 2)").count());
+        assert_eq!(1, feed1.content.matches("06:51:06 GMT").count());
+        assert_eq!(0, feed1.content.matches("07:30:00 GMT").count());
 
-        assert!(feed1.remove_duplicates(&mut existing_items, 1).is_ok());
-        assert!(feed1.write().is_ok());
-        assert_eq!(0, feed1.content.matches("<item>").count());
+        // an update in a later iteration keeps the first date as well
+        feed1.content = FEED1.replace("07:30:00 GMT", "08:15:00 GMT");
+        assert!(feed1
+            .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .is_ok());
+        assert_eq!(1, feed1.content.matches("06:51:06 GMT").count());
+        assert_eq!(0, feed1.content.matches("08:15:00 GMT").count());
 
-        let _ = fs::remove_file(&feed1.filename);
+        // an item modified in a later iteration is served with its new content and, only
+        // with freeze_pubdate, its first date
+        const ITEM: &str = "<rss version=\"2.0\"><channel><title>Example Blog</title>\
+            <link>https://blog.example.com/</link><item><title>Release notes</title>\
+            <link>https://blog.example.com/?p=release-notes</link>\
+            <description>First version</description>\
+            <pubDate>Wed, 15 Nov 2023 06:51:06 GMT</pubDate></item></channel></rss>";
+        for freeze_pubdate in [true, false] {
+            let options = DedupOptions {
+                update_modified_items: true,
+                freeze_pubdate,
+                ..Default::default()
+            };
+            let mut existing_items: ExistingItemsMap = HashMap::new();
+            feed1.content = ITEM.to_string();
+            assert!(feed1
+                .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+                .is_ok());
+            feed1.content = ITEM.replace("First version", "Corrected version").replace(
+                "06:51:06 GMT</pubDate>",
+                "08:15:00 GMT</pubDate><updated>2023-11-15T08:15:00Z</updated>",
+            );
+            assert!(feed1
+                .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+                .is_ok());
+            assert_eq!(1, feed1.content.matches("Corrected version").count());
+            assert_eq!(
+                freeze_pubdate,
+                feed1.content.contains("06:51:06 GMT"),
+                "{}",
+                feed1.content
+            );
+            assert_eq!(!freeze_pubdate, feed1.content.contains("08:15:00 GMT"));
+        }
+    }
+
+    #[test]
+    fn test_rss_strip_namespaces() {
+        const FEED1: &str = include_str!("../testdata/feedwithatomlink.rss");
+        setup_test_logger();
+        let mut feed1 = Feed::new("http://arduino-praxis.ch/feed/", "");
+        feed1.content = FEED1.to_string();
+        let options = DedupOptions {
+            strip_namespaces: true,
+            dedup_metadata: true,
+            ..Default::default()
+        };
+        assert!(feed1
+            .remove_duplicates(&mut HashMap::new(), &mut HashMap::new(), &options)
+            .is_ok());
+        assert!(!feed1.content.contains("xmlns"));
+        assert!(!Regex::new(r"</?[A-Za-z_]+:")
+            .unwrap()
+            .is_match(&feed1.content));
+        // still a plain RSS 2.0 document with all items and their other elements
+        let rssroot = Element::parse(feed1.content.as_bytes()).unwrap();
+        assert_eq!(rssroot.name, "rss");
+        assert_eq!(rssroot.attributes["version"], "2.0");
+        let channel = rssroot.get_child("channel").unwrap();
+        assert_eq!(
+            channel.get_child("link").unwrap().get_text().unwrap(),
+            "http://arduino-praxis.ch"
+        );
+        assert_eq!(count_items(&rssroot), FEED1.matches("<item>").count());
+        let item = channel.get_child("item").unwrap();
+        assert!(item.get_child("pubDate").is_some());
+        assert!(item.get_child("creator").is_none());
+    }
 
+    #[test]
+    fn test_rss_compact() {
+        const FEED1: &str = include_str!("../testdata/feedwithatomlink.rss");
+        setup_test_logger();
+        let dedup = |compact: bool| {
+            let mut feed = Feed::new("http://arduino-praxis.ch/feed/", "");
+            feed.content = FEED1.to_string();
+            let options = DedupOptions {
+                compact,
+                ..Default::default()
+            };
+            feed.remove_duplicates(&mut HashMap::new(), &mut HashMap::new(), &options)
+                .unwrap();
+            feed.content
+        };
+        let pretty = dedup(false);
+        let compact = dedup(true);
+        assert!(compact.len() < pretty.len());
+        assert!(!compact.contains(">\n"));
+        assert!(!compact.contains(">    <"));
+        assert_eq!(
+            Element::parse(compact.as_bytes()).unwrap(),
+            Element::parse(pretty.as_bytes()).unwrap()
+        );
     }
 
     #[test]
-    fn test_rss_remove_duplicates_with_large_maxage() {
-        const FEED1: &str = include_str!("../testdata/channel1.rss");
+    fn test_rss_rewrite_channel_links() {
+        const FEED1: &str = include_str!("../testdata/feedwithatomlink.rss");
         setup_test_logger();
         let mut feed1 = Feed::new(
-            "https://www.stuttgarter-zeitung.de/news",
-            "testdata/channel1_dedup_with_large_age.rss",
+            "http://arduino-praxis.ch/feed/",
+            "testdata/atomlink_rewritten.rss",
         );
         feed1.content = FEED1.to_string();
-        
         let mut existing_items: ExistingItemsMap = HashMap::new();
+        let options = DedupOptions {
+            base_url: Some("https://www.bodobolero.com/rss/".to_string()),
+            ..Default::default()
+        };
+        assert!(feed1
+            .remove_duplicates(&mut existing_items, &mut HashMap::new(), &options)
+            .is_ok());
+        let served_url = "https://www.bodobolero.com/rss/atomlink_rewritten.rss";
+        assert_eq!(
+            1,
+            feed1
+                .content
+                .matches(&format!("<link>{}</link>", served_url))
+                .count()
+        );
+        assert_eq!(
+            1,
+            feed1
+                .content
+                .matches(&format!("href=\"{}\"", served_url))
+                .count()
+        );
+        assert_eq!(
+            0,
+            feed1
+                .content
+                .matches("<link>http://arduino-praxis.ch</link>")
+                .count()
+        );
+        // item links stay canonical
+        assert_eq!(
+            10,
+            feed1
+                .content
+                .matches("<link>http://arduino-praxis.ch/")
+                .count()
+        );
+    }
 
-        assert!(feed1.remove_duplicates(&mut existing_items,std::u32::MAX as u64 ).is_ok());
-        assert!(feed1.write().is_ok());
-        assert_eq!(4, feed1.content.matches("<item>").count());
-
-        let _ = fs::remove_file(&feed1.filename);
+    #[test]
+    fn test_rss_with_bom_and_leading_whitespace() {
+        const FEED1: &str = include_str!("../testdata/channelwithbom.rss");
+        setup_test_logger();
+        assert!(FEED1.starts_with('\u{feff}'));
+        let mut feed1 = Feed::new(
+            "https://www.stuttgarter-zeitung.de/schlagzeilen",
+            "testdata/channelwithbom_dedup.rss",
+        );
+        assert!(feed1.update_content(FEED1.to_string(), ContentNormalization::default()));
+        assert!(feed1.content.starts_with("<?xml"));
+        assert!(!feed1.update_content(FEED1.to_string(), ContentNormalization::default()));
 
+        let mut existing_items: ExistingItemsMap = HashMap::new();
+        assert!(feed1
+            .remove_duplicates(
+                &mut existing_items,
+                &mut HashMap::new(),
+                &DedupOptions::default()
+            )
+            .is_ok());
+        assert_eq!(2, feed1.content.matches("<item>").count());
+        assert_eq!(2, existing_items.len());
     }
-
 }
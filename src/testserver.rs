@@ -0,0 +1,163 @@
+// minimal HTTP and HTTPS server for tests, it answers each connection with the next canned
+// response and records the requests it received
+use std::io::{BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub struct TestServer {
+    /// url of the feed served by the server
+    pub url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+// a raw HTTP/1.1 response with Content-Length, the connection is closed after each response
+pub fn response(status: &str, headers: &[(&str, &str)], body: &str) -> String {
+    let mut response = format!("HTTP/1.1 {}\r\n", status);
+    for (name, value) in headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    ));
+    response
+}
+
+impl TestServer {
+    pub fn start(responses: Vec<String>) -> Self {
+        Self::serve(responses, "http", |stream| Box::new(stream))
+    }
+
+    // serve via TLS with the self-signed certificate for localhost in testdata
+    pub fn start_tls(responses: Vec<String>) -> Self {
+        let certs = rustls_pemfile::certs(&mut BufReader::new(
+            include_bytes!("../testdata/localhost.crt").as_slice(),
+        ))
+        .unwrap()
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+        let key = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(
+            include_bytes!("../testdata/localhost.key").as_slice(),
+        ))
+        .unwrap()
+        .remove(0);
+        let config = Arc::new(
+            rustls::ServerConfig::builder()
+                .with_safe_defaults()
+                .with_no_client_auth()
+                .with_single_cert(certs, rustls::PrivateKey(key))
+                .unwrap(),
+        );
+        Self::serve(responses, "https", move |stream| {
+            let connection = rustls::ServerConnection::new(config.clone()).unwrap();
+            Box::new(rustls::StreamOwned::new(connection, stream))
+        })
+    }
+
+    // serve each route (path with query, response) for any number of requests, concurrently and
+    // each after delay, to test parallel fetching; url is the server without a path
+    pub fn start_routes(routes: Vec<(String, String)>, delay: Duration) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let routes = Arc::new(routes);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    return;
+                };
+                let recorded = recorded.clone();
+                let routes = routes.clone();
+                thread::spawn(move || {
+                    let mut stream: Box<dyn ReadWrite> = Box::new(stream);
+                    if let Some(request) = read_request(&mut stream) {
+                        let path = request.split_whitespace().nth(1).unwrap_or_default();
+                        let response = routes
+                            .iter()
+                            .find(|(route, _)| route == path)
+                            .map(|(_, response)| response.clone())
+                            .unwrap_or_else(|| response("404 Not Found", &[], ""));
+                        recorded.lock().unwrap().push(request);
+                        thread::sleep(delay);
+                        let _ = stream.write_all(response.as_bytes());
+                        let _ = stream.flush();
+                    }
+                });
+            }
+        });
+        Self {
+            url: format!("http://127.0.0.1:{}", port),
+            requests,
+        }
+    }
+
+    // the requests received so far, each as request line plus headers (and body if any)
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn serve<F>(responses: Vec<String>, scheme: &str, wrap: F) -> Self
+    where
+        F: Fn(std::net::TcpStream) -> Box<dyn ReadWrite> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let host = if scheme == "https" {
+            "localhost"
+        } else {
+            "127.0.0.1"
+        };
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        thread::spawn(move || {
+            for response in responses {
+                let Ok((stream, _)) = listener.accept() else {
+                    return;
+                };
+                let mut stream = wrap(stream);
+                if let Some(request) = read_request(&mut stream) {
+                    recorded.lock().unwrap().push(request);
+                    let _ = stream.write_all(response.as_bytes());
+                    let _ = stream.flush();
+                }
+            }
+        });
+        Self {
+            url: format!("{}://{}:{}/feed.rss", scheme, host, port),
+            requests,
+        }
+    }
+}
+
+pub trait ReadWrite: Read + Write {}
+impl<T: Read + Write> ReadWrite for T {}
+
+// read the request head and a body with Content-Length, None if the client went away
+fn read_request(stream: &mut Box<dyn ReadWrite>) -> Option<String> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 4096];
+    loop {
+        let read = stream.read(&mut buffer).ok()?;
+        if read == 0 {
+            return None;
+        }
+        request.extend_from_slice(&buffer[..read]);
+        let text = String::from_utf8_lossy(&request);
+        if let Some(head_end) = text.find("\r\n\r\n") {
+            let content_length = text[..head_end]
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+                .unwrap_or(0);
+            if request.len() >= head_end + 4 + content_length {
+                return Some(String::from_utf8_lossy(&request).to_string());
+            }
+        }
+    }
+}
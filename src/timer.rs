@@ -1,16 +1,29 @@
 use chrono::Local;
 use log::info;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
 use std::{thread, time};
 
 // run F1 every interval_in_seconds seconds (plus execution time of F1) and F2 close to midnight exactly once a day
 // if max_iterations is 0, run forever
-pub fn periodic_task<F1, F2>(mut f1: F1, mut f2: F2, interval_in_seconds: u64, max_iterations: u64)
-where
-    F1: FnMut(),
+// the sleep between iterations is interrupted by a message on wakeup, in this case F1 runs
+// immediately and its result is sent to the sender received in the message
+// if cleanup is set, F2 also runs before the next iteration regardless of the date and cleanup
+// is reset; this does not move the run close to midnight
+pub fn periodic_task<F1, F2, R>(
+    mut f1: F1,
+    mut f2: F2,
+    interval_in_seconds: u64,
+    max_iterations: u64,
+    wakeup: Option<&Receiver<Sender<R>>>,
+    cleanup: Option<&AtomicBool>,
+) where
+    F1: FnMut() -> R,
     F2: FnMut(),
 {
     let mut last_run = Local::now().date_naive();
     let mut counter = 0;
+    let mut reply_to: Option<Sender<R>> = None;
     loop {
         let today = Local::now().date_naive();
 
@@ -30,17 +43,32 @@ where
                 today,
                 last_run
             );
+        } else if cleanup.is_some_and(|cleanup| cleanup.swap(false, Ordering::SeqCst)) {
+            info!("{}: Invoking midnight function on request", module_path!());
+            f2();
         }
         info!("{}:: Starting iteration {}", module_path!(), counter);
-        f1();
+        let result = f1();
         info!("{}:: Finished iteration {}", module_path!(), counter);
+        if let Some(reply_to) = reply_to.take() {
+            // the requester may have given up waiting
+            let _ = reply_to.send(result);
+        }
 
         counter += 1;
         if max_iterations > 0 && counter >= max_iterations {
             break;
         }
         // Sleep for interval_in_seconds seconds before next iteration
-        thread::sleep(time::Duration::from_secs(interval_in_seconds));
+        let interval = time::Duration::from_secs(interval_in_seconds);
+        match wakeup.map(|wakeup| wakeup.recv_timeout(interval)) {
+            Some(Ok(sender)) => {
+                info!("{}: Woken up for immediate iteration", module_path!());
+                reply_to = Some(sender);
+            }
+            Some(Err(RecvTimeoutError::Timeout)) => {}
+            Some(Err(RecvTimeoutError::Disconnected)) | None => thread::sleep(interval),
+        }
     }
 }
 
@@ -64,8 +92,59 @@ mod tests {
             },
             1,
             3,
+            None,
+            None,
         );
         assert_eq!(mycounter, 3);
         assert_eq!(midnight_counter, 0);
     }
+
+    #[test]
+    fn test_periodic_task_wakeup() {
+        setup_test_logger();
+        let (wakeup, wakeup_receiver) = std::sync::mpsc::channel();
+        let (reply, reply_receiver) = std::sync::mpsc::channel();
+        wakeup.send(reply).unwrap();
+        let start = std::time::Instant::now();
+        let mut mycounter = 0;
+        periodic_task(
+            || {
+                mycounter += 1;
+                mycounter
+            },
+            || {},
+            3600,
+            2,
+            Some(&wakeup_receiver),
+            None,
+        );
+        // the second iteration ran right away instead of after an hour
+        assert!(start.elapsed() < std::time::Duration::from_secs(60));
+        assert_eq!(reply_receiver.recv().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_periodic_task_cleanup() {
+        setup_test_logger();
+        let cleanup = AtomicBool::new(false);
+        let mycounter = std::cell::Cell::new(0);
+        let mut cleanups = Vec::new();
+        periodic_task(
+            || {
+                mycounter.set(mycounter.get() + 1);
+                if mycounter.get() == 1 {
+                    cleanup.store(true, Ordering::SeqCst);
+                }
+            },
+            || cleanups.push(mycounter.get()),
+            0,
+            3,
+            None,
+            Some(&cleanup),
+        );
+        // the cleanup ran once, before the second iteration (or also close to midnight)
+        assert_eq!(mycounter.get(), 3);
+        assert!(cleanups.contains(&1));
+        assert!(!cleanup.load(Ordering::SeqCst));
+    }
 }
@@ -1,8 +1,16 @@
+use super::ids::{convert_url_to_unique_filename, generate_uuid, FeedNameTemplate};
 use super::opml::*;
+use super::rss::write_temporary_file;
+use chrono::Utc;
+use lazy_static::lazy_static;
 use log::{error, info};
-use std::collections::HashMap;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::Path;
+use std::process::Command;
+use url::Url;
+use xml::escape::escape_str_pcdata;
 
 // no longer needed after preserving uuids
 fn _remove_rss_files(directory: &str) -> Result<(), String> {
@@ -34,29 +42,143 @@ pub fn check_and_init_feeds(
     feedfile: &str,
     urlprefix: &str,
     newopmlfile: &str,
+    format: FeedsFileFormat,
+    options: &TargetOptions,
 ) -> Result<Vec<(String, String)>, String> {
-    if do_we_need_new_json_feeds_file(feedfile, opmlfile).unwrap() {
-        let previous_feeds: HashMap<String, String> = read_feeds(feedfile)
-            .unwrap_or_default()
-            .into_iter()
-            // use the feedfile as key and not the xmlurl
-            .map(|(v, k)| (k, v))
-            .collect();
-        if !previous_feeds.is_empty() {
+    if do_we_need_new_json_feeds_file(feedfile, opmlfile)
+        .map_err(|e| format!("Cannot read OPML file {}: {}", opmlfile, e))?
+    {
+        let previous = read_feeds(feedfile, format).unwrap_or_default();
+        if !previous.is_empty() {
             info!(
                 "Trying to preserve uuids of {} previous feeds",
-                previous_feeds.len()
+                previous.len()
             );
         }
-        let mut opml = OpmlDom::new(opmlfile)?;
-        opml.modify(urlprefix.to_string(), &previous_feeds);
-        opml.write(newopmlfile)?;
-        opml.save_feeds(feedfile)?;
+        let mut target = target_opml(opmlfile, urlprefix, newopmlfile, &previous, options)?;
+        write_opml_and_feeds(&mut target, newopmlfile, feedfile, format, options)?;
         // note that this requires the user to pick up the new OPML file to see the new feeds
         error!("A new OPML file {} has been generated\nTo see the new feeds you need to re-import the new OPML file into your newsreader", 
         newopmlfile);
     }
-    read_feeds(feedfile)
+    read_feeds(feedfile, format)
+}
+
+// the target OPML check_and_init_feeds writes for the feeds previous: the source OPML patched
+// with the url prefix and the filenames of previous or, with incremental, the existing target
+// OPML updated from it
+fn target_opml(
+    opmlfile: &str,
+    urlprefix: &str,
+    newopmlfile: &str,
+    previous: &[(String, String)],
+    options: &TargetOptions,
+) -> Result<OpmlDom, String> {
+    let previous_feeds: HashMap<String, String> = previous
+        .iter()
+        .cloned()
+        // use the feedfile as key and not the xmlurl
+        .map(|(v, k)| (k, v))
+        .collect();
+    let mut opml = OpmlDom::new(opmlfile)?;
+    opml.set_name_template(options.name_template.clone());
+    opml.modify(urlprefix.to_string(), &previous_feeds, &options.categories);
+    // without previous feeds the outlines of the target are unknown, so it is regenerated
+    if options.incremental && !previous.is_empty() && Path::new(newopmlfile).exists() {
+        let mut target = OpmlDom::new(newopmlfile)?;
+        opml.update_target(&mut target, previous, urlprefix);
+        Ok(target)
+    } else {
+        Ok(opml)
+    }
+}
+
+// write the new OPML and feeds file to temporary files and only rename them into place after both
+// were written, so a failure never leaves a new OPML file whose uuids are not recorded in the feeds
+// file; the feeds file is renamed first because feeds it records that the OPML file does not list
+// yet are harmless
+fn write_opml_and_feeds(
+    opml: &mut OpmlDom,
+    newopmlfile: &str,
+    feedfile: &str,
+    format: FeedsFileFormat,
+    options: &TargetOptions,
+) -> Result<(), String> {
+    let opml_tmp = format!("{}.tmp", newopmlfile);
+    let feeds_tmp = format!("{}.tmp", feedfile);
+    let settings = read_feed_settings(feedfile, format).unwrap_or_default();
+    let result = opml
+        .write(&opml_tmp, options)
+        .and_then(|_| opml.save_feeds(&feeds_tmp, &settings, format))
+        .and_then(|_| {
+            fs::rename(&feeds_tmp, feedfile)
+                .map_err(|e| format!("File {} cannot be renamed: {}", feeds_tmp, e))
+        })
+        .and_then(|_| {
+            fs::rename(&opml_tmp, newopmlfile)
+                .map_err(|e| format!("File {} cannot be renamed: {}", opml_tmp, e))
+        });
+    if result.is_err() {
+        let _ = fs::remove_file(&opml_tmp);
+        let _ = fs::remove_file(&feeds_tmp);
+    }
+    result
+}
+
+// like check_and_init_feeds but for a plain text file with one feed url per line instead of
+// an OPML file; empty lines and lines starting with # are ignored
+pub fn check_and_init_feeds_from_urls(
+    urlsfile: &str,
+    feedfile: &str,
+    format: FeedsFileFormat,
+    name_template: Option<&FeedNameTemplate>,
+) -> Result<Vec<(String, String)>, String> {
+    if do_we_need_new_json_feeds_file(feedfile, urlsfile)
+        .map_err(|e| format!("Cannot read feed urls file {}: {}", urlsfile, e))?
+    {
+        let previous_feeds: HashMap<String, String> = read_feeds(feedfile, format)
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        let feeds = feeds_from_urls(urlsfile, &previous_feeds, name_template)?;
+        let settings = read_feed_settings(feedfile, format).unwrap_or_default();
+        write_feeds(feedfile, &feeds, &settings, format)?;
+    }
+    read_feeds(feedfile, format)
+}
+
+// the (url, filename) pairs of the feeds of urlsfile, with the filenames of previous_feeds (url
+// to filename) for the feeds they already list and new filenames for the others
+fn feeds_from_urls(
+    urlsfile: &str,
+    previous_feeds: &HashMap<String, String>,
+    name_template: Option<&FeedNameTemplate>,
+) -> Result<Vec<(String, String)>, String> {
+    info!("Reading feed urls file {}", urlsfile);
+    let content = fs::read_to_string(urlsfile)
+        .map_err(|e| format!("Cannot read feed urls file {}: {}", urlsfile, e))?;
+    let mut feeds: Vec<(String, String)> = Vec::new();
+    for url in content.lines().map(str::trim) {
+        if url.is_empty() || url.starts_with('#') || feeds.iter().any(|(u, _)| u == url) {
+            continue;
+        }
+        let filename = match previous_feeds.get(url) {
+            Some(filename) => filename.clone(),
+            None => {
+                let filename = match name_template {
+                    Some(template) => template.filename(url, "", |filename| {
+                        previous_feeds.values().any(|taken| taken == filename)
+                            || feeds.iter().any(|(_, taken)| taken == filename)
+                    }),
+                    None => convert_url_to_unique_filename(url, &generate_uuid()),
+                };
+                info!("Added new feed {} with url {}", url, filename);
+                filename
+            }
+        };
+        feeds.push((url.to_string(), filename));
+    }
+    Ok(feeds)
 }
 
 // if the json feeds file does not exist we want to create it
@@ -78,8 +200,296 @@ fn do_we_need_new_json_feeds_file(jsonfile: &str, opmlfile: &str) -> std::io::Re
     } else if file2_exists {
         Ok(true)
     } else {
-        panic!("OPML source file {} not found", opmlfile);
+        Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("source file {} not found", opmlfile),
+        ))
+    }
+}
+
+// run the on-update hook command for an updated feed file, passing the filename and the
+// feed url as arguments; a failing hook is logged but does not fail the iteration
+pub fn run_update_hook(command: &str, filename: &str, url: &str) {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", command))
+        .arg("rssdeduper")
+        .arg(filename)
+        .arg(url)
+        .status();
+    match status {
+        Ok(status) if status.success() => {
+            info!("On-update hook for {} finished with {}", filename, status)
+        }
+        Ok(status) => error!("On-update hook for {} failed with {}", filename, status),
+        Err(e) => error!("On-update hook for {} cannot be run: {}", filename, e),
+    }
+}
+
+// the status file next to feedfile that lists the urls of feeds whose server answered 410 Gone,
+// e.g. feeds.gone.json for feeds.json
+pub fn gone_feeds_filename(feedfile: &str) -> String {
+    Path::new(feedfile)
+        .with_extension("gone.json")
+        .to_string_lossy()
+        .to_string()
+}
+
+// the urls in the gone feeds file, none if it does not exist
+pub fn read_gone_feeds(filename: &str) -> Result<HashSet<String>, String> {
+    if !Path::new(filename).exists() {
+        return Ok(HashSet::new());
+    }
+    let content = fs::read_to_string(filename)
+        .map_err(|e| format!("Cannot read gone feeds file {}: {}", filename, e))?;
+    serde_json::from_str(&content)
+        .map_err(|e| format!("Cannot deserialize gone feeds file {}: {}", filename, e))
+}
+
+pub fn write_gone_feeds(filename: &str, urls: &[&str]) -> Result<(), String> {
+    info!("Writing gone feeds file {}", filename);
+    let serialized = serde_json::to_string_pretty(urls).unwrap();
+    fs::write(filename, serialized)
+        .map_err(|e| format!("Cannot write gone feeds file {}: {}", filename, e))
+}
+
+lazy_static! {
+    // the name of a dead letter: its time followed by the url made a filename
+    static ref DEAD_LETTER_REGEX: Regex =
+        Regex::new(r"^\d{8}T\d{6}\.\d{3}Z_[A-Za-z0-9_]*\.rss$").unwrap();
+}
+
+// write body, the body of the feed at url that could not be deduplicated as it was received,
+// to a new file in directory named by the current time and url, so what the origin served can
+// be inspected later; only the max_files newest dead letters of directory are kept, 0 keeps
+// all, other files are left alone; returns the name of the new file
+pub fn write_dead_letter(
+    directory: &str,
+    url: &str,
+    body: &[u8],
+    max_files: usize,
+) -> Result<String, String> {
+    fs::create_dir_all(directory)
+        .map_err(|e| format!("Cannot create dead letter directory {}: {}", directory, e))?;
+    let timestamp = Utc::now().format("%Y%m%dT%H%M%S%.3fZ_").to_string();
+    let filename = Path::new(directory).join(convert_url_to_unique_filename(url, &timestamp));
+    let filename = filename.to_str().unwrap().to_string();
+    fs::write(&filename, body)
+        .map_err(|e| format!("Cannot write dead letter {}: {}", filename, e))?;
+    if max_files > 0 {
+        let mut dead_letters: Vec<_> = fs::read_dir(directory)
+            .map_err(|e| format!("Cannot read dead letter directory {}: {}", directory, e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.is_file()
+                    && path
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| DEAD_LETTER_REGEX.is_match(name))
+            })
+            .collect();
+        // the names start with the time, so they sort oldest first
+        dead_letters.sort();
+        let excess = dead_letters.len().saturating_sub(max_files);
+        for path in &dead_letters[..excess] {
+            fs::remove_file(path)
+                .map_err(|e| format!("Cannot remove dead letter {}: {}", path.display(), e))?;
+        }
+    }
+    Ok(filename)
+}
+
+// remove the .tmp files in directory that were last modified more than max_age ago, left behind
+// by a crash while writing; younger ones may still be written; returns the number removed
+pub fn remove_stale_temporary_files(
+    directory: &str,
+    max_age: std::time::Duration,
+) -> Result<usize, String> {
+    let now = std::time::SystemTime::now();
+    let mut removed = 0;
+    for entry in fs::read_dir(directory)
+        .map_err(|e| format!("Cannot read directory {}: {}", directory, e))?
+    {
+        let path = entry
+            .map_err(|e| format!("Cannot read directory {}: {}", directory, e))?
+            .path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("tmp") {
+            continue;
+        }
+        let stale = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| now.duration_since(modified).is_ok_and(|age| age > max_age));
+        if stale && path.is_file() {
+            fs::remove_file(&path)
+                .map_err(|e| format!("Cannot remove temporary file {}: {}", path.display(), e))?;
+            info!("Removed stale temporary file {}", path.display());
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+// write the served urls under url_prefix of filenames to filename, as a sitemap if it ends
+// in .xml and as plain text with one url per line otherwise; an unchanged file is not
+// rewritten, returns whether it changed
+pub fn write_feeds_list(
+    filename: &str,
+    url_prefix: &str,
+    filenames: &[&str],
+) -> Result<bool, String> {
+    let urls = filenames
+        .iter()
+        .map(|feed_filename| format!("{}{}", url_prefix, feed_filename));
+    let content = if filename.ends_with(".xml") {
+        let mut sitemap = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+        );
+        for url in urls {
+            sitemap.push_str(&format!(
+                "    <url>\n        <loc>{}</loc>\n    </url>\n",
+                escape_str_pcdata(&url)
+            ));
+        }
+        sitemap.push_str("</urlset>\n");
+        sitemap
+    } else {
+        urls.map(|url| url + "\n").collect()
+    };
+    if fs::read_to_string(filename).is_ok_and(|previous| previous == content) {
+        return Ok(false);
+    }
+    let tmp_filename = write_temporary_file(filename, content.as_bytes())?;
+    fs::rename(&tmp_filename, filename)
+        .map_err(|e| format!("File {} cannot be renamed: {}", tmp_filename, e))?;
+    Ok(true)
+}
+
+// check that url_prefix is an absolute url ending in / and that the served url of each filename
+// under it is a valid url; returns warnings for setups that are valid but look wrong, like a
+// url_prefix whose last path segment differs from the name of target_directory
+pub fn validate_urls(
+    url_prefix: &str,
+    target_directory: &str,
+    filenames: &[&str],
+) -> Result<Vec<String>, String> {
+    let prefix = Url::parse(url_prefix)
+        .map_err(|e| format!("URL prefix {} is not a valid url: {}", url_prefix, e))?;
+    if !matches!(prefix.scheme(), "http" | "https")
+        || prefix.cannot_be_a_base()
+        || !url_prefix.ends_with('/')
+    {
+        return Err(format!(
+            "URL prefix {} must be an http(s) url ending in /",
+            url_prefix
+        ));
+    }
+    for filename in filenames {
+        let served_url = format!("{}{}", url_prefix, filename);
+        Url::parse(&served_url)
+            .map_err(|e| format!("Served url {} is not a valid url: {}", served_url, e))?;
+    }
+    let mut warnings = Vec::new();
+    let prefix_name = prefix
+        .path_segments()
+        .and_then(|mut segments| segments.rfind(|segment| !segment.is_empty()))
+        .unwrap_or_default();
+    let directory_name = Path::new(target_directory)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    if prefix_name != directory_name {
+        warnings.push(format!(
+            "URL prefix {} does not end with the name of the target directory {}, check that the directory is served under this url",
+            url_prefix, target_directory
+        ));
     }
+    Ok(warnings)
+}
+
+// table of the feeds in feedfile with their source url, filename and served url under
+// url_prefix, tab separated with a header line
+pub fn list_feeds(
+    feedfile: &str,
+    format: FeedsFileFormat,
+    url_prefix: &str,
+) -> Result<String, String> {
+    let mut table = String::from("SOURCE URL\tFILENAME\tSERVED URL\n");
+    for (url, filename) in read_feeds(feedfile, format)? {
+        table.push_str(&format!(
+            "{}\t{}\t{}{}\n",
+            url, filename, url_prefix, filename
+        ));
+    }
+    Ok(table)
+}
+
+// the changes check_and_init_feeds, or check_and_init_feeds_from_urls with urlsfile, would
+// make for a changed source file, without writing the target OPML or the feeds file: a table of
+// the feeds that are new (with a freshly assigned filename), kept with their previous filename
+// or removed because the source file no longer lists them
+pub fn preview_opml_changes(
+    opmlfile: &str,
+    urlsfile: Option<&str>,
+    feedfile: &str,
+    urlprefix: &str,
+    newopmlfile: &str,
+    format: FeedsFileFormat,
+    options: &TargetOptions,
+) -> Result<String, String> {
+    let previous = read_feeds(feedfile, format).unwrap_or_default();
+    let feeds = match urlsfile {
+        Some(urlsfile) => feeds_from_urls(
+            urlsfile,
+            &previous.iter().cloned().collect(),
+            options.name_template.as_ref(),
+        )?,
+        None => target_opml(opmlfile, urlprefix, newopmlfile, &previous, options)?
+            .feeds()
+            .to_vec(),
+    };
+    let previous_feeds: HashMap<String, String> = previous
+        .into_iter()
+        .map(|(url, filename)| (filename, url))
+        .collect();
+    let mut table = String::from("CHANGE\tSOURCE URL\tFILENAME\n");
+    for (url, filename) in &feeds {
+        let change = if previous_feeds.contains_key(filename) {
+            "kept"
+        } else {
+            "new"
+        };
+        table.push_str(&format!("{}\t{}\t{}\n", change, url, filename));
+    }
+    let listed: HashSet<&String> = feeds.iter().map(|(_, filename)| filename).collect();
+    let mut removed: Vec<(&String, &String)> = previous_feeds
+        .iter()
+        .filter(|(filename, _)| !listed.contains(filename))
+        .collect();
+    removed.sort();
+    for (filename, url) in removed {
+        table.push_str(&format!("removed\t{}\t{}\n", url, filename));
+    }
+    Ok(table)
+}
+
+// replace each ${VAR} in value with the environment variable VAR, so secrets like tokens in
+// feed urls can stay out of the OPML and urls files; values without ${ are returned unchanged
+pub fn expand_env_vars(value: &str) -> Result<String, String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("Unterminated ${{ in {}", value))?;
+        let name = &rest[start + 2..start + end];
+        let variable = std::env::var(name)
+            .map_err(|_| format!("Environment variable {} used in {} is not set", name, value))?;
+        expanded.push_str(&variable);
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
 }
 
 // set up logger for tests with level info
@@ -154,7 +564,6 @@ mod tests {
         Ok(())
     }
     #[test]
-    #[should_panic]
     #[serial]
     fn test_is_file1_older_than_file2_when_file2_does_not_exist() {
         // Erstellen Sie zwei temporäre Dateien für den Test
@@ -169,8 +578,10 @@ mod tests {
 
         assert!(fs::write(&file1, "File1").is_ok());
 
-        // should panic because opml file does not exist
-        let _ = do_we_need_new_json_feeds_file(file1.to_str().unwrap(), file2.to_str().unwrap());
+        // fails because opml file does not exist
+        let result =
+            do_we_need_new_json_feeds_file(file1.to_str().unwrap(), file2.to_str().unwrap());
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::NotFound);
     }
 
     // the following test verifies that 55 existing deduped feeds are preserved as is
@@ -195,6 +606,8 @@ mod tests {
             feedsfile.to_str().unwrap(),
             "https://www.bodobolero.com/rss/",
             "testdata/feedly-target_iteration2.opml",
+            FeedsFileFormat::Json,
+            &TargetOptions::default(),
         );
         assert!(feeds.is_ok());
         assert_eq!(feeds.unwrap().len(), 56);
@@ -202,4 +615,446 @@ mod tests {
         let _ = fs::remove_file(&source_opml);
         let _ = fs::remove_file(&feedsfile);
     }
+
+    // the 55 feeds of iteration 1 are generated without the new Netzwirtschaft feed of the source
+    // OPML of iteration 2, which is then added incrementally
+    #[test]
+    fn test_check_and_init_feeds_incremental() {
+        setup_test_logger();
+        let directory = std::env::temp_dir().join("rssdeduper-incremental");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let path = |name: &str| directory.join(name).to_str().unwrap().to_string();
+        let (feedsfile, source_opml, target_opml) =
+            (path("feeds.json"), path("source.opml"), path("target.opml"));
+        fs::copy("testdata/feeds_iteration1.json", &feedsfile).unwrap();
+        let source = fs::read_to_string("testdata/feedly-source_iteration2.opml").unwrap();
+        let without_new_feed: String = source
+            .lines()
+            .filter(|line| !line.contains("Netzwirtschaft"))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        fs::write(&source_opml, without_new_feed).unwrap();
+        let options = TargetOptions {
+            incremental: true,
+            ..Default::default()
+        };
+        let init = || {
+            check_and_init_feeds(
+                &source_opml,
+                &feedsfile,
+                "https://www.bodobolero.com/rss/",
+                &target_opml,
+                FeedsFileFormat::Json,
+                &options,
+            )
+            .unwrap()
+        };
+        let feeds1 = init();
+        let target1 = fs::read_to_string(&target_opml).unwrap();
+
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        fs::write(&source_opml, source).unwrap();
+        let feeds2 = init();
+        let target2 = fs::read_to_string(&target_opml).unwrap();
+        let _ = fs::remove_dir_all(&directory);
+
+        assert_eq!(feeds1.len(), 55);
+        assert_eq!(feeds2.len(), 56);
+        assert_eq!(feeds2[..55], feeds1[..]);
+        let (url, filename) = &feeds2[55];
+        assert_eq!(
+            url,
+            "http://www.faz.net/aktuell/wirtschaft/netzwirtschaft/?rssview=1"
+        );
+        // only the outline of the new feed is added to the target OPML
+        let new_lines: Vec<&str> = target2
+            .lines()
+            .filter(|line| line.contains(filename.as_str()))
+            .collect();
+        assert_eq!(new_lines.len(), 1);
+        assert!(new_lines[0].contains("DD_Netzwirtschaft - FAZ.NET"));
+        let unchanged: Vec<&str> = target2
+            .lines()
+            .filter(|line| !line.contains(filename.as_str()))
+            .collect();
+        assert_eq!(unchanged, target1.lines().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_preview_opml_changes() {
+        setup_test_logger();
+        let feedsfile = "testdata/feeds_iteration1.json";
+        let before = fs::read_to_string(feedsfile).unwrap();
+        let preview = preview_opml_changes(
+            "testdata/feedly-source_iteration2.opml",
+            None,
+            feedsfile,
+            "https://www.bodobolero.com/rss/",
+            "testdata/missing/target.opml",
+            FeedsFileFormat::Json,
+            &TargetOptions::default(),
+        )
+        .unwrap();
+        let changes: Vec<Vec<&str>> = preview
+            .lines()
+            .skip(1)
+            .map(|line| line.split('\t').collect())
+            .collect();
+        assert_eq!(changes.len(), 56);
+        assert_eq!(
+            changes.iter().filter(|change| change[0] == "kept").count(),
+            55
+        );
+        let new: Vec<_> = changes.iter().filter(|change| change[0] == "new").collect();
+        assert_eq!(new.len(), 1);
+        assert!(new[0][2].ends_with(".rss"));
+        // the feeds file is neither changed nor replaced via a temporary file
+        assert_eq!(fs::read_to_string(feedsfile).unwrap(), before);
+        assert!(!Path::new(&format!("{}.tmp", feedsfile)).exists());
+
+        // new feeds get the filename of the template
+        let options = TargetOptions {
+            name_template: Some(FeedNameTemplate::new("{host}-{title-slug}.rss").unwrap()),
+            ..Default::default()
+        };
+        let preview = preview_opml_changes(
+            "testdata/feedly-source_iteration2.opml",
+            None,
+            feedsfile,
+            "https://www.bodobolero.com/rss/",
+            "testdata/missing/target.opml",
+            FeedsFileFormat::Json,
+            &options,
+        )
+        .unwrap();
+        let new: Vec<_> = preview
+            .lines()
+            .filter(|line| line.starts_with("new\t"))
+            .collect();
+        assert_eq!(
+            new,
+            vec!["new\thttp://www.faz.net/aktuell/wirtschaft/netzwirtschaft/?rssview=1\twww-faz-net-netzwirtschaft-faz-net.rss"]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_preview_feed_urls_changes() {
+        setup_test_logger();
+        let feedsfile = std::env::temp_dir().join("preview_feeds_from_urls.json");
+        let feedsfile = feedsfile.to_str().unwrap();
+        let feeds = serde_json::json!([["https://www.faz.net/rss/aktuell/", "faz.rss"]]);
+        fs::write(feedsfile, feeds.to_string()).unwrap();
+        let urlsfile = std::env::temp_dir().join("preview_feed_urls.txt");
+        fs::write(&urlsfile, "https://www.tagesschau.de/xml/rss2/\n").unwrap();
+        let options = TargetOptions {
+            name_template: Some(FeedNameTemplate::new("{host}.rss").unwrap()),
+            ..Default::default()
+        };
+        let preview = preview_opml_changes(
+            "testdata/missing/source.opml",
+            urlsfile.to_str(),
+            feedsfile,
+            "https://www.bodobolero.com/rss/",
+            "testdata/missing/target.opml",
+            FeedsFileFormat::Json,
+            &options,
+        )
+        .unwrap();
+        let _ = fs::remove_file(&urlsfile);
+        let _ = fs::remove_file(feedsfile);
+        assert_eq!(
+            preview.lines().skip(1).collect::<Vec<_>>(),
+            vec![
+                "new\thttps://www.tagesschau.de/xml/rss2/\twww-tagesschau-de.rss",
+                "removed\thttps://www.faz.net/rss/aktuell/\tfaz.rss",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_and_init_feeds_save_feeds_fails() {
+        setup_test_logger();
+        let mut target_opml = std::env::temp_dir();
+        target_opml.push("feedly-target_save_feeds_fails.opml");
+        let target_opml = target_opml.to_str().unwrap();
+        fs::write(target_opml, "previous").unwrap();
+        // the directory of the feeds file does not exist, so saving the feeds fails
+        let feedsfile = "testdata/missing/feeds.json";
+        let feeds = check_and_init_feeds(
+            "testdata/feedly-source.opml",
+            feedsfile,
+            "https://www.bodobolero.com/rss/",
+            target_opml,
+            FeedsFileFormat::Json,
+            &TargetOptions::default(),
+        );
+        assert!(feeds.is_err());
+        assert_eq!(fs::read_to_string(target_opml).unwrap(), "previous");
+        assert!(!Path::new(&format!("{}.tmp", target_opml)).exists());
+        assert!(!Path::new(feedsfile).exists());
+        let _ = fs::remove_file(target_opml);
+    }
+
+    #[test]
+    fn test_remove_stale_temporary_files() {
+        let directory = std::env::temp_dir().join("rssdeduper-stale-tmp");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let stale = directory.join("feed.rss.tmp");
+        let recent = directory.join("other.rss.tmp");
+        let feed = directory.join("feed.rss");
+        for path in [&stale, &recent, &feed] {
+            fs::write(path, "<rss/>").unwrap();
+        }
+        let two_hours_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(7200);
+        for path in [&stale, &feed] {
+            fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(two_hours_ago)
+                .unwrap();
+        }
+        let removed = remove_stale_temporary_files(
+            directory.to_str().unwrap(),
+            std::time::Duration::from_secs(3600),
+        );
+        assert_eq!(removed, Ok(1));
+        assert!(!stale.exists());
+        assert!(recent.exists());
+        assert!(feed.exists());
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_write_feeds_list() {
+        let filenames = ["faz.rss", "spiegel.rss", "a&b.rss"];
+        let prefix = "https://www.bodobolero.com/rss/";
+
+        let filename = "testdata/feeds-list.txt";
+        assert_eq!(write_feeds_list(filename, prefix, &filenames), Ok(true));
+        assert_eq!(write_feeds_list(filename, prefix, &filenames), Ok(false));
+        let content = fs::read_to_string(filename).unwrap();
+        let _ = fs::remove_file(filename);
+        assert_eq!(
+            content.lines().collect::<Vec<_>>(),
+            vec![
+                "https://www.bodobolero.com/rss/faz.rss",
+                "https://www.bodobolero.com/rss/spiegel.rss",
+                "https://www.bodobolero.com/rss/a&b.rss",
+            ]
+        );
+
+        let filename = "testdata/feeds-sitemap.xml";
+        assert_eq!(write_feeds_list(filename, prefix, &filenames), Ok(true));
+        let content = fs::read_to_string(filename).unwrap();
+        let _ = fs::remove_file(filename);
+        let sitemap = xmltree::Element::parse(content.as_bytes()).unwrap();
+        let locs: Vec<String> = sitemap
+            .children
+            .iter()
+            .filter_map(xmltree::XMLNode::as_element)
+            .filter_map(|url| url.get_child("loc").and_then(|loc| loc.get_text()))
+            .map(|loc| loc.to_string())
+            .collect();
+        assert_eq!(
+            locs,
+            vec![
+                "https://www.bodobolero.com/rss/faz.rss",
+                "https://www.bodobolero.com/rss/spiegel.rss",
+                "https://www.bodobolero.com/rss/a&b.rss",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_urls() {
+        let filenames = ["faz.rss", "spiegel.rss"];
+        assert_eq!(
+            validate_urls(
+                "https://www.bodobolero.com/rss/",
+                "/var/www/html/rss/",
+                &filenames
+            ),
+            Ok(vec![])
+        );
+        let error = validate_urls(
+            "https://www.bodobolero.com/rss",
+            "/var/www/html/rss/",
+            &filenames,
+        )
+        .unwrap_err();
+        assert!(error.contains("ending in /"));
+        assert!(
+            validate_urls("www.bodobolero.com/rss/", "/var/www/html/rss/", &filenames).is_err()
+        );
+        for prefix in ["ftp://www.bodobolero.com/rss/", "file:///var/www/html/rss/"] {
+            let error = validate_urls(prefix, "/var/www/html/rss/", &filenames).unwrap_err();
+            assert!(error.contains("http(s)"));
+        }
+        let warnings = validate_urls(
+            "https://www.bodobolero.com/feeds/",
+            "/var/www/html/rss",
+            &filenames,
+        )
+        .unwrap();
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_list_feeds() {
+        let table = list_feeds(
+            "testdata/listfeeds.json",
+            FeedsFileFormat::Json,
+            "https://www.bodobolero.com/rss/",
+        )
+        .unwrap();
+        let rows: Vec<Vec<&str>> = table
+            .lines()
+            .map(|line| line.split('\t').collect())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                vec!["SOURCE URL", "FILENAME", "SERVED URL"],
+                vec![
+                    "https://www.faz.net/rss/aktuell/",
+                    "51f9e919-e8ae-4ebd-86c0-3c40a071c198https_www_faz_net_rss_aktuell_.rss",
+                    "https://www.bodobolero.com/rss/51f9e919-e8ae-4ebd-86c0-3c40a071c198https_www_faz_net_rss_aktuell_.rss",
+                ],
+                vec![
+                    "https://www.stuttgarter-zeitung.de/news.rss",
+                    "327df577-55dd-45f5-9d9d-a9cb87f3308chttps_www_stuttgarter_zeitung_de_news_rss.rss",
+                    "https://www.bodobolero.com/rss/327df577-55dd-45f5-9d9d-a9cb87f3308chttps_www_stuttgarter_zeitung_de_news_rss.rss",
+                ],
+            ]
+        );
+        assert!(list_feeds("testdata/missing.json", FeedsFileFormat::Json, "").is_err());
+    }
+
+    #[test]
+    fn test_expand_env_vars() {
+        std::env::set_var("RSSDEDUPER_TEST_TOKEN", "s3cret");
+        assert_eq!(
+            expand_env_vars("https://www.faz.net/rss?token=${RSSDEDUPER_TEST_TOKEN}&x=1"),
+            Ok("https://www.faz.net/rss?token=s3cret&x=1".to_string())
+        );
+        assert_eq!(
+            expand_env_vars("https://www.faz.net/rss?x=$1"),
+            Ok("https://www.faz.net/rss?x=$1".to_string())
+        );
+        let error = expand_env_vars("${RSSDEDUPER_TEST_UNSET}").unwrap_err();
+        assert!(error.contains("RSSDEDUPER_TEST_UNSET"));
+        assert!(error.contains("not set"));
+        assert!(expand_env_vars("${RSSDEDUPER_TEST_TOKEN").is_err());
+    }
+
+    #[test]
+    fn test_run_update_hook() {
+        setup_test_logger();
+        let mut marker = std::env::temp_dir();
+        marker.push("rssdeduper_hook_marker.txt");
+        let _ = fs::remove_file(&marker);
+        let command = format!("echo >{}", marker.to_str().unwrap());
+
+        run_update_hook(&command, "feed.rss", "https://www.faz.net/rss");
+        assert_eq!(
+            fs::read_to_string(&marker).unwrap(),
+            "feed.rss https://www.faz.net/rss\n"
+        );
+        let _ = fs::remove_file(&marker);
+
+        // a failing hook must not panic
+        run_update_hook("false", "feed.rss", "https://www.faz.net/rss");
+    }
+
+    #[test]
+    #[serial]
+    fn test_check_and_init_feeds_from_urls() {
+        setup_test_logger();
+        let mut feedsfile = std::env::temp_dir();
+        feedsfile.push("feeds_from_urls.json");
+        let _ = fs::remove_file(&feedsfile);
+        let mut urlsfile = std::env::temp_dir();
+        urlsfile.push("feed_urls.txt");
+        fs::write(
+            &urlsfile,
+            "# my feeds\nhttps://www.faz.net/rss/aktuell/\n\n  https://www.tagesschau.de/xml/rss2/  \nhttps://www.faz.net/rss/aktuell/\n",
+        )
+        .unwrap();
+
+        let feeds = check_and_init_feeds_from_urls(
+            urlsfile.to_str().unwrap(),
+            feedsfile.to_str().unwrap(),
+            FeedsFileFormat::Json,
+            None,
+        )
+        .unwrap();
+        assert_eq!(feeds.len(), 2);
+        assert_eq!(feeds[0].0, "https://www.faz.net/rss/aktuell/");
+        assert!(feeds[0].1.ends_with("https_www_faz_net_rss_aktuell_.rss"));
+        assert_eq!(
+            feeds[0].1.len(),
+            36 + "https_www_faz_net_rss_aktuell_.rss".len()
+        );
+        assert_eq!(feeds[1].0, "https://www.tagesschau.de/xml/rss2/");
+        assert!(feeds[1]
+            .1
+            .ends_with("https_www_tagesschau_de_xml_rss2_.rss"));
+
+        // adding a feed preserves the filenames of the existing feeds
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        fs::write(
+            &urlsfile,
+            "https://www.faz.net/rss/aktuell/\nhttps://www.tagesschau.de/xml/rss2/\nhttps://www.nzz.ch/recent.rss\n",
+        )
+        .unwrap();
+        let new_feeds = check_and_init_feeds_from_urls(
+            urlsfile.to_str().unwrap(),
+            feedsfile.to_str().unwrap(),
+            FeedsFileFormat::Json,
+            None,
+        )
+        .unwrap();
+        assert_eq!(new_feeds.len(), 3);
+        assert_eq!(new_feeds[..2], feeds[..]);
+
+        let _ = fs::remove_file(&urlsfile);
+        let _ = fs::remove_file(&feedsfile);
+    }
+
+    #[test]
+    #[serial]
+    fn test_check_and_init_feeds_from_urls_with_name_template() {
+        setup_test_logger();
+        let feedsfile = std::env::temp_dir().join("feeds_from_urls_template.json");
+        let _ = fs::remove_file(&feedsfile);
+        let urlsfile = std::env::temp_dir().join("feed_urls_template.txt");
+        fs::write(
+            &urlsfile,
+            "https://www.faz.net/rss/aktuell/
+https://www.faz.net/rss/aktuell/politik/
+https://www.tagesschau.de/xml/rss2/
+",
+        )
+        .unwrap();
+        let template = FeedNameTemplate::new("{host}.rss").unwrap();
+        let feeds = check_and_init_feeds_from_urls(
+            urlsfile.to_str().unwrap(),
+            feedsfile.to_str().unwrap(),
+            FeedsFileFormat::Json,
+            Some(&template),
+        )
+        .unwrap();
+        let _ = fs::remove_file(&urlsfile);
+        let _ = fs::remove_file(&feedsfile);
+        assert_eq!(feeds[0].1, "www-faz-net.rss");
+        // the second feed of the host gets a uuid to tell it apart
+        assert!(feeds[1].1.starts_with("www-faz-net-"));
+        assert_eq!(feeds[1].1.len(), "www-faz-net-.rss".len() + 36);
+        assert_eq!(feeds[2].1, "www-tagesschau-de.rss");
+    }
 }
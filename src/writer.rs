@@ -0,0 +1,166 @@
+use super::rss::Feed;
+
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+
+// url, filename and result of writing a queued feed
+pub type WriteResult = (String, String, Result<bool, String>);
+
+// feeds waiting to be written by background threads, so the deduplication of the next feed
+// does not wait for slow storage; each writer replaces the feed files atomically just like a
+// feed written in the deduplication loop; a write that panics fails only its own feed
+pub struct WriteQueue {
+    sender: Option<SyncSender<(usize, Feed)>>,
+    writers: Vec<JoinHandle<Vec<(usize, WriteResult)>>>,
+    /// url and filename of each pushed feed
+    queued: Vec<(String, String)>,
+}
+
+impl WriteQueue {
+    // start threads writers that write each pushed feed with write; at most threads feeds wait
+    // to be written, a further push blocks until a writer takes one
+    pub fn start<F>(threads: usize, write: F) -> Self
+    where
+        F: Fn(&Feed) -> Result<bool, String> + Send + Sync + 'static,
+    {
+        let threads = threads.max(1);
+        let (sender, receiver) = mpsc::sync_channel(threads);
+        let receiver: Arc<Mutex<Receiver<(usize, Feed)>>> = Arc::new(Mutex::new(receiver));
+        let write = Arc::new(write);
+        let writers = (0..threads)
+            .map(|_| {
+                let receiver = receiver.clone();
+                let write = write.clone();
+                thread::spawn(move || {
+                    let mut written = Vec::new();
+                    loop {
+                        let next = receiver
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .recv();
+                        let Ok((index, feed)) = next else {
+                            return written;
+                        };
+                        let url = feed.url().to_string();
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| write(&feed)))
+                            .unwrap_or_else(|_| Err(format!("Writing feed {} panicked", url)));
+                        written.push((index, (url, feed.filename().to_string(), result)));
+                    }
+                })
+            })
+            .collect();
+        Self {
+            sender: Some(sender),
+            writers,
+            queued: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, feed: Feed) {
+        let sender = self.sender.as_ref().unwrap();
+        let index = self.queued.len();
+        self.queued
+            .push((feed.url().to_string(), feed.filename().to_string()));
+        // the writers only stop when the queue is finished, a feed that cannot be sent anyway
+        // is reported as failed by finish
+        let _ = sender.send((index, feed));
+    }
+
+    // wait until all pushed feeds are written and return their results in the order they were
+    // pushed; a feed whose writer died without a result is reported as failed
+    pub fn finish(mut self) -> Vec<WriteResult> {
+        drop(self.sender.take());
+        let mut written: HashMap<usize, WriteResult> = self
+            .writers
+            .drain(..)
+            .flat_map(|writer| writer.join().unwrap_or_default())
+            .collect();
+        self.queued
+            .into_iter()
+            .enumerate()
+            .map(|(index, (url, filename))| {
+                written.remove(&index).unwrap_or_else(|| {
+                    let error = format!("Writer of feed {} stopped without writing it", url);
+                    (url, filename, Err(error))
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::rss::WriteOptions;
+    use super::super::utilities::setup_test_logger;
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use std::time::Duration;
+
+    #[test]
+    fn test_write_queue_slow_sink() {
+        setup_test_logger();
+        let directory = std::env::temp_dir().join("rssdeduper-write-queue");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let options = WriteOptions::default();
+        let mut queue = WriteQueue::start(2, move |feed: &Feed| {
+            thread::sleep(Duration::from_millis(50));
+            feed.write(&options)
+        });
+        let mut expected = Vec::new();
+        for index in 0..6 {
+            let url = format!("https://www.example.com/{}.rss", index);
+            let filename = directory.join(format!("{}.rss", index));
+            let failures = [(url.as_str(), "Feed cannot be read")];
+            let feed =
+                Feed::error_feed(&url, filename.to_str().unwrap(), &failures, false).unwrap();
+            expected.push((url, filename, feed.content().to_string()));
+            queue.push(feed);
+        }
+        let results = queue.finish();
+        assert_eq!(results.len(), 6);
+        for ((url, filename, result), (expected_url, expected_filename, content)) in
+            results.iter().zip(&expected)
+        {
+            assert_eq!(url, expected_url);
+            assert_eq!(filename, expected_filename.to_str().unwrap());
+            assert_eq!(result, &Ok(true));
+            assert_eq!(&fs::read_to_string(expected_filename).unwrap(), content);
+            assert!(!Path::new(&format!("{}.tmp", filename)).exists());
+        }
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_write_queue_panicking_writer() {
+        setup_test_logger();
+        let mut queue = WriteQueue::start(1, |feed: &Feed| {
+            if feed.url().ends_with("1.rss") {
+                panic!("disk on fire");
+            }
+            Ok(true)
+        });
+        for index in 0..3 {
+            let url = format!("https://www.example.com/{}.rss", index);
+            queue.push(Feed::new(&url, &format!("{}.rss", index)));
+        }
+        let results: Vec<_> = queue
+            .finish()
+            .into_iter()
+            .map(|(url, _, result)| (url, result.is_ok()))
+            .collect();
+        // the feeds after the panicking one are written by the same writer
+        assert_eq!(
+            results,
+            vec![
+                ("https://www.example.com/0.rss".to_string(), true),
+                ("https://www.example.com/1.rss".to_string(), false),
+                ("https://www.example.com/2.rss".to_string(), true),
+            ]
+        );
+    }
+}